@@ -0,0 +1,167 @@
+//! Minimal relay for versus matches. Clients connect over WebSocket, join a
+//! room by name, and every message after the join is forwarded verbatim to
+//! the other seat in the room. The relay never simulates anything; the
+//! lockstep session on each client does.
+//!
+//! Usage: `cargo run -p tetris-relay -- [address]` (default 127.0.0.1:9001).
+
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use tetris_wasm::net::{Message, PROTOCOL_VERSION};
+use tungstenite::{accept, Message as WsMessage, WebSocket};
+
+const DEFAULT_ADDRESS: &str = "127.0.0.1:9001";
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+// Each seat holds the player's name and the channel to its socket thread.
+type Rooms = Arc<Mutex<HashMap<String, [Option<(String, Sender<String>)>; 2]>>>;
+
+fn main() {
+    let address = std::env::args().nth(1).unwrap_or_else(|| DEFAULT_ADDRESS.to_string());
+    let listener = match TcpListener::bind(&address) {
+        Ok(listener) => listener,
+        Err(err) => {
+            eprintln!("Failed to bind {}: {}", address, err);
+            std::process::exit(1);
+        }
+    };
+    println!("Relay listening on ws://{}", address);
+
+    let rooms: Rooms = Arc::new(Mutex::new(HashMap::new()));
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let rooms = Arc::clone(&rooms);
+                thread::spawn(move || handle_client(stream, rooms));
+            }
+            Err(err) => eprintln!("Connection failed: {}", err),
+        }
+    }
+}
+
+fn handle_client(stream: TcpStream, rooms: Rooms) {
+    let mut socket = match accept(stream) {
+        Ok(socket) => socket,
+        Err(err) => {
+            eprintln!("Handshake failed: {}", err);
+            return;
+        }
+    };
+
+    let (room, name) = match read_message(&mut socket) {
+        Some(Message::Join { room, name, version }) if version == PROTOCOL_VERSION => (room, name),
+        Some(Message::Join { .. }) => {
+            reject(&mut socket, "protocol version mismatch");
+            return;
+        }
+        _ => {
+            reject(&mut socket, "expected join");
+            return;
+        }
+    };
+
+    let (sender, inbox) = mpsc::channel();
+    let (seat, peer_name) = match take_seat(&rooms, &room, &name, sender) {
+        Some(seat) => seat,
+        None => {
+            reject(&mut socket, "room is full");
+            return;
+        }
+    };
+    println!("{} joined room '{}' as player {}", name, room, seat);
+    send(&mut socket, &Message::Joined { player: seat as u8 });
+    // Whoever arrives second introduces both players to each other.
+    if let Some(peer_name) = peer_name {
+        send(&mut socket, &Message::PeerJoined { name: peer_name });
+        forward(&rooms, &room, seat, Message::PeerJoined { name }.encode());
+    }
+
+    relay(&mut socket, &inbox, &rooms, &room, seat);
+
+    forward(&rooms, &room, seat, Message::Leave.encode());
+    leave_seat(&rooms, &room, seat);
+    println!("Player {} left room '{}'", seat, room);
+}
+
+// Pumps messages both ways until either side hangs up.
+fn relay(socket: &mut WebSocket<TcpStream>, inbox: &Receiver<String>, rooms: &Rooms, room: &str, seat: usize) {
+    if socket.get_ref().set_read_timeout(Some(POLL_INTERVAL)).is_err() {
+        return;
+    }
+    loop {
+        while let Ok(text) = inbox.try_recv() {
+            if socket.send(WsMessage::Text(text)).is_err() {
+                return;
+            }
+        }
+        match socket.read() {
+            Ok(WsMessage::Text(text)) => match Message::decode(&text) {
+                Some(Message::Leave) => return,
+                Some(_) => forward(rooms, room, seat, text),
+                None => eprintln!("Dropping malformed message from player {}", seat),
+            },
+            Ok(WsMessage::Close(_)) => return,
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(err))
+                if err.kind() == ErrorKind::WouldBlock || err.kind() == ErrorKind::TimedOut => {}
+            Err(_) => return,
+        }
+    }
+}
+
+fn read_message(socket: &mut WebSocket<TcpStream>) -> Option<Message> {
+    loop {
+        match socket.read() {
+            Ok(WsMessage::Text(text)) => return Message::decode(&text),
+            Ok(WsMessage::Close(_)) | Err(_) => return None,
+            Ok(_) => {}
+        }
+    }
+}
+
+fn send(socket: &mut WebSocket<TcpStream>, message: &Message) -> bool {
+    socket.send(WsMessage::Text(message.encode())).is_ok()
+}
+
+fn reject(socket: &mut WebSocket<TcpStream>, reason: &str) {
+    send(
+        socket,
+        &Message::Rejected {
+            reason: reason.to_string(),
+        },
+    );
+    let _ = socket.close(None);
+}
+
+// Returns the seat taken and the name of the player already in the room.
+fn take_seat(rooms: &Rooms, room: &str, name: &str, sender: Sender<String>) -> Option<(usize, Option<String>)> {
+    let mut rooms = rooms.lock().unwrap();
+    let seats = rooms.entry(room.to_string()).or_default();
+    let seat = seats.iter().position(|seat| seat.is_none())?;
+    seats[seat] = Some((name.to_string(), sender));
+    let peer_name = seats[1 - seat].as_ref().map(|(name, _)| name.clone());
+    Some((seat, peer_name))
+}
+
+fn leave_seat(rooms: &Rooms, room: &str, seat: usize) {
+    let mut rooms = rooms.lock().unwrap();
+    if let Some(seats) = rooms.get_mut(room) {
+        seats[seat] = None;
+        if seats.iter().all(|seat| seat.is_none()) {
+            rooms.remove(room);
+        }
+    }
+}
+
+fn forward(rooms: &Rooms, room: &str, from: usize, text: String) {
+    let rooms = rooms.lock().unwrap();
+    if let Some(Some((_, peer))) = rooms.get(room).map(|seats| &seats[1 - from]) {
+        let _ = peer.send(text);
+    }
+}
@@ -0,0 +1,31 @@
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+/// A single player input. Everything that can change the game state from the
+/// outside goes through one of these, which is what makes input logs
+/// replayable and lets two machines simulate the same match.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    SoftDrop,
+    HardDrop,
+    RotateLeft,
+    RotateRight,
+    Rotate180,
+    Hold,
+}
+
+impl Action {
+    pub const ALL: [Action; 8] = [
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::SoftDrop,
+        Action::HardDrop,
+        Action::RotateLeft,
+        Action::RotateRight,
+        Action::Rotate180,
+        Action::Hold,
+    ];
+}
@@ -0,0 +1,288 @@
+//! Line-clear animations. Cleared rows play an effect chosen by the kind of
+//! clear, then collapse; the rows above drop into place with a short fall.
+//! The clear's length decides when the rows collapse, so it is part of the
+//! simulated state, while the fall is only drawn.
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+use crate::Tetris;
+
+/// How cleared rows disappear.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClearEffect {
+    Fade,
+    /// Blinks white, then fades.
+    Flash,
+    /// Blocks shrink away from the middle of the row outwards.
+    Collapse,
+    /// Blocks fade one by one in a scattered order.
+    Dissolve,
+    /// Blocks break into pieces that fly apart and fall.
+    Shatter,
+}
+
+/// Clears that can have their own effect.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum ClearKind {
+    /// Singles, doubles and triples.
+    Lines,
+    Tetris,
+    TSpin,
+}
+
+impl ClearKind {
+    pub const ALL: [ClearKind; 3] = [ClearKind::Lines, ClearKind::Tetris, ClearKind::TSpin];
+}
+
+/// How a block in a clearing row is drawn at a point in the effect.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct ClearLook {
+    pub(crate) opacity: f64,
+    pub(crate) scale: f64, // Of the block, around its center
+    pub(crate) highlight: bool,
+    pub(crate) flash: f64, // Opacity of white drawn over the block
+    pub(crate) shatter: bool, // Drawn as `fragments` instead
+}
+
+/// A quarter of a shattered block.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Fragment {
+    pub(crate) x: f64, // Top left, in cells
+    pub(crate) y: f64,
+    pub(crate) corner: (f64, f64), // Which quarter of the block: 0.0 or 0.5 on each axis
+    pub(crate) opacity: f64,
+}
+
+/// Rows still dropping after a clear.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Fall {
+    drops: Vec<u8>, // Rows each board row has yet to fall, at the start
+    elapsed: f64,
+    duration: f64,
+}
+
+// A fixed scatter in [0, 1) per cell, so effects look the same every frame.
+fn scatter(x: usize, y: usize, salt: u32) -> f64 {
+    let mut h = (x as u32).wrapping_mul(0x9E37_79B1) ^ (y as u32).wrapping_mul(0x85EB_CA77) ^ salt.wrapping_mul(0xC2B2_AE3D);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x2C1B_3C6D);
+    h ^= h >> 12;
+    (h % 1000) as f64 / 1000.0
+}
+
+/// Look of the block at column `x` of clearing row `y`, `t` of the way
+/// through the effect.
+pub(crate) fn clear_look(effect: ClearEffect, t: f64, x: usize, y: usize, width: usize) -> ClearLook {
+    let t = t.clamp(0.0, 1.0);
+    let mut look = ClearLook {
+        opacity: 1.0,
+        scale: 1.0,
+        highlight: true,
+        flash: 0.0,
+        shatter: false,
+    };
+    match effect {
+        ClearEffect::Fade => {
+            look.opacity = 1.0 - t;
+            look.highlight = t < 0.5;
+        }
+        ClearEffect::Flash => {
+            if t < 0.5 {
+                look.flash = if ((t * 6.0) as u32).is_multiple_of(2) { 0.9 } else { 0.0 }; // Three blinks
+            } else {
+                look.opacity = 2.0 - 2.0 * t;
+            }
+        }
+        ClearEffect::Collapse => {
+            let half = width as f64 / 2.0;
+            let distance = (x as f64 + 0.5 - half).abs() / half; // 0 in the middle, 1 at the walls
+            let start = distance * 0.6;
+            look.scale = 1.0 - ((t - start) / 0.4).clamp(0.0, 1.0);
+        }
+        ClearEffect::Dissolve => {
+            let start = scatter(x, y, 1) * 0.7;
+            let local = ((t - start) / 0.3).clamp(0.0, 1.0);
+            look.opacity = 1.0 - local;
+            look.highlight = local < 0.5;
+        }
+        ClearEffect::Shatter => look.shatter = true,
+    }
+    look
+}
+
+/// The four pieces of a shattered block at (`x`, `y`), `t` of the way
+/// through the effect. They burst outwards, then gravity takes over.
+pub(crate) fn fragments(x: usize, y: usize, t: f64) -> [Fragment; 4] {
+    let t = t.clamp(0.0, 1.0);
+    let corners = [(0.0, 0.0), (0.5, 0.0), (0.0, 0.5), (0.5, 0.5)];
+    corners.map(|corner| {
+        let salt = (corner.0 * 4.0 + corner.1 * 2.0) as u32;
+        let vx = (corner.0 - 0.25) * 4.0 + (scatter(x, y, salt + 2) - 0.5) * 2.0;
+        let vy = -1.0 - scatter(x, y, salt + 7) * 2.0;
+        Fragment {
+            x: x as f64 + corner.0 + vx * t,
+            y: y as f64 + corner.1 + vy * t + 4.0 * t * t,
+            corner,
+            opacity: 1.0 - t,
+        }
+    })
+}
+
+impl Tetris {
+    /// Starts clearing `lines`, or clears them at once if animations are off.
+    pub(crate) fn start_clear(&mut self, lines: Vec<usize>, kind: ClearKind) {
+        self.emit_clear(&lines);
+        self.clearing_lines = lines;
+        self.clearing_animation_progress = 0.0;
+        self.clear_effect = self.settings.clear_effect(kind);
+        self.clear_duration = self.settings.clear_duration;
+        if self.clear_duration <= 0.0 {
+            self.finish_clear();
+        }
+    }
+
+    // Removes the cleared rows and starts the rows above falling.
+    fn finish_clear(&mut self) {
+        let mut new_board: Vec<Vec<u8>> = Vec::new();
+        let mut drops = Vec::new();
+        for y in 0..self.height {
+            if !self.clearing_lines.contains(&y) {
+                new_board.push(self.board[y].clone());
+                drops.push(self.clearing_lines.iter().filter(|&&line| line > y).count() as u8);
+            }
+        }
+        for _ in 0..self.clearing_lines.len() {
+            new_board.insert(0, vec![0; self.width]);
+            drops.insert(0, 0);
+        }
+        self.board = new_board;
+        self.forget_hint();
+        self.clearing_lines.clear();
+        self.clearing_animation_progress = 0.0;
+        self.fall = if self.settings.fall_duration > 0.0 {
+            Some(Fall {
+                drops,
+                elapsed: 0.0,
+                duration: self.settings.fall_duration,
+            })
+        } else {
+            None
+        };
+    }
+
+    /// How far through the current clear effect, from 0 to 1.
+    pub(crate) fn clear_progress(&self) -> f64 {
+        if self.clear_duration > 0.0 {
+            (self.clearing_animation_progress / self.clear_duration).min(1.0)
+        } else {
+            1.0
+        }
+    }
+
+    pub(crate) fn clear_look(&self, x: usize, y: usize) -> ClearLook {
+        clear_look(self.clear_effect, self.clear_progress(), x, y, self.width)
+    }
+
+    /// Rows above its place that board row `y` is drawn at while falling.
+    pub(crate) fn fall_offset(&self, y: usize) -> f64 {
+        match self.fall {
+            Some(ref fall) => {
+                let t = (fall.elapsed / fall.duration).min(1.0);
+                let drop = fall.drops.get(y).copied().unwrap_or(0) as f64;
+                drop * (1.0 - t * t) // Accelerating, like gravity
+            }
+            None => 0.0,
+        }
+    }
+
+    /// Whether a clear or fall is playing, so the whole board is redrawn.
+    pub(crate) fn is_animating(&self) -> bool {
+        !self.clearing_lines.is_empty() || self.fall.is_some()
+    }
+}
+
+#[wasm_bindgen]
+impl Tetris {
+    pub fn update_clearing_animation(&mut self, delta_time: f64) {
+        if let Some(ref mut fall) = self.fall {
+            fall.elapsed += delta_time;
+            if fall.elapsed >= fall.duration {
+                self.fall = None;
+            }
+        }
+        if self.clearing_lines.is_empty() {
+            return;
+        }
+        self.clearing_animation_progress += delta_time;
+        if self.clearing_animation_progress >= self.clear_duration {
+            self.finish_clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Settings;
+
+    #[test]
+    fn test_effects() {
+        let fade = clear_look(ClearEffect::Fade, 0.75, 0, 0, 10);
+        assert_eq!((fade.opacity, fade.highlight), (0.25, false));
+        // The middle of the row goes first.
+        let middle = clear_look(ClearEffect::Collapse, 0.3, 5, 0, 10);
+        let edge = clear_look(ClearEffect::Collapse, 0.3, 0, 0, 10);
+        assert!(middle.scale < 1.0 && edge.scale == 1.0);
+        assert!(clear_look(ClearEffect::Flash, 0.1, 0, 0, 10).flash > 0.0);
+        for x in 0..10 {
+            assert_eq!(clear_look(ClearEffect::Dissolve, 1.0, x, 3, 10).opacity, 0.0);
+        }
+        let pieces = fragments(4, 10, 0.5);
+        assert!(pieces[0].x < pieces[1].x && pieces.iter().all(|piece| piece.opacity == 0.5));
+    }
+
+    #[test]
+    fn test_effect_by_kind_and_fall() {
+        let mut game = Tetris::with_seed(0);
+        let mut settings = Settings::default();
+        settings.set_clear_effect(ClearKind::Tetris, ClearEffect::Shatter);
+        settings.set_clear_duration(0.5);
+        game.set_settings(settings);
+        for y in 16..20 {
+            game.board[y] = vec![1; 10];
+        }
+        game.board[15][0] = 2;
+        game.clear_lines();
+        assert_eq!(game.clear_effect, ClearEffect::Shatter);
+        game.update_clearing_animation(0.3);
+        assert_eq!(game.clearing_lines.len(), 4);
+        assert!(game.clear_look(0, 19).shatter);
+
+        game.update_clearing_animation(0.2);
+        assert!(game.clearing_lines.is_empty());
+        assert_eq!(game.board[19][0], 2);
+        assert_eq!(game.fall_offset(19), 4.0); // Drawn where it was
+        assert!(game.is_animating());
+        game.update_clearing_animation(1.0);
+        assert_eq!(game.fall_offset(19), 0.0);
+        assert!(!game.is_animating());
+    }
+
+    #[test]
+    fn test_no_animation() {
+        let mut game = Tetris::with_seed(0);
+        let mut settings = Settings::default();
+        settings.set_clear_duration(0.0);
+        settings.set_fall_duration(0.0);
+        game.set_settings(settings);
+        game.board[19] = vec![1; 10];
+        game.clear_lines();
+        assert!(game.clearing_lines.is_empty());
+        assert_eq!(game.board[19], vec![0; 10]);
+        assert!(!game.is_animating());
+    }
+}
@@ -1,6 +1,18 @@
 "use client";
 import { useEffect, useRef, useState } from "react";
-import init, { Tetris } from "@public/wasm/tetris_wasm.js";
+import init, { Action, Controls, Settings, Tetris, TouchControls } from "@public/wasm/tetris_wasm.js";
+
+// The controls help, in order; keys come from the current bindings.
+const CONTROLS: [Action, string][] = [
+  [Action.MoveLeft, "Move Left"],
+  [Action.MoveRight, "Move Right"],
+  [Action.SoftDrop, "Soft Drop"],
+  [Action.HardDrop, "Hard Drop"],
+  [Action.RotateLeft, "Rotate Left"],
+  [Action.RotateRight, "Rotate Right"],
+  [Action.Rotate180, "Rotate 180"],
+  [Action.Hold, "Hold"],
+];
 
 export default function TetrisPage() {
   const canvasRef = useRef<HTMLCanvasElement>(null);
@@ -14,27 +26,65 @@ export default function TetrisPage() {
   const [tripleCount, setTripleCount] = useState(0);
   const [doubleCount, setDoubleCount] = useState(0);
   const [singleCount, setSingleCount] = useState(0);
+  const [blockSize, setBlockSize] = useState(30);
+  const [drawStats, setDrawStats] = useState<string | null>(null);
+  const [keyHelp, setKeyHelp] = useState<[string, string][]>([]);
+  const [pauseKeys, setPauseKeys] = useState("");
   const gameRef = useRef<Tetris | null>(null);
+  const controlsRef = useRef<Controls | null>(null);
+  const touchRef = useRef<TouchControls | null>(null);
   const lastTimeRef = useRef<number>(0);
 
   useEffect(() => {
     let animationFrameId: number;
-    let dropInterval: NodeJS.Timeout | null = null;
 
     const run = async () => {
       try {
         await init("/wasm/tetris_wasm_bg.wasm");
         const game = new Tetris("game-canvas");
+        const settings = Settings.load();
+        // Keys are mapped in the engine; an empty id listens on the window.
+        const controls = new Controls("", settings);
+        game.set_settings(settings);
         gameRef.current = game;
+        controlsRef.current = controls;
+        const touch = new TouchControls("game-canvas");
+        touchRef.current = touch;
+        const describeKeys = () => {
+          const bound = controls.get_settings();
+          setKeyHelp(
+            CONTROLS.map(([action, name]): [string, string] => [bound.get_key_labels(action), name]).filter(
+              ([keys]) => keys !== "",
+            ),
+          );
+          setPauseKeys(bound.get_pause_key_labels());
+          bound.free();
+        };
+        describeKeys();
+        let capturing = false;
+        // `?debug` shows how long drawing takes, to measure renderer changes.
+        const debug = new URLSearchParams(window.location.search).has("debug");
         game.start();
 
         const update = (currentTime: number) => {
           const deltaTime = (currentTime - lastTimeRef.current) / 1000.0;
           lastTimeRef.current = currentTime;
 
+          controls.apply(game);
+          // A key was just bound: keep it for next time, and the help follows.
+          if (capturing && !controls.is_capturing()) {
+            const bound = controls.get_settings();
+            bound.save();
+            game.set_settings(bound);
+            describeKeys();
+          }
+          capturing = controls.is_capturing();
+          touch.apply(game);
+          setPaused(game.is_paused());
           if (!game.is_game_over() && !game.is_paused()) {
-            game.update_clearing_animation(deltaTime);
+            game.update(deltaTime);
             game.draw();
+            touch.draw(game);
             game.draw_next("next-canvas");
             setScore(game.get_score());
             setClearedLanes(game.get_cleared_lanes());
@@ -42,29 +92,22 @@ export default function TetrisPage() {
             setTripleCount(game.get_triple_count());
             setDoubleCount(game.get_double_count());
             setSingleCount(game.get_single_count());
+            setBlockSize(game.get_block_size());
+            if (debug) {
+              setDrawStats(`${game.get_draw_time().toFixed(2)} ms, ${game.get_cells_drawn()} cells`);
+            }
           } else {
             setGameOver(game.is_game_over());
-            setPaused(game.is_paused());
           }
           animationFrameId = requestAnimationFrame(update);
         };
 
-        const startDropInterval = () => {
-          if (dropInterval) clearInterval(dropInterval);
-          dropInterval = setInterval(() => {
-            if (gameRef.current && !gameRef.current.is_game_over() && !gameRef.current.is_paused()) {
-              gameRef.current.move_down();
-            }
-          }, 1000);
-        };
-
+        // Gravity runs in `update`, at the speed the rules set.
         lastTimeRef.current = performance.now();
-        startDropInterval();
         update(lastTimeRef.current);
 
         return () => {
           cancelAnimationFrame(animationFrameId);
-          if (dropInterval) clearInterval(dropInterval);
         };
       } catch (e) {
         setError(`Failed to load Tetris: ${e}`);
@@ -73,41 +116,12 @@ export default function TetrisPage() {
 
     run();
 
-    const handleKeyDown = (e: KeyboardEvent) => {
-      if (!gameRef.current || gameRef.current.is_game_over()) return;
-      switch (e.key.toLowerCase()) {
-        case "a":
-          gameRef.current.move_left();
-          break;
-        case "d":
-          gameRef.current.move_right();
-          break;
-        case "s":
-          gameRef.current.move_down();
-          break;
-        case "w":
-          gameRef.current.drop();
-          break;
-        case "o":
-          gameRef.current.rotate_left();
-          break;
-        case "p":
-          gameRef.current.rotate_right();
-          break;
-        case " ":
-          if (gameRef.current.is_paused()) {
-            gameRef.current.unpause();
-            setPaused(false);
-          } else {
-            gameRef.current.pause();
-            setPaused(true);
-          }
-          break;
-      }
+    return () => {
+      controlsRef.current?.free();
+      controlsRef.current = null;
+      touchRef.current?.free();
+      touchRef.current = null;
     };
-
-    window.addEventListener("keydown", handleKeyDown);
-    return () => window.removeEventListener("keydown", handleKeyDown);
   }, []);
 
   if (error) {
@@ -127,14 +141,16 @@ export default function TetrisPage() {
         </p>
       )}
       <div className="flex gap-5">
+        {/* The engine fits the board into this box at the screen's pixel density. */}
         <canvas
           id="game-canvas"
           className="border-2 border-gray-400"
           ref={canvasRef}
           width={300}
           height={600}
+          style={{ height: "min(90vh, 120vw)", aspectRatio: "1 / 2" }}
         />
-        <div className="flex flex-col">
+        <div className="flex flex-col" style={{ fontSize: `${blockSize * 0.55}px` }}>
           <canvas
             id="next-canvas"
             ref={nextCanvasRef}
@@ -152,15 +168,19 @@ export default function TetrisPage() {
             <li>Double: {doubleCount}</li>
             <li>Single: {singleCount}</li>
           </ul>
+          {drawStats && <p className="mt-2.5 font-mono text-xs">Draw: {drawStats}</p>}
           <p className="font-bold mt-4">Controls</p>
           <ul className="list-none p-0 mt-2.5 text-left">
-            <li>A - Move Left</li>
-            <li>D - Move Right</li>
-            <li>S - Move Down</li>
-            <li>W - Drop</li>
-            <li>O - Rotate Left</li>
-            <li>P - Rotate Right</li>
-            <li>Space - {paused ? "Unpause" : "Pause"}</li>
+            {keyHelp.map(([keys, name]) => (
+              <li key={name}>
+                {keys} - {name}
+              </li>
+            ))}
+            {pauseKeys && (
+              <li>
+                {pauseKeys} - {paused ? "Unpause" : "Pause"}
+              </li>
+            )}
           </ul>
         </div>
       </div>
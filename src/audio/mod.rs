@@ -0,0 +1,405 @@
+//! Sound effects and music through Web Audio, behind the `audio` cargo
+//! feature. Every sound has a built-in synthesized version; sample packs can
+//! replace any of them. Call `Audio::play_events` once per frame to sound
+//! what the engine did since the last call and keep the music going.
+
+mod music;
+mod synth;
+
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{AudioBuffer, AudioContext, AudioScheduledSourceNode, GainNode, OscillatorType, Response};
+
+use crate::events::GameEvent;
+use crate::storage::log;
+use crate::Tetris;
+use music::{is_tense, theme, Note, Sequencer, Track, ATTACK};
+use synth::{render, tone, Oscillator, Tone, Wave};
+
+const LOOKAHEAD: f64 = 0.15; // Seconds of music scheduled ahead of the clock
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Sound {
+    Move,
+    Rotate,
+    SoftDrop,
+    HardDrop,
+    Lock,
+    Hold,
+    Single,
+    Double,
+    Triple,
+    Tetris,
+    TSpin,
+    LevelUp,
+    GameOver,
+}
+
+impl Sound {
+    const ALL: [Sound; 13] = [
+        Sound::Move,
+        Sound::Rotate,
+        Sound::SoftDrop,
+        Sound::HardDrop,
+        Sound::Lock,
+        Sound::Hold,
+        Sound::Single,
+        Sound::Double,
+        Sound::Triple,
+        Sound::Tetris,
+        Sound::TSpin,
+        Sound::LevelUp,
+        Sound::GameOver,
+    ];
+
+    fn for_event(event: GameEvent) -> Sound {
+        match event {
+            GameEvent::Move => Sound::Move,
+            GameEvent::Rotate => Sound::Rotate,
+            GameEvent::SoftDrop => Sound::SoftDrop,
+            GameEvent::HardDrop => Sound::HardDrop,
+            GameEvent::Lock => Sound::Lock,
+            GameEvent::Hold => Sound::Hold,
+            GameEvent::LineClear(1) => Sound::Single,
+            GameEvent::LineClear(2) => Sound::Double,
+            GameEvent::LineClear(3) => Sound::Triple,
+            GameEvent::LineClear(_) => Sound::Tetris,
+            GameEvent::TSpin(_) => Sound::TSpin,
+            GameEvent::LevelUp => Sound::LevelUp,
+            GameEvent::GameOver => Sound::GameOver,
+        }
+    }
+
+    /// File name of the sound in a sample pack, without the extension.
+    fn file_name(self) -> &'static str {
+        match self {
+            Sound::Move => "move",
+            Sound::Rotate => "rotate",
+            Sound::SoftDrop => "soft-drop",
+            Sound::HardDrop => "hard-drop",
+            Sound::Lock => "lock",
+            Sound::Hold => "hold",
+            Sound::Single => "single",
+            Sound::Double => "double",
+            Sound::Triple => "triple",
+            Sound::Tetris => "tetris",
+            Sound::TSpin => "t-spin",
+            Sound::LevelUp => "level-up",
+            Sound::GameOver => "game-over",
+        }
+    }
+
+    /// The built-in version.
+    fn patch(self) -> Vec<Tone> {
+        use Wave::*;
+        let notes = |hz: &[f64], seconds: f64| hz.iter().map(|&hz| tone(Square, hz, hz, seconds, 0.3)).collect::<Vec<_>>();
+        match self {
+            Sound::Move => vec![tone(Square, 440.0, 440.0, 0.03, 0.12)],
+            Sound::Rotate => vec![tone(Triangle, 660.0, 880.0, 0.05, 0.3)],
+            Sound::SoftDrop => vec![tone(Triangle, 220.0, 200.0, 0.03, 0.15)],
+            Sound::HardDrop => vec![tone(Noise, 1500.0, 200.0, 0.1, 0.4), tone(Square, 110.0, 55.0, 0.08, 0.3)],
+            Sound::Lock => vec![tone(Square, 150.0, 120.0, 0.05, 0.2)],
+            Sound::Hold => vec![tone(Triangle, 520.0, 390.0, 0.08, 0.3)],
+            // Longer arpeggios for bigger clears.
+            Sound::Single => notes(&[523.0], 0.12),
+            Sound::Double => notes(&[523.0, 659.0], 0.08),
+            Sound::Triple => notes(&[523.0, 659.0, 784.0], 0.08),
+            Sound::Tetris => notes(&[523.0, 659.0, 784.0, 1047.0, 1047.0], 0.08),
+            Sound::TSpin => vec![tone(Triangle, 300.0, 900.0, 0.15, 0.4), tone(Square, 880.0, 880.0, 0.12, 0.3)],
+            Sound::LevelUp => notes(&[392.0, 523.0, 659.0, 784.0], 0.07),
+            Sound::GameOver => vec![tone(Square, 392.0, 98.0, 0.8, 0.35)],
+        }
+    }
+}
+
+/// The sounds for `events`, each once: a frame of auto-repeat can hold
+/// several moves.
+fn sounds_for(events: &[GameEvent]) -> Vec<Sound> {
+    let mut sounds = Vec::new();
+    for &event in events {
+        let sound = Sound::for_event(event);
+        if !sounds.contains(&sound) {
+            sounds.push(sound);
+        }
+    }
+    sounds
+}
+
+// The audio graph: effects -> sfx gain -> master gain -> speakers, and
+// notes -> music gain -> master gain.
+struct Output {
+    ctx: AudioContext,
+    master: GainNode,
+    sfx: GainNode,
+    music: GainNode,
+    noise: AudioBuffer, // A second of white noise, looped by noise notes
+}
+
+impl Output {
+    fn new() -> Result<Output, JsValue> {
+        let ctx = AudioContext::new()?;
+        let master = ctx.create_gain()?;
+        let sfx = ctx.create_gain()?;
+        let music = ctx.create_gain()?;
+        sfx.connect_with_audio_node(&master)?;
+        music.connect_with_audio_node(&master)?;
+        master.connect_with_audio_node(&ctx.destination())?;
+
+        let rate = ctx.sample_rate();
+        let mut oscillator = Oscillator::new(Wave::Noise);
+        let samples: Vec<f32> = (0..rate as usize).map(|_| oscillator.next(rate as f64, rate as f64) as f32).collect();
+        let noise = ctx.create_buffer(1, samples.len() as u32, rate)?;
+        noise.copy_to_channel(&samples, 0)?;
+        Ok(Output {
+            ctx,
+            master,
+            sfx,
+            music,
+            noise,
+        })
+    }
+
+    fn play_note(&self, note: &Note) -> Result<(), JsValue> {
+        let end = note.start + note.seconds;
+        let gain = self.ctx.create_gain()?;
+        let level = gain.gain();
+        level.set_value_at_time(0.0, note.start)?;
+        level.linear_ramp_to_value_at_time(note.volume as f32, note.start + ATTACK)?;
+        level.linear_ramp_to_value_at_time(0.0, end)?;
+        gain.connect_with_audio_node(&self.music)?;
+        let source: AudioScheduledSourceNode = match note.wave {
+            Wave::Noise => {
+                let source = self.ctx.create_buffer_source()?;
+                source.set_buffer(Some(&self.noise));
+                source.set_loop(true);
+                source.into()
+            }
+            wave => {
+                let oscillator = self.ctx.create_oscillator()?;
+                oscillator.set_type(if wave == Wave::Square { OscillatorType::Square } else { OscillatorType::Triangle });
+                oscillator.frequency().set_value_at_time(note.hz as f32, note.start)?;
+                oscillator.into()
+            }
+        };
+        source.connect_with_audio_node(&gain)?;
+        source.start_with_when(note.start)?;
+        source.stop_with_when(end)
+    }
+}
+
+type Buffers = Rc<RefCell<BTreeMap<Sound, AudioBuffer>>>;
+
+#[wasm_bindgen]
+pub struct Audio {
+    output: Option<Output>, // None without Web Audio; everything is then silent
+    buffers: Buffers,       // Synthesized on first use, or loaded
+    tracks: [Track; 2],     // Calm, and for a stack near the top
+    sequencer: RefCell<Sequencer>,
+    music: Cell<bool>,
+}
+
+#[wasm_bindgen]
+impl Audio {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Audio {
+        let output = match Output::new() {
+            Ok(output) => Some(output),
+            Err(err) => {
+                log(&format!("Web Audio is unavailable; playing no sound: {:?}", err));
+                None
+            }
+        };
+        Audio {
+            output,
+            buffers: Rc::default(),
+            tracks: [theme(false), theme(true)],
+            sequencer: RefCell::default(),
+            music: Cell::new(false),
+        }
+    }
+
+    /// Browsers start audio suspended. Call from a key or touch handler.
+    pub fn resume(&self) {
+        if let Some(ref output) = self.output {
+            let _ = output.ctx.resume();
+        }
+    }
+
+    /// Plays what `game` did since the last call and schedules the music
+    /// ahead, at the volumes in its settings.
+    pub fn play_events(&self, game: &mut Tetris) {
+        if let Some(ref output) = self.output {
+            output.master.gain().set_value(game.settings.master_volume as f32);
+            output.sfx.gain().set_value(game.settings.sfx_volume as f32);
+            output.music.gain().set_value(game.settings.music_volume as f32);
+        }
+        for sound in sounds_for(&game.take_events()) {
+            self.play(sound);
+        }
+        self.update_music(game);
+    }
+
+    /// The built-in music mixed to mono samples, without Web Audio: for
+    /// exporting it, or playing it through another audio stack.
+    pub fn render_music(level: u32, tense: bool, loops: usize, sample_rate: f64) -> Vec<f32> {
+        music::render(&theme(tense), level, loops, sample_rate)
+    }
+
+    pub fn get_music(&self) -> bool {
+        self.music.get()
+    }
+
+    /// Background music follows the game passed to `play_events`: faster
+    /// with each level, tense near the top, silent while paused and back
+    /// to the start after a game over.
+    pub fn set_music(&self, music: bool) {
+        self.music.set(music);
+    }
+
+    pub fn play(&self, sound: Sound) {
+        let output = match self.output {
+            Some(ref output) => output,
+            None => return,
+        };
+        let buffer = match self.buffer(output, sound) {
+            Some(buffer) => buffer,
+            None => return,
+        };
+        let played = output.ctx.create_buffer_source().and_then(|source| {
+            source.set_buffer(Some(&buffer));
+            source.connect_with_audio_node(&output.sfx)?;
+            source.start()
+        });
+        if let Err(err) = played {
+            log(&format!("Failed to play {:?}: {:?}", sound, err));
+        }
+    }
+
+    /// Replaces `sound` with the audio file at `url` once it has loaded.
+    /// The synthesized sound plays until then, or if loading fails.
+    pub fn load_sample(&self, sound: Sound, url: &str) {
+        let (ctx, window) = match (&self.output, web_sys::window()) {
+            (Some(output), Some(window)) => (output.ctx.clone(), window),
+            _ => return,
+        };
+        let buffers = self.buffers.clone();
+        let store = Closure::wrap(Box::new(move |buffer: JsValue| match buffer.dyn_into::<AudioBuffer>() {
+            Ok(buffer) => {
+                buffers.borrow_mut().insert(sound, buffer);
+            }
+            Err(_) => log(&format!("Failed to decode the {:?} sample", sound)),
+        }) as Box<dyn FnMut(JsValue)>);
+        let url_for_log = url.to_string();
+        let decode = Closure::wrap(Box::new(move |bytes: JsValue| {
+            match bytes.dyn_into().map(|bytes| ctx.decode_audio_data(&bytes)) {
+                Ok(Ok(decoding)) => {
+                    let _ = decoding.then(&store);
+                }
+                _ => log(&format!("Failed to read {}", url_for_log)),
+            }
+        }) as Box<dyn FnMut(JsValue)>);
+        let url_for_log = url.to_string();
+        let read = Closure::wrap(Box::new(move |response: JsValue| {
+            match response.dyn_into::<Response>() {
+                Ok(response) if response.ok() => {
+                    if let Ok(reading) = response.array_buffer() {
+                        let _ = reading.then(&decode);
+                    }
+                }
+                _ => log(&format!("Failed to load {}", url_for_log)),
+            }
+        }) as Box<dyn FnMut(JsValue)>);
+        let _ = window.fetch_with_str(url).then(&read);
+        // Each closure owns the next; the first lives as long as the page.
+        // Packs are loaded once, so the few bytes are not worth tracking.
+        read.forget();
+    }
+
+    /// Loads every sound of a pack, from files named like
+    /// `{base_url}/hard-drop.{extension}`. Missing files keep the built-in
+    /// sound.
+    pub fn load_pack(&self, base_url: &str, extension: &str) {
+        for sound in Sound::ALL {
+            self.load_sample(sound, &format!("{}/{}.{}", base_url.trim_end_matches('/'), sound.file_name(), extension));
+        }
+    }
+}
+
+impl Default for Audio {
+    fn default() -> Audio {
+        Audio::new()
+    }
+}
+
+impl Audio {
+    fn update_music(&self, game: &Tetris) {
+        let output = match self.output {
+            Some(ref output) => output,
+            None => return,
+        };
+        let mut sequencer = self.sequencer.borrow_mut();
+        if game.game_over {
+            sequencer.stop();
+            return;
+        }
+        if !self.music.get() || game.paused || !game.in_play() {
+            sequencer.pause();
+            return;
+        }
+        let track = &self.tracks[is_tense(&game.board) as usize];
+        let now = output.ctx.current_time();
+        for note in sequencer.schedule(track, game.get_level(), now, now + LOOKAHEAD) {
+            if let Err(err) = output.play_note(&note) {
+                log(&format!("Failed to play music: {:?}", err));
+                return;
+            }
+        }
+    }
+
+    fn buffer(&self, output: &Output, sound: Sound) -> Option<AudioBuffer> {
+        if let Some(buffer) = self.buffers.borrow().get(&sound) {
+            return Some(buffer.clone());
+        }
+        let rate = output.ctx.sample_rate();
+        let samples = render(&sound.patch(), rate as f64);
+        let buffer = output
+            .ctx
+            .create_buffer(1, samples.len() as u32, rate)
+            .and_then(|buffer| buffer.copy_to_channel(&samples, 0).map(|_| buffer));
+        match buffer {
+            Ok(buffer) => {
+                self.buffers.borrow_mut().insert(sound, buffer.clone());
+                Some(buffer)
+            }
+            Err(err) => {
+                log(&format!("Failed to synthesize {:?}: {:?}", sound, err));
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sounds_for_events() {
+        use GameEvent::*;
+        let events = [Move, Move, Move, HardDrop, Lock, LineClear(4), LevelUp];
+        assert_eq!(
+            sounds_for(&events),
+            vec![Sound::Move, Sound::HardDrop, Sound::Lock, Sound::Tetris, Sound::LevelUp]
+        );
+        assert_eq!(sounds_for(&[TSpin(2), LineClear(2)]), vec![Sound::TSpin, Sound::Double]);
+        for sound in Sound::ALL {
+            assert!(!render(&sound.patch(), 8000.0).is_empty());
+        }
+    }
+}
@@ -0,0 +1,293 @@
+//! A small chiptune sequencer. A track is a loop of equal-length patterns,
+//! one per voice, written one token per step:
+//!
+//! - a note name such as `E5`, `C#4` or `Bb3` starts a note,
+//! - `x` starts a noise hit,
+//! - `-` holds the note before it,
+//! - `.` is a rest,
+//! - `|` is a bar line and is ignored.
+//!
+//! The sequencer turns a track into timed notes, which `Audio` plays on
+//! Web Audio oscillators and `render` mixes into samples.
+
+use crate::audio::synth::{Oscillator, Wave};
+
+const NOISE_HZ: f64 = 3000.0; // Pitch of an `x` hit
+const MAX_SPEEDUP: f64 = 2.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Step {
+    Note(f64), // Hz
+    Hold,
+    Rest,
+}
+
+/// One voice of a track.
+#[derive(Clone, Debug, PartialEq)]
+struct Channel {
+    wave: Wave,
+    volume: f64,
+    steps: Vec<Step>,
+}
+
+/// `A4` is 440 Hz. Returns None for anything else than a note name.
+fn note_hz(name: &str) -> Option<f64> {
+    let mut chars = name.chars();
+    let semitone = match chars.next()? {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return None,
+    };
+    let rest = chars.as_str();
+    let (shift, octave) = match rest.strip_prefix('#') {
+        Some(octave) => (1, octave),
+        None => match rest.strip_prefix('b') {
+            Some(octave) => (-1, octave),
+            None => (0, rest),
+        },
+    };
+    let octave: i32 = octave.parse().ok()?;
+    let midi = (octave + 1) * 12 + semitone + shift;
+    Some(440.0 * 2f64.powf((midi - 69) as f64 / 12.0))
+}
+
+fn parse_pattern(pattern: &str) -> Option<Vec<Step>> {
+    pattern
+        .split_whitespace()
+        .filter(|&token| token != "|")
+        .map(|token| match token {
+            "-" => Some(Step::Hold),
+            "." => Some(Step::Rest),
+            "x" => Some(Step::Note(NOISE_HZ)),
+            name => note_hz(name).map(Step::Note),
+        })
+        .collect()
+}
+
+/// A looping multi-voice tune.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Track {
+    bpm: f64,
+    steps_per_beat: usize,
+    channels: Vec<Channel>,
+}
+
+impl Track {
+    /// None if a pattern doesn't parse or the patterns differ in length.
+    pub(crate) fn parse(bpm: f64, steps_per_beat: usize, voices: &[(Wave, f64, &str)]) -> Option<Track> {
+        let channels = voices
+            .iter()
+            .map(|&(wave, volume, pattern)| {
+                Some(Channel {
+                    wave,
+                    volume,
+                    steps: parse_pattern(pattern)?,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let length = channels.first()?.steps.len();
+        if length == 0 || channels.iter().any(|channel| channel.steps.len() != length) {
+            return None;
+        }
+        Some(Track {
+            bpm,
+            steps_per_beat: steps_per_beat.max(1),
+            channels,
+        })
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.channels[0].steps.len()
+    }
+
+    /// Seconds per step. Every level is 5% faster, up to twice the tempo.
+    pub(crate) fn step_seconds(&self, level: u32) -> f64 {
+        let speedup = (1.0 + 0.05 * level.saturating_sub(1) as f64).min(MAX_SPEEDUP);
+        60.0 / (self.bpm * speedup * self.steps_per_beat as f64)
+    }
+
+    /// The notes starting at `step`, lasting through their holds.
+    fn notes_at(&self, step: usize, start: f64, step_seconds: f64) -> Vec<Note> {
+        let step = step % self.len();
+        let mut notes = Vec::new();
+        for channel in &self.channels {
+            if let Step::Note(hz) = channel.steps[step] {
+                let holds = channel.steps[step + 1..].iter().take_while(|&&next| next == Step::Hold).count();
+                notes.push(Note {
+                    wave: channel.wave,
+                    hz,
+                    start,
+                    seconds: (holds + 1) as f64 * step_seconds,
+                    volume: channel.volume,
+                });
+            }
+        }
+        notes
+    }
+}
+
+/// A note to play, in seconds on the player's clock.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Note {
+    pub(crate) wave: Wave,
+    pub(crate) hz: f64,
+    pub(crate) start: f64,
+    pub(crate) seconds: f64,
+    pub(crate) volume: f64,
+}
+
+/// Where playback is in the loop.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Sequencer {
+    step: usize,
+    next: Option<f64>, // When the next step starts; None until playback (re)starts
+}
+
+impl Sequencer {
+    /// Notes of every step starting before `until`. After a pause, playback
+    /// picks up where it was, from `now`.
+    pub(crate) fn schedule(&mut self, track: &Track, level: u32, now: f64, until: f64) -> Vec<Note> {
+        let step_seconds = track.step_seconds(level);
+        let mut next = self.next.unwrap_or(now).max(now);
+        let mut notes = Vec::new();
+        while next < until {
+            notes.extend(track.notes_at(self.step, next, step_seconds));
+            self.step = (self.step + 1) % track.len();
+            next += step_seconds;
+        }
+        self.next = Some(next);
+        notes
+    }
+
+    /// Stops the clock, keeping the place in the loop.
+    pub(crate) fn pause(&mut self) {
+        self.next = None;
+    }
+
+    pub(crate) fn stop(&mut self) {
+        *self = Sequencer::default();
+    }
+}
+
+pub(crate) const ATTACK: f64 = 0.005;
+
+/// How loud a note is `t` seconds in: a quick ramp up, then a linear fade
+/// to silence at its end. Web Audio playback ramps the same way.
+pub(crate) fn envelope(note: &Note, t: f64) -> f64 {
+    if t < ATTACK {
+        note.volume * t / ATTACK
+    } else {
+        note.volume * (1.0 - (t - ATTACK) / (note.seconds - ATTACK)).max(0.0)
+    }
+}
+
+/// Whether the stack reaches into the top 30% of the board.
+pub(crate) fn is_tense(board: &[Vec<u8>]) -> bool {
+    let top = board.iter().position(|row| row.iter().any(|&cell| cell != 0));
+    top.is_some_and(|top| (top as f64) < board.len() as f64 * 0.3)
+}
+
+/// Mixes `loops` times through `track` at `level` into mono samples.
+pub(crate) fn render(track: &Track, level: u32, loops: usize, sample_rate: f64) -> Vec<f32> {
+    let length = track.len() as f64 * track.step_seconds(level) * loops as f64;
+    let mut samples = vec![0.0f32; (length * sample_rate).round() as usize];
+    let mut sequencer = Sequencer::default();
+    for note in sequencer.schedule(track, level, 0.0, length - 1e-9) {
+        let first = (note.start * sample_rate).round() as usize;
+        let count = (note.seconds * sample_rate) as usize;
+        let mut oscillator = Oscillator::new(note.wave);
+        for i in 0..count.min(samples.len().saturating_sub(first)) {
+            let value = oscillator.next(note.hz, sample_rate) * envelope(&note, i as f64 / sample_rate);
+            samples[first + i] += value as f32;
+        }
+    }
+    samples
+}
+
+/// The built-in tune, "Korobeiniki", in eighth notes.
+pub(crate) fn theme(tense: bool) -> Track {
+    let melody = "E5 - B4 C5 D5 - C5 B4 | A4 - A4 C5 E5 - D5 C5 | B4 - - C5 D5 - E5 - | C5 - A4 - A4 - - . \
+                  | . D5 - F5 A5 - G5 F5 | E5 - - C5 E5 - D5 C5 | B4 - B4 C5 D5 - E5 - | C5 - A4 - A4 - - .";
+    // Near the top out, the bass drives on every step and the drums double.
+    let (bass, drums, bpm) = if tense {
+        (
+            "E2 E2 E3 E2 E2 E3 E2 E3 | A2 A2 A3 A2 A2 A3 A2 A3 | G#2 G#2 G#3 G#2 E2 E2 E3 E2 | A2 A2 A3 A2 A2 A3 A2 A3 \
+             | D2 D2 D3 D2 D2 D3 D2 D3 | C2 C2 C3 C2 C2 C3 C2 C3 | G#2 G#2 G#3 G#2 E2 E2 E3 E2 | A2 A2 A3 A2 A2 A3 A2 A3",
+            "x x x x x x x x | x x x x x x x x | x x x x x x x x | x x x x x x x x \
+             | x x x x x x x x | x x x x x x x x | x x x x x x x x | x x x x x x x x",
+            160.0,
+        )
+    } else {
+        (
+            "E2 E3 E2 E3 E2 E3 E2 E3 | A2 A3 A2 A3 A2 A3 A2 A3 | G#2 G#3 G#2 G#3 E2 E3 E2 E3 | A2 A3 A2 A3 A2 A3 A2 A3 \
+             | D2 D3 D2 D3 D2 D3 D2 D3 | C2 C3 C2 C3 C2 C3 C2 C3 | G#2 G#3 G#2 G#3 E2 E3 E2 E3 | A2 A3 A2 A3 A2 A3 A2 A3",
+            "x . . . x . . . | x . . . x . . . | x . . . x . . . | x . . . x . x . \
+             | x . . . x . . . | x . . . x . . . | x . . . x . . . | x . x . x . x .",
+            140.0,
+        )
+    };
+    Track::parse(bpm, 2, &[(Wave::Square, 0.15, melody), (Wave::Triangle, 0.3, bass), (Wave::Noise, 0.08, drums)])
+        .expect("the built-in track parses")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(note_hz("A4"), Some(440.0));
+        assert!((note_hz("C#4").unwrap() - note_hz("Db4").unwrap()).abs() < 1e-9);
+        assert_eq!(note_hz("H2"), None);
+        let track = Track::parse(120.0, 2, &[(Wave::Square, 1.0, "A4 - . A5 | x"), (Wave::Noise, 1.0, "x . x . x")]).unwrap();
+        assert_eq!(track.len(), 5);
+        assert!(Track::parse(120.0, 2, &[(Wave::Square, 1.0, "A4 -"), (Wave::Noise, 1.0, "x")]).is_none());
+        assert!(Track::parse(120.0, 2, &[(Wave::Square, 1.0, "A4 Q")]).is_none());
+
+        let notes = track.notes_at(0, 1.0, 0.25);
+        assert_eq!(notes.len(), 2);
+        assert_eq!((notes[0].hz, notes[0].seconds), (440.0, 0.5)); // Held for a step
+        assert_eq!(notes[1].wave, Wave::Noise);
+    }
+
+    #[test]
+    fn test_schedule_and_tempo() {
+        let track = theme(false);
+        assert!(track.step_seconds(10) < track.step_seconds(1));
+        assert_eq!(track.step_seconds(100), track.step_seconds(1) / MAX_SPEEDUP);
+
+        let mut sequencer = Sequencer::default();
+        let step = track.step_seconds(1);
+        let notes = sequencer.schedule(&track, 1, 0.0, step * 2.5);
+        assert!(notes.iter().all(|note| note.start < step * 2.5));
+        assert_eq!(sequencer.step, 3);
+        // Nothing is scheduled twice, and a pause resumes from the same step.
+        assert!(sequencer.schedule(&track, 1, step, step * 2.5).is_empty());
+        sequencer.pause();
+        let resumed = sequencer.schedule(&track, 1, 100.0, 100.0 + step / 2.0);
+        assert_eq!(resumed, track.notes_at(3, 100.0, step));
+    }
+
+    #[test]
+    fn test_render() {
+        let track = theme(false);
+        let calm = render(&track, 1, 1, 8000.0);
+        let expected = (track.len() as f64 * track.step_seconds(1) * 8000.0).round() as usize;
+        assert_eq!(calm.len(), expected);
+        assert!(calm.iter().any(|&sample| sample.abs() > 0.1));
+        assert_eq!(calm, render(&track, 1, 1, 8000.0));
+        assert!(render(&track, 10, 1, 8000.0).len() < calm.len());
+        assert_ne!(render(&theme(true), 1, 1, 8000.0), calm);
+
+        let mut board = vec![vec![0; 10]; 20];
+        board[10][0] = 1;
+        assert!(!is_tense(&board));
+        board[5][0] = 1;
+        assert!(is_tense(&board));
+    }
+}
@@ -0,0 +1,114 @@
+//! A tiny procedural synth: sequences of swept tones rendered to samples, so
+//! the game needs no sound files.
+
+use crate::rng::Rng;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Wave {
+    Square,
+    Triangle,
+    Noise,
+}
+
+/// A note that sweeps from `from` to `to` Hz and decays to silence.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Tone {
+    pub(crate) wave: Wave,
+    pub(crate) from: f64,
+    pub(crate) to: f64,
+    pub(crate) seconds: f64,
+    pub(crate) volume: f64,
+}
+
+pub(crate) const fn tone(wave: Wave, from: f64, to: f64, seconds: f64, volume: f64) -> Tone {
+    Tone {
+        wave,
+        from,
+        to,
+        seconds,
+        volume,
+    }
+}
+
+const ATTACK: f64 = 0.005; // Seconds, to avoid clicks
+
+/// One voice, from -1 to 1. Noise holds a random level for each half
+/// cycle, so it has a pitch that follows the frequency too.
+pub(crate) struct Oscillator {
+    wave: Wave,
+    phase: f64, // In cycles
+    noise: Rng,
+    half: u64, // Half cycles so far, for noise
+    level: f64,
+}
+
+impl Oscillator {
+    pub(crate) fn new(wave: Wave) -> Oscillator {
+        Oscillator {
+            wave,
+            phase: 0.0,
+            noise: Rng::new(1),
+            half: 0,
+            level: 0.0,
+        }
+    }
+
+    pub(crate) fn next(&mut self, frequency: f64, sample_rate: f64) -> f64 {
+        let cycle = self.phase.fract();
+        let value = match self.wave {
+            Wave::Square => {
+                if cycle < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Wave::Triangle => 1.0 - 4.0 * (cycle - 0.5).abs(),
+            Wave::Noise => {
+                let half = (self.phase * 2.0) as u64;
+                if half != self.half || self.phase == 0.0 {
+                    self.half = half;
+                    self.level = (self.noise.next_u64() >> 11) as f64 / (1u64 << 52) as f64 - 1.0;
+                }
+                self.level
+            }
+        };
+        self.phase += frequency / sample_rate;
+        value
+    }
+}
+
+/// Plays `tones` one after another into mono samples at `sample_rate`.
+pub(crate) fn render(tones: &[Tone], sample_rate: f64) -> Vec<f32> {
+    let mut samples = Vec::new();
+    for tone in tones {
+        let count = (tone.seconds * sample_rate) as usize;
+        let mut oscillator = Oscillator::new(tone.wave);
+        for i in 0..count {
+            let t = i as f64 / count as f64;
+            let frequency = tone.from * (tone.to / tone.from).powf(t); // Even steps in pitch
+            let envelope = (i as f64 / (ATTACK * sample_rate)).min(1.0) * (1.0 - t);
+            samples.push((oscillator.next(frequency, sample_rate) * envelope * tone.volume) as f32);
+        }
+    }
+    samples
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let tones = [tone(Wave::Square, 100.0, 100.0, 0.5, 0.5), tone(Wave::Noise, 2000.0, 500.0, 0.25, 1.0)];
+        let samples = render(&tones, 8000.0);
+        assert_eq!(samples.len(), 6000);
+        assert!(samples.iter().all(|sample| sample.abs() <= 1.0));
+        // Fades in from silence to the volume, flipping sign twice per cycle.
+        assert_eq!(samples[0], 0.0);
+        assert!(samples[..4000].iter().any(|&sample| sample > 0.45));
+        let flips = samples[1..4000].windows(2).filter(|pair| (pair[0] > 0.0) != (pair[1] > 0.0)).count();
+        assert!((98..=100).contains(&flips), "{}", flips);
+        assert_eq!(samples, render(&tones, 8000.0));
+    }
+}
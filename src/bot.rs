@@ -0,0 +1,362 @@
+//! Heuristic placement bot. Every reachable placement of the current piece
+//! (and of the piece a hold would bring in), as found by the move generator,
+//! is scored with a weighted sum of board features in the spirit of
+//! Dellacherie and El-Tetris.
+
+use std::collections::VecDeque;
+
+use wasm_bindgen::prelude::*;
+
+use crate::movegen::placements_from;
+use crate::{Action, Piece, Tetris};
+
+/// Feature weights. Positive weights reward a feature, negative ones punish it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Weights {
+    pub aggregate_height: f64,
+    pub holes: f64,
+    pub bumpiness: f64,
+    pub wells: f64,
+    pub lines_cleared: f64,
+    pub tspin_setups: f64,
+}
+
+impl Default for Weights {
+    fn default() -> Weights {
+        Weights {
+            aggregate_height: -0.510066,
+            holes: -0.35663,
+            bumpiness: -0.184483,
+            wells: -0.05,
+            lines_cleared: 0.760666,
+            tspin_setups: 0.1,
+        }
+    }
+}
+
+/// Board features a placement is judged on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Features {
+    pub aggregate_height: u32,
+    pub holes: u32,
+    pub bumpiness: u32,
+    pub wells: u32,
+    pub lines_cleared: u32,
+    pub tspin_setups: u32,
+}
+
+impl Features {
+    pub fn score(&self, weights: &Weights) -> f64 {
+        weights.aggregate_height * self.aggregate_height as f64
+            + weights.holes * self.holes as f64
+            + weights.bumpiness * self.bumpiness as f64
+            + weights.wells * self.wells as f64
+            + weights.lines_cleared * self.lines_cleared as f64
+            + weights.tspin_setups * self.tspin_setups as f64
+    }
+}
+
+/// A chosen placement and the inputs that reach it from where the piece is.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Move {
+    pub x: i32,
+    pub y: i32,
+    pub rotation: u8, // Quarter turns clockwise from spawn
+    pub hold: bool,
+    pub actions: Vec<Action>,
+    pub score: f64,
+    // The piece (kind, x, y, rotation) each of `actions` is pressed on.
+    states: Vec<PieceState>,
+}
+
+type PieceState = (usize, i32, i32, u8);
+
+fn piece_state(piece: &Piece) -> PieceState {
+    (piece.kind, piece.x, piece.y, piece.rotation)
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Debug, Default)]
+pub struct Bot {
+    weights: Weights,
+    plan: VecDeque<(Action, PieceState)>,
+}
+
+impl Bot {
+    pub fn with_weights(weights: Weights) -> Bot {
+        Bot {
+            weights,
+            plan: VecDeque::new(),
+        }
+    }
+
+    pub fn weights(&self) -> &Weights {
+        &self.weights
+    }
+
+    /// Best placement for the game's current piece, or `None` if the game has
+    /// no piece in play or nothing fits.
+    pub fn best_move(&self, game: &Tetris) -> Option<Move> {
+        let current = game.current_piece.as_ref()?;
+        let mut best = best_placement(game, current, &self.weights);
+        if game.rules.hold && !game.hold_used {
+            let held_kind = game.hold_piece.as_ref().unwrap_or(&game.next_piece).kind;
+            if held_kind != current.kind {
+                let with_hold = best_placement(game, &game.new_piece(held_kind), &self.weights).map(|mut best| {
+                    best.hold = true;
+                    best.actions.insert(0, Action::Hold);
+                    best.states.insert(0, piece_state(current));
+                    best
+                });
+                best = match (best, with_hold) {
+                    (Some(a), Some(b)) => Some(if b.score > a.score { b } else { a }),
+                    (a, b) => a.or(b),
+                };
+            }
+        }
+        best
+    }
+
+    /// Plays the best move to completion on `game`. Returns false if there
+    /// was nothing to play.
+    pub fn play_move(&mut self, game: &mut Tetris) -> bool {
+        match self.best_move(game) {
+            Some(best) => {
+                for action in best.actions {
+                    game.apply_action(action);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl Bot {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Bot {
+        Bot::default()
+    }
+
+    pub fn set_weights(
+        &mut self,
+        aggregate_height: f64,
+        holes: f64,
+        bumpiness: f64,
+        wells: f64,
+        lines_cleared: f64,
+        tspin_setups: f64,
+    ) {
+        self.weights = Weights {
+            aggregate_height,
+            holes,
+            bumpiness,
+            wells,
+            lines_cleared,
+            tspin_setups,
+        };
+        self.plan.clear();
+    }
+
+    /// Next input of the planned move, planning a new move when the last one
+    /// has been used up or the piece has left its path, e.g. by falling.
+    /// Call once per frame for an "AI plays" demo.
+    pub fn next_action(&mut self, game: &Tetris) -> Option<Action> {
+        if game.is_game_over() || game.is_paused() || !game.clearing_lines.is_empty() {
+            return None;
+        }
+        let current = game.current_piece.as_ref()?;
+        if self.plan.front().is_some_and(|&(_, state)| state != piece_state(current)) {
+            self.plan.clear();
+        }
+        if self.plan.is_empty() {
+            let best = self.best_move(game)?;
+            self.plan = best.actions.into_iter().zip(best.states).collect();
+        }
+        self.plan.pop_front().map(|(action, _)| action)
+    }
+}
+
+/// Best placement for `start`, from where it is, without holding.
+pub(crate) fn best_placement(game: &Tetris, start: &Piece, weights: &Weights) -> Option<Move> {
+    let mut best: Option<Move> = None;
+    for placement in placements_from(&game.board, start, game.rules.rotation, game.rules.kick_180) {
+        let score = evaluate(&game.board, &placement.piece()).score(weights);
+        if best.as_ref().is_none_or(|b| score > b.score) {
+            best = Some(Move {
+                x: placement.x,
+                y: placement.y,
+                rotation: placement.rotation,
+                hold: false,
+                actions: placement.actions,
+                score,
+                states: placement.states.iter().map(|&(x, y, rotation)| (start.kind, x, y, rotation)).collect(),
+            });
+        }
+    }
+    best
+}
+
+/// Features of `board` after `piece` locks at its position and full rows clear.
+pub(crate) fn evaluate(board: &[Vec<u8>], piece: &Piece) -> Features {
+    let mut board = board.to_vec();
+    for (y, row) in piece.shape.iter().enumerate() {
+        for (x, &cell) in row.iter().enumerate() {
+            let board_y = piece.y + y as i32;
+            if cell != 0 && board_y >= 0 {
+                board[board_y as usize][(piece.x + x as i32) as usize] = piece.color;
+            }
+        }
+    }
+    let width = board[0].len();
+    let before = board.len();
+    board.retain(|row| !row.iter().all(|&cell| cell != 0));
+    let lines_cleared = (before - board.len()) as u32;
+    while board.len() < before {
+        board.insert(0, vec![0; width]);
+    }
+    board_features(&board, lines_cleared)
+}
+
+pub(crate) fn board_features(board: &[Vec<u8>], lines_cleared: u32) -> Features {
+    let height = board.len();
+    let width = board[0].len();
+    let filled = |x: i32, y: i32| -> bool {
+        x < 0 || x >= width as i32 || y >= height as i32 || (y >= 0 && board[y as usize][x as usize] != 0)
+    };
+
+    let mut features = Features {
+        lines_cleared,
+        ..Features::default()
+    };
+    let mut heights = vec![0u32; width];
+    for (x, column_height) in heights.iter_mut().enumerate() {
+        let top = (0..height).find(|&y| board[y][x] != 0).unwrap_or(height);
+        *column_height = (height - top) as u32;
+        features.holes += (top..height).filter(|&y| board[y][x] == 0).count() as u32;
+
+        // Each open cell of a well adds its depth, so deep wells cost more.
+        let mut depth = 0;
+        for y in 0..top as i32 {
+            if !filled(x as i32, y) && filled(x as i32 - 1, y) && filled(x as i32 + 1, y) {
+                depth += 1;
+                features.wells += depth;
+            } else {
+                depth = 0;
+            }
+        }
+    }
+    features.aggregate_height = heights.iter().sum();
+    features.bumpiness = heights.windows(2).map(|pair| pair[0].abs_diff(pair[1])).sum();
+
+    // A T-spin slot: room for a T pointing down with three of the four
+    // corners around its center filled.
+    for cy in 0..height as i32 - 1 {
+        for cx in 1..width as i32 - 1 {
+            let open = [(cx - 1, cy), (cx, cy), (cx + 1, cy), (cx, cy + 1)]
+                .iter()
+                .all(|&(x, y)| !filled(x, y));
+            let corners = [(cx - 1, cy - 1), (cx + 1, cy - 1), (cx - 1, cy + 1), (cx + 1, cy + 1)]
+                .iter()
+                .filter(|&&(x, y)| filled(x, y))
+                .count();
+            if open && corners >= 3 {
+                features.tspin_setups += 1;
+            }
+        }
+    }
+    features
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_features() {
+        let mut board = vec![vec![0; 4]; 4];
+        board[2] = vec![1, 1, 1, 0];
+        board[3] = vec![1, 0, 1, 0];
+        let features = board_features(&board, 0);
+        assert_eq!(features.aggregate_height, 6);
+        assert_eq!(features.holes, 1);
+        assert_eq!(features.bumpiness, 2);
+        // Column 3 is a two-deep well against the wall: 1 + 2.
+        assert_eq!(features.wells, 3);
+    }
+
+    #[test]
+    fn test_tspin_slot() {
+        let mut board = vec![vec![0; 5]; 3];
+        board[0] = vec![0, 1, 0, 0, 0];
+        board[1] = vec![0, 0, 0, 0, 1];
+        board[2] = vec![1, 1, 0, 1, 1];
+        board[1][0] = 1;
+        assert_eq!(board_features(&board, 0).tspin_setups, 1);
+    }
+
+    #[test]
+    fn test_prefers_line_clear() {
+        let mut game = Tetris::with_seed(0);
+        game.start();
+        game.current_piece = Some(Tetris::create_piece(&crate::default_shapes(), 0));
+        game.hold_used = true;
+        game.board[19] = vec![1, 1, 1, 1, 1, 1, 0, 0, 0, 0];
+        let best = Bot::new().best_move(&game).unwrap();
+        assert_eq!((best.x, best.y, best.rotation), (6, 19, 0));
+        assert_eq!(best.actions.last(), Some(&Action::HardDrop));
+    }
+
+    #[test]
+    fn test_no_hold_without_the_rule() {
+        let mut game = Tetris::with_seed(0);
+        game.set_rule_preset(crate::RulePreset::Nes);
+        game.start();
+        let bot = Bot::new();
+        for _ in 0..30 {
+            let best = bot.best_move(&game).unwrap();
+            assert!(!best.hold && !best.actions.contains(&Action::Hold));
+            for action in best.actions {
+                game.apply_action(action);
+            }
+            game.update_clearing_animation(0.3);
+        }
+    }
+
+    #[test]
+    fn test_plans_again_when_the_piece_moves() {
+        let mut game = Tetris::with_seed(3);
+        game.start();
+        let mut bot = Bot::new();
+        assert!(bot.next_action(&game).is_some());
+        // Gravity and the player take the piece off the planned path.
+        game.move_down();
+        game.move_left();
+        let mut expected = Tetris::with_seed(3);
+        expected.start();
+        expected.move_down();
+        expected.move_left();
+        assert!(bot.play_move(&mut expected));
+        while game.stats.pieces_placed == 0 {
+            let action = bot.next_action(&game).unwrap();
+            game.apply_action(action);
+        }
+        assert_eq!(game.board, expected.board);
+    }
+
+    #[test]
+    fn test_bot_clears_lines() {
+        let mut game = Tetris::with_seed(7);
+        game.start();
+        let mut bot = Bot::new();
+        for _ in 0..200 {
+            if game.is_game_over() || !bot.play_move(&mut game) {
+                break;
+            }
+            game.update_clearing_animation(0.3);
+        }
+        assert!(!game.is_game_over());
+        assert!(game.get_cleared_lanes() >= 60);
+    }
+}
@@ -0,0 +1,188 @@
+//! Waiting for a piece: the READY / GO countdown before play starts and
+//! after unpausing, and the entry delay (ARE) between a lock and the next
+//! spawn. Rotations and holds pressed while waiting for a spawn are
+//! buffered and applied as the piece appears (IRS and IHS).
+
+use wasm_bindgen::prelude::*;
+
+use crate::{Action, Tetris};
+
+const EPSILON: f64 = 1e-9; // Absorbs rounding from summing frame times
+
+/// Inputs pressed before the piece spawned.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct Buffered {
+    pub(crate) rotation: Option<Action>,
+    pub(crate) hold: bool,
+}
+
+impl Tetris {
+    /// Whether play is held up by the countdown or an entry delay. If so,
+    /// `action` is dropped, or buffered for the spawn when it is a rotation
+    /// or hold and no piece is out yet.
+    pub(crate) fn delay_input(&mut self, action: Action) -> bool {
+        if self.countdown <= 0.0 && self.entry_delay.is_none() {
+            return false;
+        }
+        if self.current_piece.is_none() && !self.paused && !self.game_over {
+            match action {
+                Action::Hold => self.buffered.hold = true,
+                Action::RotateLeft | Action::RotateRight | Action::Rotate180 => self.buffered.rotation = Some(action),
+                _ => {}
+            }
+        }
+        true
+    }
+
+    /// Brings out the next piece, applying any buffered hold, then rotation.
+    pub(crate) fn spawn_next(&mut self) {
+        self.current_piece = Some(self.next_piece.clone());
+        self.next_piece = self.random_piece();
+        self.spawn_score = self.score;
+        self.reset_lock();
+        self.on_hint_spawn();
+        let buffered = std::mem::take(&mut self.buffered);
+        if buffered.hold {
+            self.hold();
+        }
+        if let Some(rotation) = buffered.rotation {
+            self.apply_action(rotation);
+        }
+        self.check_spawn();
+    }
+
+    /// Spawns the next piece after `delay` seconds, or at once.
+    pub(crate) fn spawn_after(&mut self, delay: f64) {
+        if delay > 0.0 {
+            self.current_piece = None;
+            self.entry_delay = Some(delay);
+        } else {
+            self.spawn_next();
+        }
+    }
+
+    /// Counts down the countdown, then the entry delay once any clear has
+    /// finished.
+    pub(crate) fn update_entry(&mut self, delta_time: f64) {
+        if self.paused || self.game_over {
+            return;
+        }
+        if self.countdown > 0.0 {
+            self.countdown -= delta_time;
+            if self.countdown > EPSILON {
+                return;
+            }
+            self.countdown = 0.0;
+            if self.current_piece.is_none() && self.entry_delay.is_none() {
+                self.spawn_next();
+            }
+            return;
+        }
+        if !self.clearing_lines.is_empty() {
+            return;
+        }
+        if let Some(left) = self.entry_delay {
+            if left - delta_time > EPSILON {
+                self.entry_delay = Some(left - delta_time);
+            } else {
+                self.entry_delay = None;
+                self.spawn_next();
+            }
+        }
+    }
+
+    /// Whether a game is on: a piece is out or on its way, counting the
+    /// countdown before the first.
+    pub(crate) fn in_play(&self) -> bool {
+        self.current_piece.is_some() || self.entry_delay.is_some() || self.countdown > 0.0
+    }
+
+    /// Restarts the countdown, if one is set.
+    pub(crate) fn start_countdown(&mut self) {
+        self.countdown = self.settings.countdown;
+    }
+
+    /// "READY" for the first half of the countdown, then "GO".
+    pub(crate) fn countdown_label(&self) -> Option<&'static str> {
+        if self.countdown <= 0.0 {
+            None
+        } else if self.countdown > self.settings.countdown / 2.0 {
+            Some("READY")
+        } else {
+            Some("GO")
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl Tetris {
+    /// Seconds left of the READY / GO countdown; 0 once play is on.
+    pub fn get_countdown(&self) -> f64 {
+        self.countdown
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Settings;
+
+    fn game_with(countdown: f64, are: f64, line_are: f64) -> Tetris {
+        let mut game = Tetris::with_seed(0);
+        let mut settings = Settings::default();
+        settings.set_countdown(countdown);
+        settings.set_are(are);
+        settings.set_line_are(line_are);
+        game.set_settings(settings);
+        game
+    }
+
+    #[test]
+    fn test_countdown() {
+        let mut game = game_with(2.0, 0.0, 0.0);
+        game.start();
+        assert!(game.current_piece.is_none());
+        assert_eq!(game.countdown_label(), Some("READY"));
+        for _ in 0..70 {
+            game.step();
+        }
+        assert_eq!(game.countdown_label(), Some("GO"));
+        game.drop(); // Ignored
+        for _ in 0..50 {
+            game.step();
+        }
+        assert!(game.current_piece.is_some());
+        assert_eq!(game.countdown_label(), None);
+        assert_eq!(game.stats.pieces_placed, 0);
+
+        // Unpausing counts down again before the piece moves.
+        game.pause();
+        game.unpause();
+        let y = game.current_piece.as_ref().unwrap().y;
+        game.move_down();
+        assert_eq!(game.current_piece.as_ref().unwrap().y, y);
+        assert!(game.get_countdown() > 0.0);
+    }
+
+    #[test]
+    fn test_entry_delay_and_buffering() {
+        let mut game = game_with(0.0, 0.5, 1.0);
+        game.start();
+        let next = game.next_piece.kind;
+        game.drop();
+        assert!(game.current_piece.is_none());
+        game.rotate_right();
+        game.hold();
+        for _ in 0..29 {
+            game.step();
+        }
+        assert!(game.current_piece.is_none());
+        game.step();
+
+        // The next piece went to the hold, and its replacement spawned turned.
+        let piece = game.current_piece.as_ref().unwrap();
+        assert_eq!(game.hold_piece.as_ref().unwrap().kind, next);
+        assert_eq!(piece.rotation, 1);
+        assert_eq!(game.buffered, Buffered::default());
+    }
+}
@@ -0,0 +1,71 @@
+//! Things that happened in the engine since they were last collected, for
+//! feedback such as sound. Only the latest `MAX_EVENTS` are kept, so a
+//! game nobody listens to doesn't grow.
+
+use crate::Tetris;
+
+const MAX_EVENTS: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum GameEvent {
+    Move,
+    Rotate,
+    SoftDrop,
+    HardDrop,
+    Lock,
+    Hold,
+    LineClear(u32), // Lines cleared without a T-spin
+    TSpin(u32),     // Lines cleared by a T-spin
+    LevelUp,
+    GameOver,
+}
+
+impl Tetris {
+    pub(crate) fn push_event(&mut self, event: GameEvent) {
+        if self.events.len() == MAX_EVENTS {
+            self.events.remove(0);
+        }
+        self.events.push(event);
+    }
+
+    /// Hands out the events so far, oldest first.
+    #[cfg_attr(not(feature = "audio"), allow(dead_code))]
+    pub(crate) fn take_events(&mut self) -> Vec<GameEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Ends the game, once.
+    pub(crate) fn end_game(&mut self) {
+        if !self.game_over {
+            self.game_over = true;
+            self.push_event(GameEvent::GameOver);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{default_shapes, Action};
+
+    #[test]
+    fn test_events() {
+        let mut game = Tetris::with_seed(0);
+        game.start();
+        game.current_piece = Some(Tetris::create_piece(&default_shapes(), 2));
+        game.apply_action(Action::MoveLeft);
+        game.apply_action(Action::RotateRight);
+        game.apply_action(Action::HardDrop);
+        use GameEvent::*;
+        assert_eq!(game.take_events(), vec![Move, Rotate, HardDrop, Lock]);
+        assert!(game.take_events().is_empty());
+
+        for _ in 0..MAX_EVENTS + 5 {
+            game.push_event(Move);
+        }
+        game.push_event(Hold);
+        let events = game.take_events();
+        assert_eq!(events.len(), MAX_EVENTS);
+        assert_eq!(events.last(), Some(&Hold));
+    }
+}
@@ -0,0 +1,293 @@
+//! Finesse tracking: compares the moves and rotations a player used for each
+//! piece with the fewest inputs that reach the same placement from spawn.
+
+use wasm_bindgen::prelude::*;
+use web_sys::CanvasRenderingContext2d;
+
+use std::collections::{HashSet, VecDeque};
+
+use crate::movegen::cells;
+use crate::rotation::Kick180;
+use crate::rotation_system::{turn, Rotation};
+use crate::{collides, shape_collides, Action, Piece, Tetris};
+
+const FLASH_DURATION: f64 = 0.3;
+
+/// What happens when a piece is placed with more inputs than needed.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FinesseTraining {
+    /// Faults are only counted.
+    Off,
+    /// The placement is undone and the piece starts again from the top.
+    Restart,
+    /// The board flashes red.
+    Flash,
+}
+
+/// One placement that took more inputs than the finesse solution.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FinesseFault {
+    pub piece: u32, // Index of the piece in the game
+    pub kind: usize,
+    pub used: u32,
+    pub optimal: u32,
+}
+
+#[wasm_bindgen]
+impl Tetris {
+    pub fn get_finesse_faults(&self) -> u32 {
+        self.finesse_faults.len() as u32
+    }
+
+    pub fn set_finesse_training(&mut self, mode: FinesseTraining) {
+        self.finesse_training = mode;
+    }
+
+    pub fn get_finesse_training(&self) -> FinesseTraining {
+        self.finesse_training
+    }
+
+    pub fn is_finesse_flashing(&self) -> bool {
+        self.finesse_flash > 0.0
+    }
+}
+
+impl Tetris {
+    pub fn finesse_log(&self) -> &[FinesseFault] {
+        &self.finesse_faults
+    }
+
+    pub(crate) fn record_input(&mut self, action: Action) {
+        if self.current_piece.is_some() {
+            self.piece_inputs.push(action);
+            self.stats.keys_pressed += 1;
+        }
+    }
+
+    /// Judges the current piece just before it locks. Returns true if the
+    /// training mode sent the piece back to the top instead.
+    pub(crate) fn check_finesse(&mut self) -> bool {
+        let inputs = std::mem::take(&mut self.piece_inputs);
+        let piece = match self.current_piece {
+            Some(ref piece) => piece.clone(),
+            None => return false,
+        };
+        let used = inputs
+            .iter()
+            .filter(|action| {
+                matches!(
+                    action,
+                    Action::MoveLeft | Action::MoveRight | Action::RotateLeft | Action::RotateRight | Action::Rotate180
+                )
+            })
+            .count() as u32;
+        let optimal = match minimal_inputs(&self.board, &piece, self.rules.rotation, self.rules.kick_180) {
+            Some(optimal) => optimal,
+            None => return false,
+        };
+        if used <= optimal {
+            return false;
+        }
+        self.finesse_faults.push(FinesseFault {
+            piece: self.stats.pieces_placed,
+            kind: piece.kind,
+            used,
+            optimal,
+        });
+        self.stats.finesse_faults += 1;
+        match self.finesse_training {
+            FinesseTraining::Off => false,
+            FinesseTraining::Flash => {
+                self.finesse_flash = FLASH_DURATION;
+                false
+            }
+            FinesseTraining::Restart => {
+                // Soft drop points from the failed attempt don't count.
+                self.score = self.spawn_score;
+                self.current_piece = Some(self.new_piece(piece.kind));
+                self.gravity_time = 0.0;
+                self.reset_lock();
+                true
+            }
+        }
+    }
+
+    pub(crate) fn update_finesse_flash(&mut self, delta_time: f64) {
+        self.finesse_flash = (self.finesse_flash - delta_time).max(0.0);
+    }
+
+    /// Opacity of the red fault flash, 0 when not flashing.
+    pub(crate) fn finesse_flash_alpha(&self) -> f64 {
+        0.4 * self.finesse_flash / FLASH_DURATION
+    }
+
+    #[allow(deprecated)]
+    pub(crate) fn draw_finesse_flash(&self, ctx: &CanvasRenderingContext2d, width: f64, height: f64) {
+        let alpha = self.finesse_flash_alpha();
+        if alpha <= 0.0 {
+            return;
+        }
+        ctx.set_fill_style(&JsValue::from_str(&format!("rgba(255, 0, 0, {})", alpha)));
+        ctx.fill_rect(0.0, 0.0, width, height);
+    }
+}
+
+/// Fewest key presses, moves and rotations, that bring a freshly spawned
+/// piece of the same kind above `target` so that a hard drop lands exactly
+/// on it, turning by `rotation` and half turning by `kick_180`. A held shift
+/// slides to the wall on one press, as DAS does. `None` if the target cannot
+/// be reached that way.
+pub(crate) fn minimal_inputs(board: &[Vec<u8>], target: &Piece, rotation: Rotation, kick_180: Kick180) -> Option<u32> {
+    let (width, height) = (board[0].len(), board.len());
+    let covered = cells(&target.shape, target.x, target.y);
+    let lands = |piece: &Piece| {
+        let mut y = piece.y;
+        while !shape_collides(&piece.shape, piece.x, y + 1, board, width, height) {
+            y += 1;
+        }
+        cells(&piece.shape, piece.x, y) == covered
+    };
+    let system = rotation.system();
+    let spawn = system.spawn(target.kind, width);
+    if collides(&spawn, board, width, height) {
+        return None;
+    }
+    let mut seen = HashSet::from([(spawn.x, spawn.y, spawn.rotation)]);
+    let mut queue = VecDeque::from([(spawn, 0)]);
+    // Breadth first, so the first state that lands on the target took the
+    // fewest presses.
+    while let Some((piece, presses)) = queue.pop_front() {
+        if lands(&piece) {
+            return Some(presses);
+        }
+        let mut next = Vec::new();
+        for direction in [-1, 1] {
+            let shifted = |piece: &Piece| Piece { x: piece.x + direction, ..piece.clone() };
+            let mut slid = piece.clone();
+            while !collides(&shifted(&slid), board, width, height) {
+                slid = shifted(&slid);
+            }
+            if slid.x != piece.x {
+                next.push(shifted(&piece)); // Tap
+                next.push(slid); // Hold to the wall
+            }
+            next.extend(turn(system, kick_180, &piece, direction, board));
+        }
+        next.extend(turn(system, kick_180, &piece, 2, board));
+        for piece in next {
+            if seen.insert((piece.x, piece.y, piece.rotation)) {
+                queue.push_back((piece, presses + 1));
+            }
+        }
+    }
+    None // Tucks and spins have no hard-drop finesse
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::{RulePreset, RuleSet};
+    use crate::{default_shapes, rotate};
+
+    fn game_with_t() -> Tetris {
+        let mut game = Tetris::with_seed(0);
+        game.start();
+        game.current_piece = Some(Tetris::create_piece(&default_shapes(), 2));
+        game
+    }
+
+    #[test]
+    fn test_minimal_inputs() {
+        let game = game_with_t();
+        let mut target = Tetris::create_piece(&default_shapes(), 2);
+        target.x = 0;
+        target.y = 18;
+        // Spawns at x = 3: one press slides it to the wall.
+        assert_eq!(minimal_inputs(&game.board, &target, Rotation::Classic, Kick180::SrsPlus), Some(1));
+        target.x = 1;
+        assert_eq!(minimal_inputs(&game.board, &target, Rotation::Classic, Kick180::SrsPlus), Some(2));
+        target.x = 6; // Three taps right, or slide right and tap back
+        assert_eq!(minimal_inputs(&game.board, &target, Rotation::Classic, Kick180::SrsPlus), Some(2));
+        target.x = 0;
+        target.shape = rotate(&target.shape, 1);
+        target.y = 17;
+        assert_eq!(minimal_inputs(&game.board, &target, Rotation::Classic, Kick180::SrsPlus), Some(2));
+        // Flat side up is one half turn away.
+        target.shape = rotate(&target.shape, 1);
+        target.x = 3;
+        target.y = 18;
+        assert_eq!(minimal_inputs(&game.board, &target, Rotation::Classic, Kick180::SrsPlus), Some(1));
+    }
+
+    #[test]
+    fn test_das_to_the_wall_is_finesse() {
+        let mut game = game_with_t();
+        game.press(Action::MoveLeft);
+        game.update(0.5);
+        game.release(Action::MoveLeft);
+        assert_eq!(game.current_piece.as_ref().unwrap().x, 0);
+        game.drop();
+        assert_eq!(game.get_finesse_faults(), 0);
+
+        // Tapping all the way over is a fault.
+        game.current_piece = Some(Tetris::create_piece(&default_shapes(), 2));
+        for _ in 0..3 {
+            game.move_left();
+        }
+        game.drop();
+        assert_eq!(game.finesse_log()[0].used, 3);
+        assert_eq!(game.finesse_log()[0].optimal, 1);
+    }
+
+    #[test]
+    fn test_counts_fault() {
+        let mut game = game_with_t();
+        game.move_left();
+        game.move_right();
+        game.move_right();
+        game.drop();
+        assert_eq!(game.get_finesse_faults(), 1);
+        assert_eq!(game.finesse_log()[0].used, 3);
+        assert_eq!(game.finesse_log()[0].optimal, 1);
+
+        game.current_piece = Some(Tetris::create_piece(&default_shapes(), 2));
+        game.move_right();
+        game.drop();
+        assert_eq!(game.get_finesse_faults(), 1);
+    }
+
+    #[test]
+    fn test_restart_training() {
+        let mut game = game_with_t();
+        game.set_finesse_training(FinesseTraining::Restart);
+        game.rotate_right();
+        game.rotate_right();
+        game.rotate_right();
+        game.rotate_right();
+        game.drop();
+        assert_eq!(game.get_finesse_faults(), 1);
+        // Nothing was placed; the T is back at the top.
+        assert!(game.board.iter().all(|row| row.iter().all(|&cell| cell == 0)));
+        let piece = game.current_piece.as_ref().unwrap();
+        assert_eq!((piece.kind, piece.y), (2, 0));
+    }
+
+    #[test]
+    fn test_restart_earns_nothing() {
+        let mut game = Tetris::with_seed(0);
+        game.set_rules(&RuleSet::preset(RulePreset::Guideline));
+        game.start();
+        game.current_piece = Some(game.new_piece(2));
+        game.set_finesse_training(FinesseTraining::Restart);
+        game.rotate_right();
+        game.rotate_right();
+        game.rotate_right();
+        game.rotate_right();
+        game.soft_drop();
+        game.soft_drop();
+        game.drop();
+        assert_eq!(game.get_finesse_faults(), 1);
+        assert_eq!(game.get_score(), 0);
+    }
+}
@@ -0,0 +1,196 @@
+//! Controllers through the Web Gamepad API. Pads are polled once per frame;
+//! pressed buttons and tilted stick axes become codes such as
+//! `GamepadButton0` or `GamepadAxis0-` that go through the same `KeyMap` as
+//! keyboard codes, so bindings, capture and DAS/ARR all work the same way.
+
+use std::collections::BTreeMap;
+
+use wasm_bindgen::JsCast;
+
+use crate::input::{Input, KeyMap};
+
+/// One pad's buttons and axes at the time of a poll.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PadState {
+    pub index: u32,
+    pub buttons: Vec<bool>,
+    pub axes: Vec<f64>, // -1.0 to 1.0
+}
+
+impl PadState {
+    /// Codes for everything held down, with stick axes counted only past
+    /// `deadzone`.
+    pub fn active_codes(&self, deadzone: f64) -> Vec<String> {
+        let mut codes = Vec::new();
+        for (i, &pressed) in self.buttons.iter().enumerate() {
+            if pressed {
+                codes.push(format!("GamepadButton{}", i));
+            }
+        }
+        for (i, &value) in self.axes.iter().enumerate() {
+            if value <= -deadzone {
+                codes.push(format!("GamepadAxis{}-", i));
+            } else if value >= deadzone {
+                codes.push(format!("GamepadAxis{}+", i));
+            }
+        }
+        codes
+    }
+}
+
+/// Where pad states come from: the browser, or canned states in tests.
+pub trait GamepadSource {
+    /// Every connected pad.
+    fn poll(&mut self) -> Vec<PadState>;
+}
+
+/// `navigator.getGamepads()`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WebGamepads;
+
+impl GamepadSource for WebGamepads {
+    fn poll(&mut self) -> Vec<PadState> {
+        let pads = match web_sys::window().and_then(|win| win.navigator().get_gamepads().ok()) {
+            Some(pads) => pads,
+            None => return Vec::new(),
+        };
+        let mut states = Vec::new();
+        for pad in pads.iter() {
+            // Empty slots are null.
+            let pad = match pad.dyn_into::<web_sys::Gamepad>() {
+                Ok(pad) if pad.connected() => pad,
+                _ => continue,
+            };
+            let buttons = pad
+                .buttons()
+                .iter()
+                .map(|button| button.dyn_into::<web_sys::GamepadButton>().map(|b| b.pressed()).unwrap_or(false))
+                .collect();
+            let axes = pad.axes().iter().map(|axis| axis.as_f64().unwrap_or(0.0)).collect();
+            states.push(PadState {
+                index: pad.index(),
+                buttons,
+                axes,
+            });
+        }
+        states
+    }
+}
+
+/// Turns successive polls into presses and releases.
+#[derive(Clone, Debug, Default)]
+pub struct Gamepads<S: GamepadSource> {
+    source: S,
+    active: BTreeMap<u32, Vec<String>>, // Codes held on each pad at the last poll
+}
+
+impl<S: GamepadSource> Gamepads<S> {
+    pub fn new(source: S) -> Gamepads<S> {
+        Gamepads {
+            source,
+            active: BTreeMap::new(),
+        }
+    }
+
+    pub fn connected(&self) -> usize {
+        self.active.len()
+    }
+
+    /// Polls the pads and reports what changed since the last poll. A pad that
+    /// disconnected lets go of everything it held.
+    pub fn poll(&mut self, keys: &mut KeyMap, deadzone: f64) -> Vec<Input> {
+        let mut current: BTreeMap<u32, Vec<String>> = BTreeMap::new();
+        for pad in self.source.poll() {
+            current.insert(pad.index, pad.active_codes(deadzone));
+        }
+
+        let mut inputs = Vec::new();
+        // Presses first, so sliding from the D-pad to the stick (two codes for
+        // one action) never lets go of the action in between.
+        for (index, codes) in &current {
+            let before = self.active.get(index);
+            for code in codes {
+                if !before.is_some_and(|before| before.contains(code)) {
+                    inputs.extend(keys.key_down(code).0);
+                }
+            }
+        }
+        for (index, codes) in &self.active {
+            let now = current.get(index);
+            for code in codes {
+                if !now.is_some_and(|now| now.contains(code)) {
+                    inputs.extend(keys.key_up(code));
+                }
+            }
+        }
+        self.active = current;
+        inputs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Action, Settings};
+
+    #[derive(Default)]
+    struct FakePads {
+        states: Vec<PadState>,
+    }
+
+    impl GamepadSource for FakePads {
+        fn poll(&mut self) -> Vec<PadState> {
+            self.states.clone()
+        }
+    }
+
+    fn pad(buttons: &[usize], axes: Vec<f64>) -> PadState {
+        let mut state = PadState {
+            index: 0,
+            buttons: vec![false; 16],
+            axes,
+        };
+        for &button in buttons {
+            state.buttons[button] = true;
+        }
+        state
+    }
+
+    #[test]
+    fn test_buttons_and_stick() {
+        let mut keys = KeyMap::new(Settings::default());
+        let mut pads = Gamepads::new(FakePads::default());
+
+        pads.source.states = vec![pad(&[14], vec![0.3, 0.0])];
+        assert_eq!(pads.poll(&mut keys, 0.5), vec![Input::Press(Action::MoveLeft)]);
+        // Held: nothing new.
+        assert_eq!(pads.poll(&mut keys, 0.5), vec![]);
+
+        // D-pad released while the stick is pushed left keeps the move held.
+        pads.source.states = vec![pad(&[], vec![-0.8, 0.0])];
+        assert_eq!(pads.poll(&mut keys, 0.5), vec![]);
+
+        pads.source.states = vec![pad(&[0], vec![0.9, 0.0])];
+        assert_eq!(
+            pads.poll(&mut keys, 0.5),
+            vec![
+                Input::Press(Action::RotateRight),
+                Input::Press(Action::MoveRight),
+                Input::Release(Action::MoveLeft),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_disconnect_releases() {
+        let mut keys = KeyMap::new(Settings::default());
+        let mut pads = Gamepads::new(FakePads::default());
+        pads.source.states = vec![pad(&[13], vec![])];
+        assert_eq!(pads.poll(&mut keys, 0.5), vec![Input::Press(Action::SoftDrop)]);
+        assert_eq!(pads.connected(), 1);
+
+        pads.source.states.clear();
+        assert_eq!(pads.poll(&mut keys, 0.5), vec![Input::Release(Action::SoftDrop)]);
+        assert_eq!(pads.connected(), 0);
+    }
+}
@@ -0,0 +1,173 @@
+//! Held inputs: delayed auto shift (DAS), auto repeat rate (ARR) and soft
+//! drop speed, driven by `press`/`release` and timed in `update`.
+
+use wasm_bindgen::prelude::*;
+
+use crate::{Action, Tetris, FRAME_TIME};
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Handling {
+    left: bool,
+    right: bool,
+    direction: i32, // -1, 0 or 1: the most recently pressed held direction
+    charge: f64,    // Milliseconds the direction has been held
+    repeats: u32,   // Auto-repeat shifts done since the DAS charged
+    soft_drop: bool,
+    soft_drop_time: f64, // Milliseconds since the last soft drop row
+}
+
+#[wasm_bindgen]
+impl Tetris {
+    /// A key for `action` went down. Moves and soft drop act at once and keep
+    /// repeating while held, using the DAS, ARR and SDF settings.
+    pub fn press(&mut self, action: Action) {
+        match action {
+            Action::MoveLeft => {
+                self.handling.left = true;
+                self.start_shift(-1);
+            }
+            Action::MoveRight => {
+                self.handling.right = true;
+                self.start_shift(1);
+            }
+            Action::SoftDrop => {
+                self.handling.soft_drop = true;
+                self.handling.soft_drop_time = 0.0;
+                if !self.paused && self.can_fall() {
+                    self.apply_action(Action::SoftDrop);
+                }
+            }
+            _ => self.apply_action(action),
+        }
+    }
+
+    /// A key for `action` went up. Releasing one direction while the other is
+    /// still held switches to it.
+    pub fn release(&mut self, action: Action) {
+        match action {
+            Action::MoveLeft => {
+                self.handling.left = false;
+                if self.handling.direction == -1 {
+                    self.handling.direction = if self.handling.right { 1 } else { 0 };
+                    self.reset_charge();
+                }
+            }
+            Action::MoveRight => {
+                self.handling.right = false;
+                if self.handling.direction == 1 {
+                    self.handling.direction = if self.handling.left { -1 } else { 0 };
+                    self.reset_charge();
+                }
+            }
+            Action::SoftDrop => self.handling.soft_drop = false,
+            _ => {}
+        }
+    }
+}
+
+impl Tetris {
+    fn start_shift(&mut self, direction: i32) {
+        self.handling.direction = direction;
+        self.reset_charge();
+        self.apply_action(if direction < 0 { Action::MoveLeft } else { Action::MoveRight });
+    }
+
+    fn reset_charge(&mut self) {
+        self.handling.charge = 0.0;
+        self.handling.repeats = 0;
+    }
+
+    pub(crate) fn update_handling(&mut self, delta_time: f64) {
+        if self.paused || self.game_over || self.current_piece.is_none() || self.countdown > 0.0 || !self.clearing_lines.is_empty() {
+            return;
+        }
+        let elapsed = delta_time * 1000.0;
+
+        if self.handling.direction != 0 {
+            self.handling.charge += elapsed;
+            let (das, arr) = (self.settings.das, self.settings.arr);
+            if self.handling.charge >= das {
+                let due = if arr == 0.0 {
+                    u32::MAX
+                } else {
+                    ((self.handling.charge - das) / arr) as u32 + 1
+                };
+                while self.handling.repeats < due {
+                    if !self.shift(self.handling.direction) {
+                        self.handling.repeats = due; // Against a wall; nothing more to do
+                        break;
+                    }
+                    self.handling.repeats += 1;
+                }
+            }
+        }
+
+        if self.handling.soft_drop {
+            self.handling.soft_drop_time += elapsed;
+            let (frames, rows) = self.gravity();
+            let interval = frames as f64 * FRAME_TIME * 1000.0 / (rows as f64 * self.settings.sdf);
+            while self.handling.soft_drop_time >= interval {
+                self.handling.soft_drop_time -= interval;
+                // Held soft drop never locks the piece; gravity does.
+                if !self.can_fall() {
+                    self.handling.soft_drop_time = 0.0;
+                    break;
+                }
+                self.soft_drop();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{default_shapes, Settings};
+
+    fn game_with_settings(das: f64, arr: f64) -> Tetris {
+        let mut game = Tetris::with_seed(0);
+        let mut settings = Settings::default();
+        settings.set_das(das);
+        settings.set_arr(arr);
+        game.set_settings(settings);
+        game.start();
+        game.current_piece = Some(Tetris::create_piece(&default_shapes(), 2)); // T at x = 3
+        game
+    }
+
+    fn piece_x(game: &Tetris) -> i32 {
+        game.current_piece.as_ref().unwrap().x
+    }
+
+    #[test]
+    fn test_das_and_arr() {
+        let mut game = game_with_settings(100.0, 20.0);
+        game.press(Action::MoveRight);
+        assert_eq!(piece_x(&game), 4);
+        game.update(0.09);
+        assert_eq!(piece_x(&game), 4);
+        game.update(0.03); // 120 ms: charged at 100, one repeat at 120
+        assert_eq!(piece_x(&game), 6);
+        game.release(Action::MoveRight);
+        game.update(1.0);
+        assert_eq!(piece_x(&game), 6);
+        // One key press, however many cells it moved.
+        assert_eq!(game.get_stats().get_keys_pressed(), 1);
+    }
+
+    #[test]
+    fn test_instant_arr_and_soft_drop() {
+        let mut game = game_with_settings(0.0, 0.0);
+        game.press(Action::MoveLeft);
+        game.update(FRAME_TIME);
+        assert_eq!(piece_x(&game), 0);
+
+        game.press(Action::SoftDrop);
+        for _ in 0..55 {
+            game.update(FRAME_TIME); // Lands before gravity's first pull
+        }
+        let piece = game.current_piece.as_ref().unwrap();
+        assert_eq!(piece.y, 18); // Resting on the floor, not locked
+        assert_eq!(game.get_stats().get_pieces_placed(), 0);
+    }
+}
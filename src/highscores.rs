@@ -0,0 +1,329 @@
+//! Top-N leaderboards and personal bests per game mode, persisted as one
+//! versioned JSON document.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use wasm_bindgen::prelude::*;
+
+use crate::storage::{log, LocalStorage, Storage};
+use crate::Tetris;
+
+pub const STORAGE_KEY: &str = "tetris-wasm.highscores";
+/// Where unreadable stored high scores are kept before starting afresh.
+pub const BACKUP_KEY: &str = "tetris-wasm.highscores.corrupt";
+/// Bump when the stored layout changes and add a step to `migrate`.
+pub const SCHEMA_VERSION: u32 = 1;
+pub const MAX_ENTRIES: usize = 10;
+
+/// How a mode's leaderboard is ordered.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ranking {
+    /// Highest score first (marathon-style modes).
+    Score,
+    /// Fastest completed run first (sprint-style modes such as 40L).
+    Time,
+}
+
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ScoreEntry {
+    pub name: String,
+    pub score: u32,
+    pub lines: u32,
+    pub time: f64,      // Seconds of play
+    pub date: f64,      // Milliseconds since the Unix epoch
+    pub completed: bool, // Whether the mode's goal was reached
+    pub seed: Option<u64>,
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PersonalBest {
+    pub score: u32,
+    pub lines: u32,
+    pub fastest_time: Option<f64>, // Only completed runs count
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+struct ModeRecords {
+    entries: Vec<ScoreEntry>,
+    best: PersonalBest,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct Document {
+    version: u32,
+    modes: BTreeMap<String, ModeRecords>,
+}
+
+pub struct HighScores<S: Storage> {
+    storage: S,
+    modes: BTreeMap<String, ModeRecords>,
+    // Set when the stored data was written by a newer version, so saving
+    // would destroy it.
+    read_only: bool,
+}
+
+// Why stored high scores could not be loaded.
+enum LoadError {
+    Newer(u32),
+    Corrupt(String),
+}
+
+impl<S: Storage> HighScores<S> {
+    /// Reads the stored high scores. Data from a newer version is left
+    /// alone and nothing is saved over it; unreadable data is copied to
+    /// `BACKUP_KEY` and the table starts empty.
+    pub fn load(mut storage: S) -> HighScores<S> {
+        let mut read_only = false;
+        let modes = match storage.get(STORAGE_KEY) {
+            Some(text) => match serde_json::from_str::<Value>(&text)
+                .map_err(|err| LoadError::Corrupt(err.to_string()))
+                .and_then(migrate)
+            {
+                Ok(document) => document.modes,
+                Err(LoadError::Newer(version)) => {
+                    read_only = true;
+                    log(&format!("Not saving high scores: schema version {} is newer than {}", version, SCHEMA_VERSION));
+                    BTreeMap::new()
+                }
+                Err(LoadError::Corrupt(err)) => {
+                    log(&format!("Discarding unreadable high scores, backed up as '{}': {}", BACKUP_KEY, err));
+                    if let Err(err) = storage.set(BACKUP_KEY, &text) {
+                        log(&err);
+                    }
+                    BTreeMap::new()
+                }
+            },
+            None => BTreeMap::new(),
+        };
+        HighScores {
+            storage,
+            modes,
+            read_only,
+        }
+    }
+
+    /// Records a finished game. Returns its place on the mode's leaderboard,
+    /// or `None` if it did not make the top entries.
+    pub fn submit(&mut self, mode: &str, ranking: Ranking, entry: ScoreEntry) -> Option<usize> {
+        let records = self.modes.entry(mode.to_string()).or_default();
+        records.best.score = records.best.score.max(entry.score);
+        records.best.lines = records.best.lines.max(entry.lines);
+        if entry.completed && records.best.fastest_time.is_none_or(|time| entry.time < time) {
+            records.best.fastest_time = Some(entry.time);
+        }
+
+        let rank = match ranking {
+            Ranking::Score => records.entries.iter().position(|other| entry.score > other.score),
+            Ranking::Time if entry.completed => records
+                .entries
+                .iter()
+                .position(|other| !other.completed || entry.time < other.time),
+            Ranking::Time => None,
+        };
+        let rank = rank.unwrap_or(records.entries.len());
+        let placed = if rank < MAX_ENTRIES && (ranking == Ranking::Score || entry.completed) {
+            records.entries.insert(rank, entry);
+            records.entries.truncate(MAX_ENTRIES);
+            Some(rank)
+        } else {
+            None
+        };
+        self.save();
+        placed
+    }
+
+    pub fn entries(&self, mode: &str) -> &[ScoreEntry] {
+        self.modes.get(mode).map(|records| records.entries.as_slice()).unwrap_or(&[])
+    }
+
+    pub fn personal_best(&self, mode: &str) -> PersonalBest {
+        self.modes.get(mode).map(|records| records.best).unwrap_or_default()
+    }
+
+    pub fn modes(&self) -> impl Iterator<Item = &str> {
+        self.modes.keys().map(|mode| mode.as_str())
+    }
+
+    pub fn clear(&mut self, mode: &str) {
+        self.modes.remove(mode);
+        self.save();
+    }
+
+    fn save(&mut self) {
+        if self.read_only {
+            return;
+        }
+        let document = Document {
+            version: SCHEMA_VERSION,
+            modes: self.modes.clone(),
+        };
+        let text = serde_json::to_string(&document).expect("high scores always serialize");
+        if let Err(err) = self.storage.set(STORAGE_KEY, &text) {
+            log(&err);
+        }
+    }
+}
+
+// Brings a stored document of any known version up to `SCHEMA_VERSION`.
+// Version 1 is the first layout ever stored, so there is nothing to convert yet.
+fn migrate(value: Value) -> Result<Document, LoadError> {
+    let version = match value.get("version").and_then(Value::as_u64) {
+        Some(version) => version as u32,
+        None => return Err(LoadError::Corrupt("no schema version".to_string())),
+    };
+    if version > SCHEMA_VERSION {
+        return Err(LoadError::Newer(version));
+    }
+    serde_json::from_value(value).map_err(|err| LoadError::Corrupt(err.to_string()))
+}
+
+fn now() -> f64 {
+    #[cfg(target_arch = "wasm32")]
+    {
+        js_sys::Date::now()
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as f64)
+            .unwrap_or(0.0)
+    }
+}
+
+/// Leaderboards backed by `window.localStorage`.
+#[wasm_bindgen]
+pub struct HighScoreTable {
+    scores: HighScores<LocalStorage>,
+}
+
+#[wasm_bindgen]
+impl HighScoreTable {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> HighScoreTable {
+        HighScoreTable {
+            scores: HighScores::load(LocalStorage::new()),
+        }
+    }
+
+    /// Records `game` under `mode`. `completed` says whether the mode's goal
+    /// (e.g. 40 lines) was reached. Returns the leaderboard place, or -1.
+    pub fn submit(&mut self, mode: &str, ranking: Ranking, name: &str, game: &Tetris, completed: bool) -> i32 {
+        let entry = entry_for(name, game, completed);
+        match self.scores.submit(mode, ranking, entry) {
+            Some(rank) => rank as i32,
+            None => -1,
+        }
+    }
+
+    /// Modes that have any records.
+    pub fn modes(&self) -> Vec<String> {
+        self.scores.modes().map(String::from).collect()
+    }
+
+    pub fn count(&self, mode: &str) -> usize {
+        self.scores.entries(mode).len()
+    }
+
+    pub fn entry(&self, mode: &str, index: usize) -> Option<ScoreEntry> {
+        self.scores.entries(mode).get(index).cloned()
+    }
+
+    pub fn personal_best(&self, mode: &str) -> PersonalBest {
+        self.scores.personal_best(mode)
+    }
+
+    pub fn clear(&mut self, mode: &str) {
+        self.scores.clear(mode);
+    }
+}
+
+impl Default for HighScoreTable {
+    fn default() -> HighScoreTable {
+        HighScoreTable::new()
+    }
+}
+
+pub fn entry_for(name: &str, game: &Tetris, completed: bool) -> ScoreEntry {
+    ScoreEntry {
+        name: name.to_string(),
+        score: game.score,
+        lines: game.cleared_lanes,
+        time: game.stats.elapsed,
+        date: now(),
+        completed,
+        seed: Some(game.seed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    fn entry(score: u32, time: f64, completed: bool) -> ScoreEntry {
+        ScoreEntry {
+            name: "p".to_string(),
+            score,
+            lines: 40,
+            time,
+            date: 0.0,
+            completed,
+            seed: None,
+        }
+    }
+
+    #[test]
+    fn test_score_ranking_and_persistence() {
+        let mut scores = HighScores::load(MemoryStorage::new());
+        assert_eq!(scores.submit("marathon", Ranking::Score, entry(100, 0.0, false)), Some(0));
+        assert_eq!(scores.submit("marathon", Ranking::Score, entry(300, 0.0, false)), Some(0));
+        assert_eq!(scores.submit("marathon", Ranking::Score, entry(200, 0.0, false)), Some(1));
+        for _ in 0..MAX_ENTRIES {
+            scores.submit("marathon", Ranking::Score, entry(500, 0.0, false));
+        }
+        assert_eq!(scores.submit("marathon", Ranking::Score, entry(1, 0.0, false)), None);
+        assert_eq!(scores.entries("marathon").len(), MAX_ENTRIES);
+
+        let reloaded = HighScores::load(scores.storage.clone());
+        assert_eq!(reloaded.entries("marathon"), scores.entries("marathon"));
+        assert_eq!(reloaded.personal_best("marathon").score, 500);
+    }
+
+    #[test]
+    fn test_sprint_personal_best() {
+        let mut scores = HighScores::load(MemoryStorage::new());
+        assert_eq!(scores.submit("40l", Ranking::Time, entry(0, 30.0, false)), None);
+        assert_eq!(scores.submit("40l", Ranking::Time, entry(0, 90.0, true)), Some(0));
+        assert_eq!(scores.submit("40l", Ranking::Time, entry(0, 75.5, true)), Some(0));
+        assert_eq!(scores.personal_best("40l").fastest_time, Some(75.5));
+        assert_eq!(scores.personal_best("marathon"), PersonalBest::default());
+    }
+
+    #[test]
+    fn test_corrupt_and_newer_versions() {
+        for corrupt in ["{}", "[]", "not json", r#"{"version":1,"modes":[]}"#] {
+            let mut storage = MemoryStorage::new();
+            storage.set(STORAGE_KEY, corrupt).unwrap();
+            let mut scores = HighScores::load(storage);
+            assert_eq!(scores.modes().count(), 0);
+            assert_eq!(scores.storage.get(BACKUP_KEY).as_deref(), Some(corrupt));
+            // The table starts over and saves again.
+            scores.submit("marathon", Ranking::Score, entry(1, 0.0, false));
+            assert_eq!(HighScores::load(scores.storage.clone()).personal_best("marathon").score, 1);
+        }
+
+        let mut storage = MemoryStorage::new();
+        let future = r#"{"version":99,"modes":{}}"#;
+        storage.set(STORAGE_KEY, future).unwrap();
+        let mut scores = HighScores::load(storage);
+        scores.submit("marathon", Ranking::Score, entry(1, 0.0, false));
+        // Data from a newer version is left alone.
+        assert_eq!(scores.storage.get(STORAGE_KEY).as_deref(), Some(future));
+    }
+}
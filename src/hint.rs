@@ -0,0 +1,172 @@
+//! "Suggest best move" hints for new players, computed with the bot's
+//! placement evaluator.
+
+use wasm_bindgen::prelude::*;
+
+use crate::bot::{best_placement, Weights};
+use crate::rotation_system::RotationSystem;
+use crate::{Piece, Tetris};
+
+/// Recommended final position of the current piece. `rotation` counts
+/// clockwise quarter turns from the spawn orientation.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Hint {
+    pub x: i32,
+    pub y: i32,
+    pub rotation: u8,
+}
+
+#[wasm_bindgen]
+impl Tetris {
+    /// Best placement for the current piece, or `None` in ranked games or when
+    /// there is no piece in play. Each piece that gets a hint counts once in
+    /// the hint statistics.
+    pub fn get_hint(&mut self) -> Option<Hint> {
+        let hint = self.compute_hint()?;
+        self.count_hint();
+        Some(hint)
+    }
+
+    /// Shows the hinted placement as an outline in `draw()`.
+    pub fn set_show_hint(&mut self, show: bool) {
+        self.show_hint = show;
+        if show && !self.ranked {
+            self.count_hint();
+        }
+    }
+
+    /// Ranked games never give hints. Only takes effect before `start()`.
+    pub fn set_ranked(&mut self, ranked: bool) {
+        if !self.in_play() {
+            self.ranked = ranked;
+        }
+    }
+
+    pub fn is_ranked(&self) -> bool {
+        self.ranked
+    }
+
+    pub fn get_hints_used(&self) -> u32 {
+        self.stats.hints_used
+    }
+}
+
+impl Tetris {
+    // Searched once per piece and board, as `draw()` asks every frame.
+    fn compute_hint(&self) -> Option<Hint> {
+        if self.ranked {
+            return None;
+        }
+        if let Some(hint) = self.hint.get() {
+            return hint;
+        }
+        let piece = self.current_piece.as_ref()?;
+        let hint = best_placement(self, &self.new_piece(piece.kind), &Weights::default()).map(|best| Hint {
+            x: best.x,
+            y: best.y,
+            rotation: best.rotation,
+        });
+        self.hint.set(Some(hint));
+        hint
+    }
+
+    /// The piece or the board changed: the hint needs working out again.
+    pub(crate) fn forget_hint(&self) {
+        self.hint.set(None);
+    }
+
+    fn count_hint(&mut self) {
+        if self.current_piece.is_some() && !self.hint_counted {
+            self.hint_counted = true;
+            self.stats.hints_used += 1;
+        }
+    }
+
+    /// Called whenever a new piece enters play.
+    pub(crate) fn on_hint_spawn(&mut self) {
+        self.forget_hint();
+        self.hint_counted = false;
+        if self.show_hint && !self.ranked {
+            self.count_hint();
+        }
+    }
+
+    /// The hinted placement to outline, if hints are shown.
+    pub(crate) fn hint_outline(&self) -> Option<Piece> {
+        if !self.show_hint {
+            return None;
+        }
+        let hint = self.compute_hint()?;
+        Some(hint_piece(self.rules.rotation.system(), self.current_piece.as_ref()?.kind, hint))
+    }
+}
+
+fn hint_piece(system: &dyn RotationSystem, kind: usize, hint: Hint) -> Piece {
+    let mut piece = system.spawn(kind, 0);
+    piece.shape = system.shape(kind, hint.rotation);
+    piece.rotation = hint.rotation;
+    piece.x = hint.x;
+    piece.y = hint.y;
+    piece
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{default_shapes, Rotation};
+
+    #[test]
+    fn test_hint_fills_line() {
+        let mut game = Tetris::with_seed(0);
+        game.start();
+        game.current_piece = Some(Tetris::create_piece(&default_shapes(), 0));
+        game.board[19] = vec![1, 1, 1, 1, 1, 1, 0, 0, 0, 0];
+        let hint = game.get_hint().unwrap();
+        assert_eq!(hint, Hint { x: 6, y: 19, rotation: 0 });
+        let piece = hint_piece(Rotation::Classic.system(), 0, hint);
+        assert_eq!(piece.shape, vec![vec![1, 1, 1, 1]]);
+
+        // Asking again for the same piece is not counted twice.
+        game.get_hint();
+        assert_eq!(game.get_hints_used(), 1);
+    }
+
+    #[test]
+    fn test_hint_follows_the_piece() {
+        let mut game = Tetris::with_seed(0);
+        game.start();
+        game.set_show_hint(true);
+        let kind = |game: &Tetris| game.current_piece.as_ref().unwrap().kind;
+        assert_eq!(game.hint_outline().unwrap().kind, kind(&game));
+        game.hold();
+        assert_eq!(game.hint_outline().unwrap().kind, kind(&game));
+        game.drop();
+        let hint = game.hint_outline().unwrap();
+        assert_eq!(hint.kind, kind(&game));
+        // Worked out again for the board with the last piece on it.
+        let best = best_placement(&game, &game.new_piece(kind(&game)), &Weights::default()).unwrap();
+        assert_eq!((hint.x, hint.y, hint.rotation), (best.x, best.y, best.rotation));
+    }
+
+    #[test]
+    fn test_no_hints_when_ranked() {
+        let mut game = Tetris::with_seed(0);
+        game.set_ranked(true);
+        game.start();
+        assert_eq!(game.get_hint(), None);
+        game.set_show_hint(true);
+        assert_eq!(game.get_hints_used(), 0);
+    }
+
+    #[test]
+    fn test_ranked_is_fixed_once_started() {
+        let mut game = Tetris::with_seed(0);
+        let mut settings = crate::Settings::default();
+        settings.set_countdown(2.0);
+        game.set_settings(settings);
+        game.start();
+        game.set_ranked(true); // During the countdown
+        assert!(!game.is_ranked());
+    }
+}
@@ -0,0 +1,317 @@
+//! Keyboard and gamepad input. `Controls` listens for key events on a page
+//! element, polls gamepads, and turns both into engine actions through the
+//! rebindable table in `Settings`, so host pages don't map keys themselves.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{EventTarget, KeyboardEvent};
+
+use crate::gamepad::{Gamepads, WebGamepads};
+use crate::storage::log;
+use crate::{Action, Settings, Tetris};
+
+/// What a key event means to the game.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Input {
+    Press(Action),
+    Release(Action),
+    TogglePause,
+}
+
+/// The mapping from key codes to inputs, plus the keys currently held and an
+/// optional pending "press a key to bind" request.
+#[derive(Clone, Debug, Default)]
+pub struct KeyMap {
+    settings: Settings,
+    held: Vec<String>,
+    capture: Option<Action>,
+}
+
+impl KeyMap {
+    pub fn new(settings: Settings) -> KeyMap {
+        KeyMap {
+            settings,
+            ..KeyMap::default()
+        }
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    /// Replaces the bindings. Keys held under the old bindings are released.
+    pub fn set_settings(&mut self, settings: Settings) -> Vec<Input> {
+        let released = self.release_all();
+        self.settings = settings;
+        released
+    }
+
+    /// The next key pressed is bound to `action` instead of acting.
+    pub fn capture(&mut self, action: Action) {
+        self.capture = Some(action);
+    }
+
+    pub fn cancel_capture(&mut self) {
+        self.capture = None;
+    }
+
+    pub fn capturing(&self) -> Option<Action> {
+        self.capture
+    }
+
+    /// Handles a key going down. Returns the resulting input and whether the
+    /// key was claimed (so the browser's default, e.g. scrolling, should be
+    /// suppressed).
+    pub fn key_down(&mut self, code: &str) -> (Option<Input>, bool) {
+        if let Some(action) = self.capture.take() {
+            if code != "Escape" {
+                self.settings.add_key(action, code);
+            }
+            return (None, true);
+        }
+        if self.held.iter().any(|key| key == code) {
+            return (None, true); // Browser auto-repeat; DAS handles repeats
+        }
+        if self.settings.is_pause_key(code) {
+            self.held.push(code.to_string()); // So auto-repeat doesn't toggle again
+            return (Some(Input::TogglePause), true);
+        }
+        let action = match self.settings.action_for_key(code) {
+            Some(action) => action,
+            None => return (None, false),
+        };
+        let already_held = self.held_action(action);
+        self.held.push(code.to_string());
+        if already_held {
+            (None, true)
+        } else {
+            (Some(Input::Press(action)), true)
+        }
+    }
+
+    pub fn key_up(&mut self, code: &str) -> Option<Input> {
+        let index = self.held.iter().position(|key| key == code)?;
+        self.held.remove(index);
+        let action = self.settings.action_for_key(code)?;
+        // Another key for the same action keeps it held.
+        if self.held_action(action) {
+            None
+        } else {
+            Some(Input::Release(action))
+        }
+    }
+
+    /// Lets go of everything, e.g. when the page loses focus.
+    pub fn release_all(&mut self) -> Vec<Input> {
+        let mut released = Vec::new();
+        for code in std::mem::take(&mut self.held) {
+            if let Some(action) = self.settings.action_for_key(&code) {
+                if !released.contains(&Input::Release(action)) {
+                    released.push(Input::Release(action));
+                }
+            }
+        }
+        released
+    }
+
+    fn held_action(&self, action: Action) -> bool {
+        self.held.iter().any(|key| self.settings.action_for_key(key) == Some(action))
+    }
+}
+
+#[derive(Default)]
+struct ControlState {
+    keys: KeyMap,
+    gamepads: Gamepads<WebGamepads>,
+    inputs: VecDeque<Input>,
+}
+
+type Listener = Closure<dyn FnMut(web_sys::Event)>;
+
+/// Keyboard listeners and gamepad polling. Inputs are queued as they arrive
+/// and handed to a game by `apply`, once per animation frame.
+#[wasm_bindgen]
+pub struct Controls {
+    state: Rc<RefCell<ControlState>>,
+    target: Option<EventTarget>,
+    listeners: Vec<(&'static str, Listener)>,
+}
+
+#[wasm_bindgen]
+impl Controls {
+    /// Listens on the element with id `target_id`, or on the whole window if
+    /// there is no such element.
+    #[wasm_bindgen(constructor)]
+    pub fn new(target_id: &str, settings: &Settings) -> Controls {
+        let state = Rc::new(RefCell::new(ControlState {
+            keys: KeyMap::new(settings.clone()),
+            ..ControlState::default()
+        }));
+        let window = web_sys::window();
+        let element = window
+            .as_ref()
+            .and_then(|win| win.document())
+            .and_then(|doc| doc.get_element_by_id(target_id));
+        let target: Option<EventTarget> = match element {
+            Some(element) => Some(element.into()),
+            None => window.map(Into::into),
+        };
+        let mut controls = Controls {
+            state,
+            target,
+            listeners: Vec::new(),
+        };
+        if controls.target.is_none() {
+            log("No window to listen for keys on");
+            return controls;
+        }
+
+        let state = controls.state.clone();
+        controls.listen("keydown", move |event| {
+            if let Some(event) = event.dyn_ref::<KeyboardEvent>() {
+                let mut state = state.borrow_mut();
+                let (input, claimed) = state.keys.key_down(&event.code());
+                if claimed {
+                    event.prevent_default();
+                }
+                state.inputs.extend(input);
+            }
+        });
+        let state = controls.state.clone();
+        controls.listen("keyup", move |event| {
+            if let Some(event) = event.dyn_ref::<KeyboardEvent>() {
+                let mut state = state.borrow_mut();
+                let input = state.keys.key_up(&event.code());
+                state.inputs.extend(input);
+            }
+        });
+        let state = controls.state.clone();
+        controls.listen("blur", move |_| {
+            let mut state = state.borrow_mut();
+            let released = state.keys.release_all();
+            state.inputs.extend(released);
+        });
+        controls
+    }
+
+    /// Polls the gamepads and feeds every input received since the last call
+    /// to `game`. Call once per frame.
+    pub fn apply(&self, game: &mut Tetris) {
+        let inputs: Vec<Input> = {
+            let mut state = self.state.borrow_mut();
+            let ControlState { keys, gamepads, inputs } = &mut *state;
+            let polled = gamepads.poll(keys, keys.settings().deadzone);
+            inputs.extend(polled);
+            inputs.drain(..).collect()
+        };
+        for input in inputs {
+            match input {
+                Input::Press(action) => game.press(action),
+                Input::Release(action) => game.release(action),
+                Input::TogglePause if game.is_paused() => game.unpause(),
+                Input::TogglePause => game.pause(),
+            }
+        }
+    }
+
+    /// Binds the next key pressed to `action`, in addition to its current
+    /// keys. Escape cancels.
+    pub fn capture(&self, action: Action) {
+        self.state.borrow_mut().keys.capture(action);
+    }
+
+    pub fn cancel_capture(&self) {
+        self.state.borrow_mut().keys.cancel_capture();
+    }
+
+    pub fn get_gamepads_connected(&self) -> usize {
+        self.state.borrow().gamepads.connected()
+    }
+
+    pub fn is_capturing(&self) -> bool {
+        self.state.borrow().keys.capturing().is_some()
+    }
+
+    /// Current settings including bindings made by capture, for saving or
+    /// for `Tetris::set_settings`.
+    pub fn get_settings(&self) -> Settings {
+        self.state.borrow().keys.settings().clone()
+    }
+
+    pub fn set_settings(&self, settings: &Settings) {
+        let mut state = self.state.borrow_mut();
+        let released = state.keys.set_settings(settings.clone());
+        state.inputs.extend(released);
+    }
+}
+
+impl Controls {
+    fn listen(&mut self, event: &'static str, handler: impl FnMut(web_sys::Event) + 'static) {
+        let listener = Closure::wrap(Box::new(handler) as Box<dyn FnMut(web_sys::Event)>);
+        if let Some(ref target) = self.target {
+            if target
+                .add_event_listener_with_callback(event, listener.as_ref().unchecked_ref())
+                .is_err()
+            {
+                log(&format!("Failed to listen for {}", event));
+            }
+        }
+        self.listeners.push((event, listener));
+    }
+}
+
+impl Drop for Controls {
+    fn drop(&mut self) {
+        if let Some(ref target) = self.target {
+            for (event, listener) in &self.listeners {
+                let _ = target.remove_event_listener_with_callback(event, listener.as_ref().unchecked_ref());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_maps_keys() {
+        let mut keys = KeyMap::new(Settings::default());
+        assert_eq!(keys.key_down("ArrowLeft"), (Some(Input::Press(Action::MoveLeft)), true));
+        // A second key for the same action, and browser repeats, don't press again.
+        assert_eq!(keys.key_down("KeyA"), (None, true));
+        assert_eq!(keys.key_down("ArrowLeft"), (None, true));
+        assert_eq!(keys.key_up("ArrowLeft"), None);
+        assert_eq!(keys.key_up("KeyA"), Some(Input::Release(Action::MoveLeft)));
+        assert_eq!(keys.key_down("Space"), (Some(Input::TogglePause), true));
+        // Holding pause toggles once; the next press toggles again.
+        assert_eq!(keys.key_down("Space"), (None, true));
+        assert_eq!(keys.key_up("Space"), None);
+        assert_eq!(keys.key_down("Space"), (Some(Input::TogglePause), true));
+        keys.key_up("Space");
+        assert_eq!(keys.key_down("KeyM"), (None, false));
+
+        keys.key_down("KeyC");
+        assert_eq!(keys.release_all(), vec![Input::Release(Action::Hold)]);
+    }
+
+    #[test]
+    fn test_capture() {
+        let mut keys = KeyMap::new(Settings::default());
+        keys.capture(Action::Hold);
+        assert_eq!(keys.key_down("KeyD"), (None, true));
+        assert_eq!(keys.capturing(), None);
+        assert_eq!(keys.key_up("KeyD"), None);
+        assert_eq!(keys.key_down("KeyD"), (Some(Input::Press(Action::Hold)), true));
+        assert!(!keys.settings().get_keys(Action::MoveRight).contains(&"KeyD".to_string()));
+
+        keys.capture(Action::Hold);
+        keys.key_down("Escape");
+        assert_eq!(keys.settings().action_for_key("Escape"), None);
+    }
+}
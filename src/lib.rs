@@ -1,7 +1,71 @@
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement};
 
+mod action;
+mod animation;
+#[cfg(feature = "audio")]
+pub mod audio;
+pub mod bot;
+mod entry;
+mod events;
+mod finesse;
+pub mod gamepad;
+mod handling;
+pub mod highscores;
+mod hint;
+pub mod input;
+pub mod movegen;
+pub mod net;
+mod particles;
+mod randomizer;
+pub mod render;
+mod rng;
+mod rotation;
+mod rotation_system;
+mod rules;
+mod settings;
+mod stats;
+pub mod storage;
+mod theme;
+pub mod touch;
+
+pub use action::Action;
+pub use animation::{ClearEffect, ClearKind};
+#[cfg(feature = "audio")]
+pub use audio::{Audio, Sound};
+pub use finesse::{FinesseFault, FinesseTraining};
+pub use highscores::{HighScoreTable, PersonalBest, Ranking, ScoreEntry};
+pub use hint::Hint;
+pub use randomizer::Randomizer;
+pub use rotation::Kick180;
+pub use input::Controls;
+pub use render::hud::{HudItem, HudLayout, HudPosition};
+pub use render::Renderer;
+pub use rotation_system::Rotation;
+pub use rules::{Gravity, RulePreset, RuleSet, Scoring};
+pub use settings::Settings;
+pub use stats::{GameResult, Stats};
+pub use theme::{BlockStyle, GhostStyle, Theme};
+pub use touch::TouchControls;
+use animation::Fall;
+use entry::Buffered;
+use events::GameEvent;
+use handling::Handling;
+use particles::Particles;
+use randomizer::Generator;
+use render::canvas::{Sprites, Tile, BLOCK_SIZE};
+use render::hud::Hud;
+use render::layout::Layout;
+use rng::Rng;
+
+/// The engine runs at a fixed 60 frames per second when driven by `step`.
+pub const FRAMES_PER_SECOND: u32 = 60;
+const FRAME_TIME: f64 = 1.0 / FRAMES_PER_SECOND as f64;
+const GRAVITY_FRAMES: u32 = 60; // One row per second
+
 #[wasm_bindgen]
 pub struct Tetris {
     board: Vec<Vec<u8>>,
@@ -9,17 +73,63 @@ pub struct Tetris {
     height: usize,
     current_piece: Option<Piece>,
     next_piece: Piece,
+    queue: VecDeque<Piece>, // Dealt after the next piece, for previews
+    generator: Generator,
+    hold_piece: Option<Piece>,
+    hold_used: bool, // Only one hold per piece
     score: u32,
     game_over: bool,
     paused: bool,
     ctx: Option<CanvasRenderingContext2d>,
+    sprites: RefCell<Option<Sprites>>, // Painted on first draw, at the layout's scale
+    layout: Cell<Layout>,
+    shown: RefCell<render::canvas::Shown>, // Board cells on the canvas, for partial repaints
+    draw_time: Cell<f64>, // Milliseconds a draw takes, smoothed over recent frames
     cleared_lanes: u32,
     tetris_count: u32,
     triple_count: u32,
     double_count: u32,
     single_count: u32,
     clearing_lines: Vec<usize>,
-    clearing_animation_progress: f64, // 0.0 to 0.3 seconds
+    clearing_animation_progress: f64, // Seconds into the clear
+    clear_effect: ClearEffect,
+    clear_duration: f64, // Seconds, from the settings when the clear started
+    fall: Option<Fall>,
+    particles: Particles,
+    hud: Hud,
+    hud_layout: HudLayout,
+    events: Vec<GameEvent>,
+    countdown: f64, // Seconds of READY / GO left
+    entry_delay: Option<f64>, // Seconds until the next piece spawns
+    buffered: Buffered,
+    rules: RuleSet,
+    lock_time: f64, // Seconds the piece has rested on the stack
+    lock_resets: u32,
+    lowest_y: i32, // Lowest row the piece reached, for lock delay resets
+    rng: Rng,
+    seed: u64, // Seed the piece sequence started from
+    frame: u32,
+    gravity_time: f64, // Seconds since gravity last pulled the piece
+    spawn_score: u32, // Score when the current piece came out
+    pending_garbage: VecDeque<(u32, usize)>, // (lines, hole column)
+    outgoing_garbage: u32,
+    ranked: bool,
+    show_hint: bool,
+    hint_counted: bool, // Whether the current piece already counted as hinted
+    hint: Cell<Option<Option<Hint>>>, // Worked out for the current piece and board
+    piece_inputs: Vec<Action>, // Inputs used on the current piece
+    finesse_faults: Vec<FinesseFault>,
+    finesse_training: FinesseTraining,
+    finesse_flash: f64, // Seconds left of the fault flash
+    stats: Stats,
+    combo: u32,     // Consecutive pieces that cleared lines
+    b2b_chain: u32, // Consecutive Tetris or T-spin clears
+    last_move_rotation: bool, // A T-spin needs the last successful move to be a rotation
+    settings: Settings,
+    theme: Theme, // Resolved from the settings
+    handling: Handling,
+    #[cfg(feature = "webgl")]
+    gl: Option<render::webgl::GlRenderer>,
 }
 
 #[derive(Clone)]
@@ -28,109 +138,66 @@ struct Piece {
     x: i32,
     y: i32,
     color: u8,
+    kind: usize, // Index into default_shapes()
+    rotation: u8, // Quarter turns clockwise from the spawn orientation
+}
+
+const T_KIND: usize = 2;
+
+fn default_shapes() -> Vec<Vec<Vec<u8>>> {
+    vec![
+        vec![vec![1, 1, 1, 1]], // I
+        vec![vec![1, 1], vec![1, 1]], // O
+        vec![vec![1, 1, 1], vec![0, 1, 0]], // T
+        vec![vec![1, 1, 1], vec![1, 0, 0]], // L
+        vec![vec![1, 1, 1], vec![0, 0, 1]], // J
+        vec![vec![1, 1, 0], vec![0, 1, 1]], // S
+        vec![vec![0, 1, 1], vec![1, 1, 0]], // Z
+    ]
 }
 
 #[wasm_bindgen]
 impl Tetris {
     #[wasm_bindgen(constructor)]
     pub fn new(canvas_id: &str) -> Tetris {
-        let document = match web_sys::window().and_then(|win| win.document()) {
-            Some(doc) => doc,
-            None => {
-                web_sys::console::log_1(&"Failed to access window.document".into());
-                return Tetris::new_fallback();
-            }
-        };
-
-        let canvas = match document.get_element_by_id(canvas_id) {
-            Some(elem) => match elem.dyn_into::<HtmlCanvasElement>() {
-                Ok(canvas) => canvas,
-                Err(_) => {
-                    web_sys::console::log_1(&"Failed to cast element to HtmlCanvasElement".into());
-                    return Tetris::new_fallback();
-                }
-            },
-            None => {
-                web_sys::console::log_1(&format!("Canvas element '{}' not found", canvas_id).into());
-                return Tetris::new_fallback();
-            }
-        };
-
-        let ctx = match canvas.get_context("2d") {
-            Ok(Some(ctx)) => match ctx.dyn_into::<CanvasRenderingContext2d>() {
-                Ok(ctx) => ctx,
-                Err(_) => {
-                    web_sys::console::log_1(&"Failed to cast context to CanvasRenderingContext2d".into());
-                    return Tetris::new_fallback();
-                }
-            },
-            Ok(None) => {
-                web_sys::console::log_1(&"Failed to get 2d context".into());
-                return Tetris::new_fallback();
-            }
-            Err(_) => {
-                web_sys::console::log_1(&"Error getting canvas context".into());
-                return Tetris::new_fallback();
-            }
+        let ctx = match context_for_canvas(canvas_id) {
+            Some(ctx) => ctx,
+            None => return Tetris::new_fallback(),
         };
-
-        let width = 10;
-        let height = 20;
-        let board = vec![vec![0; width]; height];
-        let shapes = vec![
-            vec![vec![1, 1, 1, 1]], // I
-            vec![vec![1, 1], vec![1, 1]], // O
-            vec![vec![1, 1, 1], vec![0, 1, 0]], // T
-            vec![vec![1, 1, 1], vec![1, 0, 0]], // L
-            vec![vec![1, 1, 1], vec![0, 0, 1]], // J
-            vec![vec![1, 1, 0], vec![0, 1, 1]], // S
-            vec![vec![0, 1, 1], vec![1, 1, 0]], // Z
-        ];
-
         Tetris {
-            board,
-            width,
-            height,
-            current_piece: None,
-            next_piece: Tetris::create_piece(&shapes, None),
-            score: 0,
-            game_over: false,
-            paused: false,
             ctx: Some(ctx),
-            cleared_lanes: 0,
-            tetris_count: 0,
-            triple_count: 0,
-            double_count: 0,
-            single_count: 0,
-            clearing_lines: Vec::new(),
-            clearing_animation_progress: 0.0,
+            ..Tetris::new_fallback()
         }
     }
 
     fn new_fallback() -> Tetris {
+        Tetris::with_rng(Rng::from_entropy())
+    }
+
+    fn with_rng(rng: Rng) -> Tetris {
+        let seed = rng.state();
         let width = 10;
         let height = 20;
         let board = vec![vec![0; width]; height];
-        let shapes = vec![
-            vec![vec![1, 1, 1, 1]], // I
-            vec![vec![1, 1], vec![1, 1]], // O
-            vec![vec![1, 1, 1], vec![0, 1, 0]], // T
-            vec![vec![1, 1, 1], vec![1, 0, 0]], // L
-            vec![vec![1, 1, 1], vec![0, 0, 1]], // J
-            vec![vec![1, 1, 0], vec![0, 1, 1]], // S
-            vec![vec![0, 1, 1], vec![1, 1, 0]], // Z
-        ];
 
-        Tetris {
+        let mut game = Tetris {
             board,
             width,
             height,
             current_piece: None,
-            next_piece: Tetris::create_piece(&shapes, None),
+            next_piece: Tetris::create_piece(&default_shapes(), 0), // Dealt below
+            queue: VecDeque::new(),
+            generator: Generator::default(),
+            hold_piece: None,
+            hold_used: false,
             score: 0,
             game_over: false,
             paused: false,
             ctx: None,
+            sprites: RefCell::default(),
+            shown: RefCell::default(),
+            draw_time: Cell::new(0.0),
+            layout: Cell::default(),
             cleared_lanes: 0,
             tetris_count: 0,
             triple_count: 0,
@@ -138,41 +205,90 @@ impl Tetris {
             single_count: 0,
             clearing_lines: Vec::new(),
             clearing_animation_progress: 0.0,
-        }
+            clear_effect: ClearEffect::Fade,
+            clear_duration: 0.0,
+            fall: None,
+            particles: Particles::new(seed),
+            hud: Hud::default(),
+            hud_layout: HudLayout::default(),
+            events: Vec::new(),
+            countdown: 0.0,
+            entry_delay: None,
+            buffered: Buffered::default(),
+            rules: RuleSet::default(),
+            lock_time: 0.0,
+            lock_resets: 0,
+            lowest_y: 0,
+            rng,
+            seed,
+            frame: 0,
+            gravity_time: 0.0,
+            spawn_score: 0,
+            pending_garbage: VecDeque::new(),
+            outgoing_garbage: 0,
+            ranked: false,
+            show_hint: false,
+            hint_counted: false,
+            hint: Cell::new(None),
+            piece_inputs: Vec::new(),
+            finesse_faults: Vec::new(),
+            finesse_training: FinesseTraining::Off,
+            finesse_flash: 0.0,
+            stats: Stats::default(),
+            combo: 0,
+            b2b_chain: 0,
+            last_move_rotation: false,
+            settings: Settings::default(),
+            theme: Theme::default(),
+            handling: Handling::default(),
+            #[cfg(feature = "webgl")]
+            gl: None,
+        };
+        game.deal_queue();
+        game
     }
 
-    fn create_piece(shapes: &[Vec<Vec<u8>>], test_idx: Option<usize>) -> Piece {
-        let idx = test_idx.unwrap_or_else(|| {
-            #[cfg(target_arch = "wasm32")]
-            {
-                (js_sys::Math::random() * shapes.len() as f64).floor() as usize
-            }
-            #[cfg(not(target_arch = "wasm32"))]
-            {
-                0 // Default to first shape for tests
-            }
-        });
-        let color = ((idx % 6) + 1) as u8; // Maps to 1-6 (colors #f00 to #0ff)
+    fn create_piece(shapes: &[Vec<Vec<u8>>], idx: usize) -> Piece {
+        let color = (idx + 1) as u8; // One color per kind, so renderers can tell kinds apart
         Piece {
             shape: shapes[idx].clone(),
             x: 4 - shapes[idx][0].len() as i32 / 2,
             y: 0,
             color,
+            kind: idx,
+            rotation: 0,
         }
     }
 
+    /// Engine without a canvas, for bots, tools and native tests.
+    pub fn with_seed(seed: u64) -> Tetris {
+        Tetris::with_rng(Rng::new(seed))
+    }
+
+    /// Reseeds the piece sequence. Only takes effect before `start()`, so two
+    /// engines given the same seed deal the same pieces.
+    pub fn set_seed(&mut self, seed: u64) {
+        if self.in_play() {
+            return;
+        }
+        self.rng = Rng::new(seed);
+        self.seed = seed;
+        self.particles = Particles::new(seed);
+        self.deal_queue();
+    }
+
+    pub fn get_seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Deals the first piece, after the countdown if one is set.
     pub fn start(&mut self) {
-        if self.current_piece.is_none() {
-            self.current_piece = Some(self.next_piece.clone());
-            self.next_piece = Tetris::create_piece(&[
-                vec![vec![1, 1, 1, 1]],
-                vec![vec![1, 1], vec![1, 1]],
-                vec![vec![1, 1, 1], vec![0, 1, 0]],
-                vec![vec![1, 1, 1], vec![1, 0, 0]],
-                vec![vec![1, 1, 1], vec![0, 0, 1]],
-                vec![vec![1, 1, 0], vec![0, 1, 1]],
-                vec![vec![0, 1, 1], vec![1, 1, 0]],
-            ], None);
+        if self.in_play() {
+            return;
+        }
+        self.start_countdown();
+        if self.countdown <= 0.0 {
+            self.spawn_next();
         }
     }
 
@@ -180,7 +296,11 @@ impl Tetris {
         self.paused = true;
     }
 
+    /// Resumes play, after the countdown if one is set and a game is on.
     pub fn unpause(&mut self) {
+        if self.paused && !self.game_over && self.in_play() {
+            self.start_countdown();
+        }
         self.paused = false;
     }
 
@@ -189,33 +309,23 @@ impl Tetris {
     }
 
     pub fn move_left(&mut self) {
-        if self.paused {
+        if self.paused || self.delay_input(Action::MoveLeft) {
             return;
         }
-        if let Some(ref mut piece) = self.current_piece {
-            let old_x = piece.x;
-            piece.x -= 1;
-            if collides(piece, &self.board, self.width, self.height) {
-                piece.x = old_x;
-            }
-        }
+        self.record_input(Action::MoveLeft);
+        self.shift(-1);
     }
 
     pub fn move_right(&mut self) {
-        if self.paused {
+        if self.paused || self.delay_input(Action::MoveRight) {
             return;
         }
-        if let Some(ref mut piece) = self.current_piece {
-            let old_x = piece.x;
-            piece.x += 1;
-            if collides(piece, &self.board, self.width, self.height) {
-                piece.x = old_x;
-            }
-        }
+        self.record_input(Action::MoveRight);
+        self.shift(1);
     }
 
     pub fn move_down(&mut self) -> bool {
-        if self.paused {
+        if self.paused || self.delay_input(Action::SoftDrop) {
             return true;
         }
         if !self.clearing_lines.is_empty() {
@@ -226,117 +336,345 @@ impl Tetris {
             piece.y += 1;
             if collides(piece, &self.board, self.width, self.height) {
                 piece.y = old_y;
-                self.merge();
-                self.clear_lines();
-                self.current_piece = Some(self.next_piece.clone());
-                self.next_piece = Tetris::create_piece(&[
-                    vec![vec![1, 1, 1, 1]],
-                    vec![vec![1, 1], vec![1, 1]],
-                    vec![vec![1, 1, 1], vec![0, 1, 0]],
-                    vec![vec![1, 1, 1], vec![1, 0, 0]],
-                    vec![vec![1, 1, 1], vec![0, 0, 1]],
-                    vec![vec![1, 1, 0], vec![0, 1, 1]],
-                    vec![vec![0, 1, 1], vec![1, 1, 0]],
-                ], None);
-                if collides(&self.current_piece.as_ref().unwrap(), &self.board, self.width, self.height) {
-                    self.game_over = true;
+                if self.rules.lock_delay > 0.0 {
+                    return true; // Resting; the lock delay runs in `update`
+                }
+                self.lock_piece();
+                if self.game_over {
                     return false;
                 }
+            } else {
+                self.last_move_rotation = false;
             }
         }
         true
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn drop(&mut self) {
-        if self.paused {
+        if self.paused || self.delay_input(Action::HardDrop) {
             return;
         }
         if !self.clearing_lines.is_empty() {
             return; // Wait for animation to finish
         }
-        if let Some(ref mut piece) = self.current_piece {
-            let mut temp_y = piece.y;
-            while !collides(&Piece { y: temp_y + 1, ..piece.clone() }, &self.board, self.width, self.height) {
-                temp_y += 1;
+        self.record_input(Action::HardDrop);
+        let (landed, from_y) = match self.current_piece {
+            Some(ref mut piece) => {
+                let mut temp_y = piece.y;
+                while !collides(&Piece { y: temp_y + 1, ..piece.clone() }, &self.board, self.width, self.height) {
+                    temp_y += 1;
+                }
+                if temp_y != piece.y {
+                    self.last_move_rotation = false;
+                }
+                let from_y = piece.y;
+                piece.y = temp_y;
+                (piece.clone(), from_y)
             }
-            piece.y = temp_y;
-            self.merge();
-            self.clear_lines();
-            self.current_piece = Some(self.next_piece.clone());
-            self.next_piece = Tetris::create_piece(&[
-                vec![vec![1, 1, 1, 1]],
-                vec![vec![1, 1], vec![1, 1]],
-                vec![vec![1, 1, 1], vec![0, 1, 0]],
-                vec![vec![1, 1, 1], vec![1, 0, 0]],
-                vec![vec![1, 1, 1], vec![0, 0, 1]],
-                vec![vec![1, 1, 0], vec![0, 1, 1]],
-                vec![vec![0, 1, 1], vec![1, 1, 0]],
-            ], None);
-            if collides(&self.current_piece.as_ref().unwrap(), &self.board, self.width, self.height) {
-                self.game_over = true;
+            None => return,
+        };
+        // Judged before scoring, so a piece sent back to the top earns nothing.
+        if self.check_finesse() {
+            return;
+        }
+        self.score += self.rules.scoring.drop_points((landed.y - from_y) as u32, true);
+        self.push_event(GameEvent::HardDrop);
+        self.emit_hard_drop(&landed, from_y);
+        self.place_piece();
+    }
+
+    // Moves the current piece `dx` columns if it fits.
+    fn shift(&mut self, dx: i32) -> bool {
+        if let Some(ref mut piece) = self.current_piece {
+            piece.x += dx;
+            if collides(piece, &self.board, self.width, self.height) {
+                piece.x -= dx;
+            } else {
+                self.last_move_rotation = false;
+                self.push_event(GameEvent::Move);
+                self.moved();
+                return true;
             }
         }
+        false
     }
 
-    pub fn rotate_left(&mut self) {
-        if self.paused {
+    fn can_fall(&self) -> bool {
+        match self.current_piece {
+            Some(ref piece) => !collides(&Piece { y: piece.y + 1, ..piece.clone() }, &self.board, self.width, self.height),
+            None => false,
+        }
+    }
+
+    // The current piece moved down as far as it can go.
+    fn ghost_piece(&self) -> Option<Piece> {
+        let mut ghost = self.current_piece.clone()?;
+        while !collides(&Piece { y: ghost.y + 1, ..ghost.clone() }, &self.board, self.width, self.height) {
+            ghost.y += 1;
+        }
+        Some(ghost)
+    }
+
+    fn lock_piece(&mut self) {
+        if self.check_finesse() {
+            return;
+        }
+        self.place_piece();
+    }
+
+    // Locks the current piece into the board, once finesse has been judged.
+    fn place_piece(&mut self) {
+        self.merge();
+        self.push_event(GameEvent::Lock);
+        self.stats.pieces_placed += 1;
+        if let Some(ref piece) = self.current_piece {
+            self.stats.piece_counts[piece.kind] += 1;
+        }
+        if self.locked_out() {
+            self.end_game();
+            return;
+        }
+        if self.clear_lines() == 0 {
+            self.apply_garbage();
+        }
+        self.hold_used = false;
+        self.last_move_rotation = false;
+        let delay = if self.clearing_lines.is_empty() { self.settings.are } else { self.settings.line_are };
+        self.spawn_after(delay);
+    }
+
+    fn check_spawn(&mut self) {
+        if collides(self.current_piece.as_ref().unwrap(), &self.board, self.width, self.height) {
+            self.end_game();
+        }
+    }
+
+    /// Swaps the current piece with the held one (or the next piece if the hold
+    /// is empty). Allowed once per piece; the swapped-in piece respawns at the top.
+    pub fn hold(&mut self) {
+        if self.paused || self.delay_input(Action::Hold) || self.hold_used || self.game_over || !self.rules.hold {
             return;
         }
         if !self.clearing_lines.is_empty() {
             return; // Wait for animation to finish
         }
-        if let Some(ref mut piece) = self.current_piece {
-            let new_shape = rotate(&piece.shape, -1);
-            let old_shape = piece.shape.clone();
-            piece.shape = new_shape;
-            if collides(piece, &self.board, self.width, self.height) {
-                piece.shape = old_shape;
+        let current_kind = match self.current_piece {
+            Some(ref piece) => piece.kind,
+            None => return,
+        };
+        let incoming = match self.hold_piece.take() {
+            Some(held) => held,
+            None => {
+                let next = self.next_piece.clone();
+                self.next_piece = self.random_piece();
+                next
             }
+        };
+        self.hold_piece = Some(self.new_piece(current_kind));
+        self.current_piece = Some(self.new_piece(incoming.kind));
+        self.hold_used = true;
+        self.stats.holds += 1;
+        self.push_event(GameEvent::Hold);
+        self.piece_inputs.clear();
+        self.record_input(Action::Hold);
+        self.last_move_rotation = false;
+        self.reset_lock();
+        self.on_hint_spawn();
+        self.check_spawn();
+    }
+
+    pub fn rotate_left(&mut self) {
+        if self.paused || self.delay_input(Action::RotateLeft) {
+            return;
         }
+        if !self.clearing_lines.is_empty() {
+            return; // Wait for animation to finish
+        }
+        self.record_input(Action::RotateLeft);
+        self.turn(-1);
     }
 
     pub fn rotate_right(&mut self) {
-        if self.paused {
+        if self.paused || self.delay_input(Action::RotateRight) {
             return;
         }
         if !self.clearing_lines.is_empty() {
             return; // Wait for animation to finish
         }
-        if let Some(ref mut piece) = self.current_piece {
-            let new_shape = rotate(&piece.shape, 1);
-            let old_shape = piece.shape.clone();
-            piece.shape = new_shape;
-            if collides(piece, &self.board, self.width, self.height) {
-                piece.shape = old_shape;
+        self.record_input(Action::RotateRight);
+        self.turn(1);
+    }
+
+    pub fn apply_action(&mut self, action: Action) {
+        match action {
+            Action::MoveLeft => self.move_left(),
+            Action::MoveRight => self.move_right(),
+            Action::SoftDrop => {
+                if !self.paused && self.current_piece.is_some() && self.countdown <= 0.0 {
+                    self.record_input(Action::SoftDrop);
+                    self.push_event(GameEvent::SoftDrop);
+                }
+                self.soft_drop();
             }
+            Action::HardDrop => self.drop(),
+            Action::RotateLeft => self.rotate_left(),
+            Action::RotateRight => self.rotate_right(),
+            Action::Rotate180 => self.rotate_180(),
+            Action::Hold => self.hold(),
         }
     }
 
-    pub fn update_clearing_animation(&mut self, delta_time: f64) {
-        if self.clearing_lines.is_empty() {
+    /// Advances the simulation by exactly one frame: gravity and the clear
+    /// animation run on the frame counter instead of wall-clock time, so the
+    /// same inputs on the same frames always produce the same state.
+    pub fn step(&mut self) {
+        if self.paused || self.game_over {
             return;
         }
-        self.clearing_animation_progress += delta_time;
-        if self.clearing_animation_progress >= 0.3 {
-            // Finish clearing
-            let mut new_board: Vec<Vec<u8>> = Vec::new();
-            for y in 0..self.height {
-                if !self.clearing_lines.contains(&y) {
-                    new_board.push(self.board[y].clone());
-                }
+        self.frame += 1;
+        self.update(FRAME_TIME);
+    }
+
+    pub fn get_frame(&self) -> u32 {
+        self.frame
+    }
+
+    /// FNV-1a hash over everything that affects future play. Two engines that
+    /// agree on this hash at a frame are in the same state.
+    pub fn state_hash(&self) -> u64 {
+        let mut hasher = StateHasher::new();
+        for row in &self.board {
+            hasher.write(row);
+        }
+        if let Some(ref piece) = self.current_piece {
+            for row in &piece.shape {
+                hasher.write(row);
             }
-            for _ in 0..self.clearing_lines.len() {
-                new_board.insert(0, vec![0; self.width]);
+            hasher.write_u64(piece.x as u64);
+            hasher.write_u64(piece.y as u64);
+            hasher.write(&[piece.color]);
+        }
+        for row in &self.next_piece.shape {
+            hasher.write(row);
+        }
+        for piece in &self.queue {
+            hasher.write(&[piece.kind as u8]);
+        }
+        hasher.write(&self.generator.state());
+        if let Some(ref piece) = self.hold_piece {
+            hasher.write_u64(piece.kind as u64);
+        }
+        hasher.write(&[self.hold_used as u8]);
+        hasher.write_u64(self.score as u64);
+        hasher.write_u64(self.cleared_lanes as u64);
+        hasher.write(&[self.game_over as u8]);
+        for &y in &self.clearing_lines {
+            hasher.write_u64(y as u64);
+        }
+        for &(lines, hole) in &self.pending_garbage {
+            hasher.write_u64(lines as u64);
+            hasher.write_u64(hole as u64);
+        }
+        hasher.write_u64(self.combo as u64);
+        hasher.write_u64(self.b2b_chain as u64);
+        hasher.write(&[self.last_move_rotation as u8]);
+        hasher.write_u64(self.rng.state());
+        hasher.write_u64(self.gravity_time.to_bits());
+        hasher.write_u64(self.spawn_score as u64);
+        hasher.write_u64(self.countdown.to_bits());
+        hasher.write_u64(self.entry_delay.map_or(u64::MAX, f64::to_bits));
+        hasher.write(&[self.buffered.rotation.map_or(0, |action| action as u8 + 1), self.buffered.hold as u8]);
+        hasher.write_u64(self.lock_time.to_bits());
+        hasher.write_u64(self.lock_resets as u64);
+        hasher.write_u64(self.lowest_y as u64);
+        hasher.finish()
+    }
+
+    /// Hash of everything besides the inputs that decides how a game plays:
+    /// the size, the rules and the simulated settings. Versus peers must agree on it.
+    pub(crate) fn config_hash(&self) -> u64 {
+        let mut hasher = StateHasher::new();
+        hasher.write_u64(self.width as u64);
+        hasher.write_u64(self.height as u64);
+        let rules = &self.rules;
+        hasher.write(&[
+            rules.randomizer as u8,
+            rules.rotation as u8,
+            rules.kick_180 as u8,
+            rules.gravity as u8,
+            rules.scoring as u8,
+            rules.hold as u8,
+            rules.lock_out as u8,
+            rules.garbage_out as u8,
+        ]);
+        hasher.write_u64(rules.lock_delay.to_bits());
+        hasher.write_u64(rules.lock_resets as u64);
+        hasher.write_u64(rules.previews as u64);
+        hasher.write(&[self.finesse_training as u8]);
+        let settings = &self.settings;
+        for value in [
+            settings.das,
+            settings.arr,
+            settings.sdf,
+            settings.animation_speed,
+            settings.clear_duration,
+            settings.fall_duration,
+            settings.countdown,
+            settings.are,
+            settings.line_are,
+        ] {
+            hasher.write_u64(value.to_bits());
+        }
+        hasher.finish()
+    }
+
+    /// Queues incoming garbage. It rises from the bottom the next time a
+    /// piece locks without clearing a line.
+    pub fn queue_garbage(&mut self, lines: u32, hole: usize) {
+        if lines > 0 {
+            self.pending_garbage.push_back((lines, hole.min(self.width - 1)));
+        }
+    }
+
+    /// Returns the garbage this board has sent since the last call.
+    pub fn take_outgoing_garbage(&mut self) -> u32 {
+        std::mem::take(&mut self.outgoing_garbage)
+    }
+
+    fn apply_garbage(&mut self) {
+        self.forget_hint();
+        while let Some((lines, hole)) = self.pending_garbage.pop_front() {
+            for _ in 0..lines {
+                if self.board[0].iter().any(|&cell| cell != 0) {
+                    self.end_game();
+                }
+                self.board.remove(0);
+                let mut row = vec![GARBAGE_COLOR; self.width];
+                row[hole] = 0;
+                self.board.push(row);
             }
-            self.board = new_board;
-            self.clearing_lines.clear();
-            self.clearing_animation_progress = 0.0;
         }
     }
 
+    /// Advances everything that runs on wall-clock time. Call once per
+    /// animation frame with the seconds elapsed since the last call.
+    pub fn update(&mut self, delta_time: f64) {
+        self.update_entry(delta_time);
+        self.update_handling(delta_time);
+        self.update_gravity(delta_time);
+        self.update_lock(delta_time);
+        self.update_clearing_animation(delta_time * self.settings.animation_speed);
+        self.particles.update(delta_time);
+        self.update_hud(delta_time);
+        self.update_finesse_flash(delta_time);
+        self.update_stats(delta_time);
+    }
+
     fn merge(&mut self) {
+        self.forget_hint();
         if let Some(ref piece) = self.current_piece {
-            if piece.color == 0 || piece.color as usize >= 7 {
+            if piece.color == 0 || piece.color >= GARBAGE_COLOR {
                 web_sys::console::log_1(&format!("Invalid color during merge: {}", piece.color).into());
                 return;
             }
@@ -345,7 +683,7 @@ impl Tetris {
                     if piece.shape[y][x] != 0 {
                         let board_y = piece.y + y as i32;
                         if board_y >= 0 && board_y < self.height as i32 {
-                            self.board[board_y as usize][piece.x as usize + x] = piece.color;
+                            self.board[board_y as usize][(piece.x + x as i32) as usize] = piece.color;
                         }
                     }
                 }
@@ -353,9 +691,10 @@ impl Tetris {
         }
     }
 
-    fn clear_lines(&mut self) {
+    /// Scores any full rows and starts clearing them. Returns how many.
+    fn clear_lines(&mut self) -> u32 {
         if !self.clearing_lines.is_empty() {
-            return; // Already clearing
+            return 0; // Already clearing
         }
         let mut lines_to_clear = Vec::new();
         let mut lines_cleared = 0;
@@ -365,270 +704,161 @@ impl Tetris {
                 lines_cleared += 1;
             }
         }
-        if !lines_to_clear.is_empty() {
-            self.clearing_lines = lines_to_clear;
-            self.clearing_animation_progress = 0.0;
+        // The locked piece is still the current piece at this point.
+        let tspin = self.last_move_rotation
+            && self
+                .current_piece
+                .as_ref()
+                .is_some_and(|piece| is_tspin(piece, &self.board, self.width, self.height));
+        if lines_to_clear.is_empty() {
+            self.combo = 0;
+            if tspin {
+                // Scores without lines, leaving the back-to-back chain alone.
+                let clear = rules::Clear {
+                    lines: 0,
+                    tspin,
+                    b2b: false,
+                    combo: 0,
+                    perfect: false,
+                };
+                self.score += self.rules.scoring.clear_points(&clear, self.get_level(), self.cleared_lanes);
+                self.push_event(GameEvent::TSpin(0));
+                self.announce(render::hud::action_texts(0, true, false, 0));
+            }
+        } else {
+            let (level, total) = (self.get_level(), self.cleared_lanes);
             self.cleared_lanes += lines_cleared;
+            self.push_event(if tspin { GameEvent::TSpin(lines_cleared) } else { GameEvent::LineClear(lines_cleared) });
+            if self.get_level() > level {
+                self.push_event(GameEvent::LevelUp);
+            }
 
+            self.combo += 1;
+            if tspin || lines_cleared == 4 {
+                self.b2b_chain += 1;
+            } else {
+                self.b2b_chain = 0;
+            }
+            let combo = self.combo - 1;
+            let b2b = self.b2b_chain.saturating_sub(1);
+            self.stats.max_combo = self.stats.max_combo.max(combo);
+            self.stats.max_b2b = self.stats.max_b2b.max(b2b);
+            let attack = attack_for_clear(lines_cleared, tspin, b2b > 0, combo);
+            self.announce(render::hud::action_texts(lines_cleared, tspin, b2b > 0, combo));
+            self.stats.attack += attack;
+            self.send_attack(attack);
+
+            let perfect = (0..self.height).all(|y| lines_to_clear.contains(&y) || self.board[y].iter().all(|&cell| cell == 0));
+            let clear = rules::Clear {
+                lines: lines_cleared,
+                tspin,
+                b2b: b2b > 0,
+                combo,
+                perfect,
+            };
+            self.score += self.rules.scoring.clear_points(&clear, level, total);
             match lines_cleared {
-                4 => {
-                    self.tetris_count += 1;
-                    self.score += 1000;
-                }
-                3 => {
-                    self.triple_count += 1;
-                    self.score += 600;
-                }
-                2 => {
-                    self.double_count += 1;
-                    self.score += 300;
-                }
-                1 => {
-                    self.single_count += 1;
-                    self.score += 100;
-                }
+                4 => self.tetris_count += 1,
+                3 => self.triple_count += 1,
+                2 => self.double_count += 1,
+                1 => self.single_count += 1,
                 _ => {}
             }
+            let kind = if tspin {
+                ClearKind::TSpin
+            } else if lines_cleared == 4 {
+                ClearKind::Tetris
+            } else {
+                ClearKind::Lines
+            };
+            self.start_clear(lines_to_clear, kind);
         }
+        lines_cleared
     }
 
-    #[allow(deprecated)]
-    fn draw_background(&self, ctx: &CanvasRenderingContext2d, width: f64, height: f64, block_size: f64) {
-        let grid_color = "#1C2526"; // Dark gray background
-        let line_color = "#2A3435"; // Lighter gray grid lines
-
-        // Fill the canvas with the base color
-        ctx.set_fill_style(&JsValue::from_str(grid_color));
-        ctx.fill_rect(0.0, 0.0, width, height);
-
-        // Draw grid lines using stroke for precision
-        ctx.set_stroke_style(&JsValue::from_str(line_color));
-        ctx.set_line_width(1.0);
-
-        // Vertical lines
-        for x in 0..=(width as i32 / block_size as i32) {
-            let x_pos = x as f64 * block_size;
-            ctx.begin_path();
-            ctx.move_to(x_pos, 0.0);
-            ctx.line_to(x_pos, height);
-            ctx.stroke();
+    // Outgoing attack cancels pending garbage first; only the rest is sent.
+    fn send_attack(&mut self, mut attack: u32) {
+        while attack > 0 {
+            match self.pending_garbage.front_mut() {
+                Some((lines, _)) if *lines > attack => {
+                    *lines -= attack;
+                    attack = 0;
+                }
+                Some((lines, _)) => {
+                    attack -= *lines;
+                    self.pending_garbage.pop_front();
+                }
+                None => break,
+            }
         }
+        self.outgoing_garbage += attack;
+    }
 
-        // Horizontal lines
-        for y in 0..=(height as i32 / block_size as i32) {
-            let y_pos = y as f64 * block_size;
-            ctx.begin_path();
-            ctx.move_to(0.0, y_pos);
-            ctx.line_to(width, y_pos);
-            ctx.stroke();
+    pub fn draw(&self) {
+        let start = render::canvas::now();
+        self.draw_board();
+        if let (Some(start), Some(end)) = (start, render::canvas::now()) {
+            self.record_draw_time(end - start);
         }
     }
 
-    #[allow(deprecated)]
-    pub fn draw(&self) {
+    fn draw_board(&self) {
+        #[cfg(feature = "webgl")]
+        if let Some(ref gl) = self.gl {
+            gl.draw(self);
+            return;
+        }
         if let Some(ctx) = &self.ctx {
-            let block_size = 30.0;
-            let colors = vec![
-                "#000", "#ff5555", "#55ff55", "#5555ff", "#ffff55", "#ff55ff", "#55ffff",
-            ];
-            let highlight_colors = vec![
-                "#000", "#ff9999", "#99ff99", "#9999ff", "#ffff99", "#ff99ff", "#99ffff",
-            ];
-
-            // Draw light grey border
-            ctx.set_fill_style(&JsValue::from_str("#d3d3d3"));
-            ctx.fill_rect(-2.0, -2.0, block_size * self.width as f64 + 4.0, block_size * self.height as f64 + 4.0);
-
-            // Replace solid black fill with grid background
-            self.draw_background(&ctx, block_size * self.width as f64, block_size * self.height as f64, block_size);
-
-            // Draw board
-            for y in 0..self.height {
-                for x in 0..self.width {
-                    if self.board[y][x] != 0 {
-                        let color_idx = self.board[y][x] as usize;
-                        if color_idx >= colors.len() || color_idx == 0 {
-                            web_sys::console::log_1(&format!("Invalid color index in board: {}", color_idx).into());
-                            return;
-                        }
-                        let alpha = if self.clearing_lines.contains(&y) {
-                            1.0 - (self.clearing_animation_progress / 0.3) // Fade out
-                        } else {
-                            1.0
-                        };
-                        let gradient = ctx.create_linear_gradient(
-                            x as f64 * block_size,
-                            y as f64 * block_size,
-                            (x as f64 + 1.0) * block_size,
-                            (y as f64 + 1.0) * block_size,
-                        );
-                        let _ = gradient.add_color_stop(0.0, &format!("rgba({}, {}, {}, {})", 
-                            u8::from_str_radix(&colors[color_idx][1..3], 16).unwrap(), 
-                            u8::from_str_radix(&colors[color_idx][3..5], 16).unwrap(), 
-                            u8::from_str_radix(&colors[color_idx][5..7], 16).unwrap(), 
-                            alpha));
-                        let _ = gradient.add_color_stop(1.0, &format!("rgba(0, 0, 0, {})", alpha));
-                        ctx.set_fill_style(&gradient);
-                        ctx.fill_rect(
-                            x as f64 * block_size + 2.0,
-                            y as f64 * block_size + 2.0,
-                            block_size - 4.0,
-                            block_size - 4.0,
-                        );
-                        if !self.clearing_lines.contains(&y) || self.clearing_animation_progress < 0.15 {
-                            ctx.set_fill_style(&JsValue::from_str(&format!("rgba({}, {}, {}, {})", 
-                                u8::from_str_radix(&highlight_colors[color_idx][1..3], 16).unwrap(), 
-                                u8::from_str_radix(&highlight_colors[color_idx][3..5], 16).unwrap(), 
-                                u8::from_str_radix(&highlight_colors[color_idx][5..7], 16).unwrap(), 
-                                alpha)));
-                            ctx.fill_rect(
-                                x as f64 * block_size + 4.0,
-                                y as f64 * block_size + 4.0,
-                                block_size - 8.0,
-                                block_size - 8.0,
-                            );
-                        }
-                    }
-                }
-            }
-
-            // Draw current piece
-            if let Some(ref piece) = self.current_piece {
-                for y in 0..piece.shape.len() {
-                    for x in 0..piece.shape[y].len() {
-                        if piece.shape[y][x] != 0 {
-                            let color_idx = piece.color as usize;
-                            if color_idx >= colors.len() || color_idx == 0 {
-                                web_sys::console::log_1(&format!("Invalid color index in piece: {}", color_idx).into());
-                                return;
-                            }
-                            let gradient = ctx.create_linear_gradient(
-                                (piece.x + x as i32) as f64 * block_size,
-                                (piece.y + y as i32) as f64 * block_size,
-                                (piece.x + x as i32 + 1) as f64 * block_size,
-                                (piece.y + y as i32 + 1) as f64 * block_size,
-                            );
-                            let _ = gradient.add_color_stop(0.0, colors[color_idx]);
-                            let _ = gradient.add_color_stop(1.0, "#000");
-                            ctx.set_fill_style(&gradient);
-                            ctx.fill_rect(
-                                (piece.x + x as i32) as f64 * block_size + 2.0,
-                                (piece.y + y as i32) as f64 * block_size + 2.0,
-                                block_size - 4.0,
-                                block_size - 4.0,
-                            );
-                            ctx.set_fill_style(&JsValue::from_str(highlight_colors[color_idx]));
-                            ctx.fill_rect(
-                                (piece.x + x as i32) as f64 * block_size + 4.0,
-                                (piece.y + y as i32) as f64 * block_size + 4.0,
-                                block_size - 8.0,
-                                block_size - 8.0,
-                            );
-                        }
-                    }
-                }
-            }
-
-            // Draw pause overlay if paused
-            if self.paused {
-                ctx.set_fill_style(&JsValue::from_str("rgba(0, 0, 0, 0.7)"));
-                ctx.fill_rect(0.0, 0.0, block_size * self.width as f64, block_size * self.height as f64);
-                ctx.set_fill_style(&JsValue::from_str("#FFD700")); // Gold color for "PAUSE"
-                ctx.set_font("40px Arial");
-                ctx.set_text_align("center");
-                let _ = ctx.fill_text("PAUSE", block_size * self.width as f64 / 2.0, block_size * self.height as f64 / 2.0);
-            }
+            self.draw_cells(ctx);
         }
     }
 
-    #[allow(deprecated)]
+    /// Draws the next pieces, as many as the rules preview, top to bottom.
     pub fn draw_next(&self, canvas_id: &str) {
-        let block_size = 30.0;
-        let colors = vec![
-            "#000", "#ff5555", "#55ff55", "#5555ff", "#ffff55", "#ff55ff", "#55ffff",
-        ];
-        let highlight_colors = vec![
-            "#000", "#ff9999", "#99ff99", "#9999ff", "#ffff99", "#ff99ff", "#99ffff",
-        ];
+        self.draw_preview(canvas_id, &self.previews());
+    }
 
-        let document = match web_sys::window().and_then(|win| win.document()) {
-            Some(doc) => doc,
-            None => {
-                web_sys::console::log_1(&"Failed to access window.document".into());
-                return;
-            }
-        };
+    pub fn draw_hold(&self, canvas_id: &str) {
+        let held: Vec<&Piece> = self.hold_piece.iter().collect();
+        self.draw_preview(canvas_id, &held);
+    }
 
-        let canvas = match document.get_element_by_id(canvas_id) {
-            Some(elem) => match elem.dyn_into::<HtmlCanvasElement>() {
-                Ok(canvas) => canvas,
-                Err(_) => {
-                    web_sys::console::log_1(&"Failed to cast element to HtmlCanvasElement".into());
-                    return;
-                }
-            },
-            None => {
-                web_sys::console::log_1(&format!("Canvas element '{}' not found", canvas_id).into());
-                return;
-            }
+    // Four cells square for one piece; three rows a piece for more.
+    #[allow(deprecated)]
+    fn draw_preview(&self, canvas_id: &str, pieces: &[&Piece]) {
+        let ctx = match context_for_canvas(canvas_id) {
+            Some(ctx) => ctx,
+            None => return,
         };
-
-        let ctx = match canvas.get_context("2d") {
-            Ok(Some(ctx)) => match ctx.dyn_into::<CanvasRenderingContext2d>() {
-                Ok(ctx) => ctx,
-                Err(_) => {
-                    web_sys::console::log_1(&"Failed to cast context to CanvasRenderingContext2d".into());
-                    return;
-                }
-            },
-            Ok(None) => {
-                web_sys::console::log_1(&"Failed to get 2d context".into());
-                return;
-            }
-            Err(_) => {
-                web_sys::console::log_1(&"Error getting canvas context".into());
-                return;
-            }
+        let rows = (3 * pieces.len()).max(4);
+        let scale = match ctx.canvas() {
+            Some(canvas) => self.fit_preview(&canvas, 4, rows).scale(),
+            None => return,
+        };
+        let sprites = match self.sprites_at(scale) {
+            Some(sprites) => sprites,
+            None => return,
         };
+        let _ = ctx.set_transform(scale, 0.0, 0.0, scale, 0.0, 0.0);
 
         // Draw light grey border
-        ctx.set_fill_style(&JsValue::from_str("#d3d3d3"));
-        ctx.fill_rect(-2.0, -2.0, block_size * 4.0 + 4.0, block_size * 4.0 + 4.0);
-
-        // Replace solid black fill with grid background
-        self.draw_background(&ctx, block_size * 4.0, block_size * 4.0, block_size);
-
-        // Draw the next piece
-        for y in 0..self.next_piece.shape.len() {
-            for x in 0..self.next_piece.shape[y].len() {
-                if self.next_piece.shape[y][x] != 0 {
-                    let color_idx = self.next_piece.color as usize;
-                    if color_idx >= colors.len() || color_idx == 0 {
-                        web_sys::console::log_1(&format!("Invalid color index in draw_next: {}", color_idx).into());
-                        return;
+        ctx.set_fill_style(&JsValue::from_str(&self.theme.border));
+        ctx.fill_rect(-2.0, -2.0, BLOCK_SIZE * 4.0 + 4.0, BLOCK_SIZE * rows as f64 + 4.0);
+
+        let background = if self.settings.grid { Tile::Grid } else { Tile::Plain };
+        for y in 0..rows {
+            for x in 0..4 {
+                sprites.draw(&ctx, background, x as f64, y as f64);
+            }
+        }
+
+        // Draw the previewed pieces
+        for (i, piece) in pieces.iter().enumerate() {
+            for (y, row) in piece.shape.iter().enumerate() {
+                for (x, &cell) in row.iter().enumerate() {
+                    if cell != 0 {
+                        sprites.draw(&ctx, Tile::Block(piece.color), x as f64, (3 * i + y) as f64);
                     }
-                    let gradient = ctx.create_linear_gradient(
-                        x as f64 * block_size,
-                        y as f64 * block_size,
-                        (x as f64 + 1.0) * block_size,
-                        (y as f64 + 1.0) * block_size,
-                    );
-                    let _ = gradient.add_color_stop(0.0, colors[color_idx]);
-                    let _ = gradient.add_color_stop(1.0, "#000");
-                    ctx.set_fill_style(&gradient);
-                    ctx.fill_rect(
-                        x as f64 * block_size + 2.0,
-                        y as f64 * block_size + 2.0,
-                        block_size - 4.0,
-                        block_size - 4.0,
-                    );
-                    ctx.set_fill_style(&JsValue::from_str(highlight_colors[color_idx]));
-                    ctx.fill_rect(
-                        x as f64 * block_size + 4.0,
-                        y as f64 * block_size + 4.0,
-                        block_size - 8.0,
-                        block_size - 8.0,
-                    );
                 }
             }
         }
@@ -642,6 +872,11 @@ impl Tetris {
         self.game_over
     }
 
+    /// Starts at 1 and rises every 10 lines.
+    pub fn get_level(&self) -> u32 {
+        self.cleared_lanes / 10 + 1
+    }
+
     pub fn get_cleared_lanes(&self) -> u32 {
         self.cleared_lanes
     }
@@ -663,26 +898,152 @@ impl Tetris {
     }
 }
 
-fn rotate(shape: &Vec<Vec<u8>>, direction: i32) -> Vec<Vec<u8>> {
-    let mut new_shape = vec![vec![0; shape.len()]; shape[0].len()];
-    for y in 0..shape.len() {
-        for x in 0..shape[y].len() {
-            if direction == 1 {
+const GARBAGE_COLOR: u8 = 8; // After the seven piece colors
+
+// Extra garbage by combo length (consecutive clears after the first).
+const COMBO_ATTACK: [u32; 11] = [0, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5];
+
+// Garbage sent for a clear of `lines` rows.
+fn attack_for_clear(lines: u32, tspin: bool, b2b: bool, combo: u32) -> u32 {
+    let base = if tspin {
+        lines * 2
+    } else {
+        match lines {
+            2 => 1,
+            3 => 2,
+            4 => 4,
+            _ => 0,
+        }
+    };
+    base + b2b as u32 + COMBO_ATTACK[(combo as usize).min(COMBO_ATTACK.len() - 1)]
+}
+
+// Three-corner rule: a T whose center has at least three of its four diagonal
+// neighbours blocked (walls and floor count as blocked).
+fn is_tspin(piece: &Piece, board: &[Vec<u8>], width: usize, height: usize) -> bool {
+    if piece.kind != T_KIND {
+        return false;
+    }
+    // The center is the only cell of a T with three neighbours in the piece.
+    let cell = |x: i32, y: i32| {
+        y >= 0 && (y as usize) < piece.shape.len() && x >= 0 && (x as usize) < piece.shape[y as usize].len() && piece.shape[y as usize][x as usize] != 0
+    };
+    let mut center = None;
+    for y in 0..piece.shape.len() as i32 {
+        for x in 0..piece.shape[y as usize].len() as i32 {
+            let neighbours = [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)]
+                .iter()
+                .filter(|&&(nx, ny)| cell(nx, ny))
+                .count();
+            if cell(x, y) && neighbours == 3 {
+                center = Some((piece.x + x, piece.y + y));
+            }
+        }
+    }
+    let (cx, cy) = match center {
+        Some(center) => center,
+        None => return false,
+    };
+    let blocked = [(cx - 1, cy - 1), (cx + 1, cy - 1), (cx - 1, cy + 1), (cx + 1, cy + 1)]
+        .iter()
+        .filter(|&&(x, y)| {
+            x < 0 || x >= width as i32 || y >= height as i32 || (y >= 0 && board[y as usize][x as usize] != 0)
+        })
+        .count();
+    blocked >= 3
+}
+
+struct StateHasher(u64);
+
+impl StateHasher {
+    fn new() -> StateHasher {
+        StateHasher(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.write(&value.to_le_bytes());
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+fn context_for_canvas(canvas_id: &str) -> Option<CanvasRenderingContext2d> {
+    let document = match web_sys::window().and_then(|win| win.document()) {
+        Some(doc) => doc,
+        None => {
+            web_sys::console::log_1(&"Failed to access window.document".into());
+            return None;
+        }
+    };
+
+    let canvas = match document.get_element_by_id(canvas_id) {
+        Some(elem) => match elem.dyn_into::<HtmlCanvasElement>() {
+            Ok(canvas) => canvas,
+            Err(_) => {
+                web_sys::console::log_1(&"Failed to cast element to HtmlCanvasElement".into());
+                return None;
+            }
+        },
+        None => {
+            web_sys::console::log_1(&format!("Canvas element '{}' not found", canvas_id).into());
+            return None;
+        }
+    };
+
+    match canvas.get_context("2d") {
+        Ok(Some(ctx)) => match ctx.dyn_into::<CanvasRenderingContext2d>() {
+            Ok(ctx) => Some(ctx),
+            Err(_) => {
+                web_sys::console::log_1(&"Failed to cast context to CanvasRenderingContext2d".into());
+                None
+            }
+        },
+        Ok(None) => {
+            web_sys::console::log_1(&"Failed to get 2d context".into());
+            None
+        }
+        Err(_) => {
+            web_sys::console::log_1(&"Error getting canvas context".into());
+            None
+        }
+    }
+}
+
+// Turns `shape` clockwise by `direction` quarter turns (negative turns
+// counter-clockwise, 2 is half a turn).
+fn rotate(shape: &[Vec<u8>], direction: i32) -> Vec<Vec<u8>> {
+    let mut shape = shape.to_vec();
+    for _ in 0..direction.rem_euclid(4) {
+        let mut new_shape = vec![vec![0; shape.len()]; shape[0].len()];
+        for y in 0..shape.len() {
+            for x in 0..shape[y].len() {
                 new_shape[x][shape.len() - 1 - y] = shape[y][x];
-            } else {
-                new_shape[shape[0].len() - 1 - x][y] = shape[y][x];
             }
         }
+        shape = new_shape;
     }
-    new_shape
+    shape
 }
 
 fn collides(piece: &Piece, board: &[Vec<u8>], width: usize, height: usize) -> bool {
-    for y in 0..piece.shape.len() {
-        for x in 0..piece.shape[y].len() {
-            if piece.shape[y][x] != 0 {
-                let board_x = piece.x + x as i32;
-                let board_y = piece.y + y as i32;
+    shape_collides(&piece.shape, piece.x, piece.y, board, width, height)
+}
+
+fn shape_collides(shape: &[Vec<u8>], x: i32, y: i32, board: &[Vec<u8>], width: usize, height: usize) -> bool {
+    for (shape_y, row) in shape.iter().enumerate() {
+        for (shape_x, &cell) in row.iter().enumerate() {
+            if cell != 0 {
+                let board_x = x + shape_x as i32;
+                let board_y = y + shape_y as i32;
                 if board_x < 0
                     || board_x >= width as i32
                     || board_y >= height as i32
@@ -701,38 +1062,21 @@ mod tests {
     use super::*;
 
     fn setup_tetris() -> Tetris {
-        let width = 10;
-        let height = 20;
-        let board = vec![vec![0; width]; height];
         let shapes = vec![
             vec![vec![1, 1, 1, 1]], // I
             vec![vec![1, 1], vec![1, 1]], // O
             vec![vec![1, 1, 1], vec![0, 1, 0]], // T
         ];
         Tetris {
-            board,
-            width,
-            height,
-            current_piece: None,
-            next_piece: Tetris::create_piece(&shapes, Some(0)),
-            score: 0,
-            game_over: false,
-            paused: false,
-            ctx: None,
-            cleared_lanes: 0,
-            tetris_count: 0,
-            triple_count: 0,
-            double_count: 0,
-            single_count: 0,
-            clearing_lines: Vec::new(),
-            clearing_animation_progress: 0.0,
+            next_piece: Tetris::create_piece(&shapes, 0),
+            ..Tetris::with_rng(Rng::new(0))
         }
     }
 
     #[test]
     fn test_create_piece() {
         let shapes = vec![vec![vec![1, 1, 1, 1]]];
-        let piece = Tetris::create_piece(&shapes, Some(0));
+        let piece = Tetris::create_piece(&shapes, 0);
         assert_eq!(piece.shape, vec![vec![1, 1, 1, 1]]);
         assert_eq!(piece.x, 2);
         assert_eq!(piece.y, 0);
@@ -747,6 +1091,8 @@ mod tests {
             x: 4,
             y: 0,
             color: 1,
+            kind: 0,
+            rotation: 0,
         });
         tetris.move_left();
         assert_eq!(tetris.current_piece.as_ref().unwrap().x, 3);
@@ -766,6 +1112,8 @@ mod tests {
             x: 4,
             y: 18,
             color: 1,
+            kind: 0,
+            rotation: 0,
         });
         assert!(tetris.move_down());
         assert_eq!(tetris.current_piece.as_ref().unwrap().y, 19);
@@ -783,6 +1131,8 @@ mod tests {
             x: 4,
             y: 0,
             color: 1,
+            kind: 0,
+            rotation: 0,
         });
         tetris.rotate_right();
         let expected_shape = vec![vec![0, 1], vec![1, 1], vec![0, 1]];
@@ -810,6 +1160,56 @@ mod tests {
         assert_eq!(tetris.board[0], vec![0; 10]);
     }
 
+    #[test]
+    fn test_tspin_double() {
+        let mut tetris = setup_tetris();
+        tetris.board[19] = vec![1, 1, 1, 1, 0, 1, 1, 1, 1, 1];
+        tetris.board[18] = vec![1, 1, 1, 0, 0, 0, 1, 1, 1, 1];
+        tetris.board[17][3] = 1;
+        tetris.current_piece = Some(Tetris::create_piece(&default_shapes(), T_KIND));
+        tetris.current_piece.as_mut().unwrap().x = 3;
+        tetris.current_piece.as_mut().unwrap().y = 18;
+        tetris.last_move_rotation = true;
+        tetris.drop();
+        assert_eq!(tetris.clearing_lines, vec![18, 19]);
+        assert_eq!(tetris.stats.attack, 4);
+        assert_eq!(tetris.b2b_chain, 1);
+    }
+
+    #[test]
+    fn test_tspin_without_lines() {
+        let mut tetris = Tetris::with_seed(0);
+        tetris.set_rule_preset(RulePreset::Guideline);
+        tetris.start();
+        tetris.board[19] = vec![1, 1, 1, 1, 0, 1, 1, 1, 1, 0];
+        tetris.board[18] = vec![1, 1, 1, 0, 0, 0, 1, 1, 1, 0];
+        tetris.board[17][3] = 1;
+        tetris.current_piece = Some(Tetris::create_piece(&default_shapes(), T_KIND));
+        tetris.current_piece.as_mut().unwrap().x = 3;
+        tetris.current_piece.as_mut().unwrap().y = 18;
+        tetris.last_move_rotation = true;
+        tetris.drop();
+        assert!(tetris.clearing_lines.is_empty());
+        assert_eq!(tetris.get_score(), 400);
+        assert_eq!(tetris.b2b_chain, 0);
+    }
+
+    #[test]
+    fn test_combo() {
+        let mut tetris = setup_tetris();
+        for _ in 0..3 {
+            tetris.board[19] = vec![1; 10];
+            tetris.clear_lines();
+            tetris.update_clearing_animation(0.3);
+        }
+        assert_eq!(tetris.combo, 3);
+        assert_eq!(tetris.stats.max_combo, 2);
+        // Singles send nothing on their own; the combo adds 1 + 1.
+        assert_eq!(tetris.stats.attack, 2);
+        tetris.clear_lines();
+        assert_eq!(tetris.combo, 0);
+    }
+
     #[test]
     fn test_game_over() {
         let mut tetris = setup_tetris();
@@ -821,6 +1221,8 @@ mod tests {
             x: 8,
             y: 0,
             color: 1,
+            kind: 0,
+            rotation: 0,
         });
         tetris.drop();
         assert!(tetris.is_game_over());
@@ -0,0 +1,251 @@
+//! Reachable-placement move generator shared by the bot, hints and
+//! analysis tools. A breadth-first search over moves, rotations and single-row
+//! soft drops from the spawn position finds every distinct resting position,
+//! including tucks and spins a plain hard drop cannot reach, each with the
+//! shortest input path that reaches it.
+
+use std::collections::{HashSet, VecDeque};
+
+use crate::rotation::Kick180;
+use crate::rotation_system::{turn, Rotation};
+use crate::{default_shapes, shape_collides, Action, Piece, Tetris};
+
+/// A resting position of a piece and the inputs that reach it from spawn.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Placement {
+    pub kind: usize,
+    pub x: i32,
+    pub y: i32,
+    pub rotation: u8, // Quarter turns clockwise from spawn
+    pub shape: Vec<Vec<u8>>,
+    /// Shortest input sequence, always ending in `HardDrop`.
+    pub actions: Vec<Action>,
+    /// Position and rotation (x, y, rotation) before each of `actions`.
+    pub(crate) states: Vec<(i32, i32, u8)>,
+}
+
+impl Placement {
+    pub(crate) fn piece(&self) -> Piece {
+        Piece {
+            x: self.x,
+            y: self.y,
+            shape: self.shape.clone(),
+            rotation: self.rotation,
+            ..Tetris::create_piece(&default_shapes(), self.kind)
+        }
+    }
+
+    /// Whether the path needs soft drops, i.e. the placement is a tuck or spin.
+    pub fn is_tuck(&self) -> bool {
+        self.actions.contains(&Action::SoftDrop)
+    }
+}
+
+/// Every distinct resting position of a piece of `kind` (an index into the
+/// standard piece list) spawned on `board` and turned by `rotation`, the
+/// game's rotation system, with half turns kicked by `kick_180`, in order of
+/// increasing path length. Empty if the spawn position is already blocked.
+pub fn placements(board: &[Vec<u8>], kind: usize, rotation: Rotation, kick_180: Kick180) -> Vec<Placement> {
+    let spawn = rotation.system().spawn(kind, board[0].len());
+    placements_from(board, &spawn, rotation, kick_180)
+}
+
+/// The same, for `start` wherever it is now, e.g. a piece that has already
+/// fallen or moved. Its shape must be the system's shape for its rotation.
+pub(crate) fn placements_from(board: &[Vec<u8>], start: &Piece, rotation: Rotation, kick_180: Kick180) -> Vec<Placement> {
+    let height = board.len();
+    let width = board[0].len();
+    let system = rotation.system();
+    let kind = start.kind;
+    // Each state is a position and one of four precomputed shapes; turns
+    // may also kick the position.
+    let shapes: Vec<Vec<Vec<u8>>> = (0..4).map(|rotation| system.shape(kind, rotation)).collect();
+    let turned = |x: i32, y: i32, rotation: u8, direction: i32| {
+        let piece = Piece {
+            x,
+            y,
+            rotation,
+            shape: shapes[rotation as usize].clone(),
+            ..start.clone()
+        };
+        turn(system, kick_180, &piece, direction, board).map(|piece| (piece.x, piece.y, piece.rotation))
+    };
+    let blocked = |x: i32, y: i32, rotation: u8| shape_collides(&shapes[rotation as usize], x, y, board, width, height);
+    if blocked(start.x, start.y, start.rotation) {
+        return Vec::new();
+    }
+
+    let mut nodes = vec![Node {
+        x: start.x,
+        y: start.y,
+        rotation: start.rotation,
+        parent: 0,
+        action: None,
+    }];
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::new();
+    let mut landed = HashSet::new();
+    let mut result = Vec::new();
+    seen.insert((start.x, start.y, start.rotation));
+    queue.push_back(0);
+
+    while let Some(index) = queue.pop_front() {
+        let Node { x, y, rotation, .. } = nodes[index];
+        // States come out in order of path length, so the first state that
+        // hard drops onto a resting position has its shortest path.
+        let mut rest_y = y;
+        while !blocked(x, rest_y + 1, rotation) {
+            rest_y += 1;
+        }
+        let shape = &shapes[rotation as usize];
+        if landed.insert(cells(shape, x, rest_y)) {
+            let (mut actions, mut states) = path_to(&nodes, index);
+            actions.push(Action::HardDrop);
+            states.push((x, y, rotation));
+            result.push(Placement {
+                kind,
+                x,
+                y: rest_y,
+                rotation,
+                shape: shape.clone(),
+                actions,
+                states,
+            });
+        }
+
+        let mut moves = vec![
+            (x - 1, y, rotation, Action::MoveLeft),
+            (x + 1, y, rotation, Action::MoveRight),
+            (x, y + 1, rotation, Action::SoftDrop),
+        ];
+        for (direction, action) in [(-1, Action::RotateLeft), (1, Action::RotateRight), (2, Action::Rotate180)] {
+            if let Some((x, y, rotation)) = turned(x, y, rotation, direction) {
+                moves.insert(moves.len() - 1, (x, y, rotation, action));
+            }
+        }
+        for (x, y, rotation, action) in moves {
+            if seen.insert((x, y, rotation)) && !blocked(x, y, rotation) {
+                nodes.push(Node {
+                    x,
+                    y,
+                    rotation,
+                    parent: index,
+                    action: Some(action),
+                });
+                queue.push_back(nodes.len() - 1);
+            }
+        }
+    }
+    result
+}
+
+/// The board cells a piece covers, to tell placements apart.
+pub(crate) fn cells(shape: &[Vec<u8>], x: i32, y: i32) -> Vec<(i32, i32)> {
+    let mut cells = Vec::new();
+    for (dy, row) in shape.iter().enumerate() {
+        for (dx, &cell) in row.iter().enumerate() {
+            if cell != 0 {
+                cells.push((x + dx as i32, y + dy as i32));
+            }
+        }
+    }
+    cells
+}
+
+#[derive(Clone, Copy)]
+struct Node {
+    x: i32,
+    y: i32,
+    rotation: u8,
+    parent: usize,
+    action: Option<Action>, // Input that led here from the parent
+}
+
+// The inputs that lead to `index`, and the state each is pressed in.
+fn path_to(nodes: &[Node], mut index: usize) -> (Vec<Action>, Vec<(i32, i32, u8)>) {
+    let mut actions = Vec::new();
+    let mut states = Vec::new();
+    while let Some(action) = nodes[index].action {
+        index = nodes[index].parent;
+        let Node { x, y, rotation, .. } = nodes[index];
+        actions.push(action);
+        states.push((x, y, rotation));
+    }
+    actions.reverse();
+    states.reverse();
+    (actions, states)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_board_placements() {
+        let board = vec![vec![0; 10]; 20];
+        // T: two flat orientations with 8 columns, two upright ones with 9.
+        assert_eq!(placements(&board, 2, Rotation::Classic, Kick180::SrsPlus).len(), 34);
+        // O: one orientation, 9 columns.
+        assert_eq!(placements(&board, 1, Rotation::Classic, Kick180::SrsPlus).len(), 9);
+        assert!(placements(&board, 2, Rotation::Classic, Kick180::SrsPlus).iter().all(|placement| !placement.is_tuck()));
+    }
+
+    #[test]
+    fn test_finds_tuck() {
+        let mut board = vec![vec![0; 10]; 20];
+        // A roof over columns 0-2 leaves a pocket only reachable by sliding in.
+        board[17] = vec![1, 1, 1, 0, 0, 0, 0, 0, 0, 0];
+        let all = placements(&board, 1, Rotation::Classic, Kick180::SrsPlus);
+        let tuck = all
+            .iter()
+            .find(|placement| placement.x == 0 && placement.y == 18)
+            .expect("O fits under the roof");
+        assert!(tuck.is_tuck());
+        assert_eq!(tuck.actions.last(), Some(&Action::HardDrop));
+
+        // Replaying the path on the engine lands exactly there.
+        let mut game = Tetris::with_seed(0);
+        game.start();
+        game.board = board;
+        game.current_piece = Some(Tetris::create_piece(&default_shapes(), 1));
+        for &action in &tuck.actions {
+            game.apply_action(action);
+        }
+        assert_eq!(game.board[18][0], 2);
+        assert_eq!(game.board[19][1], 2);
+    }
+
+    #[test]
+    fn test_follows_the_rotation_system() {
+        // The path to an upright SRS I against the wall replays on a game
+        // turning by SRS.
+        let mut game = Tetris::with_seed(0);
+        game.set_rule_preset(crate::RulePreset::Guideline);
+        game.start();
+        game.current_piece = Some(game.new_piece(0));
+        let upright = placements(&game.board, 0, Rotation::Srs, Kick180::SrsPlus)
+            .into_iter()
+            .find(|placement| cells(&placement.shape, placement.x, placement.y).iter().all(|&(x, _)| x == 0))
+            .expect("an upright I fits against the wall");
+        for &action in &upright.actions {
+            game.apply_action(action);
+        }
+        assert!((16..20).all(|y| game.board[y][0] == 1));
+    }
+
+    #[test]
+    fn test_turns_half_way_in_one_press() {
+        let board = vec![vec![0; 10]; 20];
+        let flipped = placements(&board, 2, Rotation::Srs, Kick180::SrsPlus)
+            .into_iter()
+            .find(|placement| placement.rotation == 2 && placement.x == 3)
+            .expect("a T fits flat side up");
+        assert_eq!(flipped.actions, vec![Action::Rotate180, Action::HardDrop]);
+    }
+
+    #[test]
+    fn test_blocked_spawn() {
+        let board = vec![vec![1; 10]; 20];
+        assert!(placements(&board, 0, Rotation::Classic, Kick180::SrsPlus).is_empty());
+    }
+}
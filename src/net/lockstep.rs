@@ -0,0 +1,394 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use super::protocol::{Message, PROTOCOL_VERSION};
+use crate::rng::Rng;
+use crate::{Action, Tetris};
+
+/// Frames between a local input and the frame it is applied on. Gives the
+/// remote inputs time to arrive before the simulation needs them.
+pub const DEFAULT_INPUT_DELAY: u32 = 3;
+/// How often (in frames) each side publishes a checksum of its own board.
+pub const CHECKSUM_INTERVAL: u32 = 30;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionState {
+    /// Waiting for the peer to join and send its seed.
+    Connecting,
+    Playing,
+    /// The simulations disagreed on the given frame.
+    Desynced(u32),
+    /// The peer left before the match finished.
+    Abandoned,
+    /// The peer plays with different rules or settings; the match never starts.
+    Mismatched,
+    Finished,
+}
+
+/// Deterministic lockstep versus match. Both machines simulate both boards;
+/// only inputs cross the wire. A frame is simulated once the inputs of both
+/// players for it are known, and the hashes each side publishes are compared
+/// against the local simulation of the peer's board to catch desyncs.
+pub struct LockstepSession {
+    local: Tetris,
+    remote: Tetris,
+    state: SessionState,
+    frame: u32,
+    input_delay: u32,
+    next_send_frame: u32,
+    local_seed: u64,
+    seed_sent: bool,
+    remote_seed: Option<u64>,
+    // One hole sequence per sender, so both machines draw holes in the same
+    // order no matter which board they treat as local.
+    local_garbage_rng: Rng,
+    remote_garbage_rng: Rng,
+    pending_local: Vec<Action>,
+    local_inputs: BTreeMap<u32, Vec<Action>>,
+    remote_inputs: BTreeMap<u32, Vec<Action>>,
+    // Our simulation of the peer's board, waiting for the peer's own report.
+    remote_hashes: BTreeMap<u32, u64>,
+    remote_garbage: BTreeMap<u32, u32>,
+    reported_hashes: BTreeMap<u32, u64>,
+    reported_garbage: BTreeMap<u32, u32>,
+    outbox: VecDeque<Message>,
+}
+
+impl LockstepSession {
+    pub fn new(local: Tetris, remote: Tetris, room: &str, name: &str, local_seed: u64) -> LockstepSession {
+        let mut outbox = VecDeque::new();
+        outbox.push_back(Message::Join {
+            room: room.to_string(),
+            name: name.to_string(),
+            version: PROTOCOL_VERSION,
+        });
+        LockstepSession {
+            local,
+            remote,
+            state: SessionState::Connecting,
+            frame: 0,
+            input_delay: DEFAULT_INPUT_DELAY,
+            next_send_frame: DEFAULT_INPUT_DELAY,
+            local_seed,
+            seed_sent: false,
+            remote_seed: None,
+            local_garbage_rng: Rng::new(local_seed),
+            remote_garbage_rng: Rng::new(0),
+            pending_local: Vec::new(),
+            local_inputs: BTreeMap::new(),
+            remote_inputs: BTreeMap::new(),
+            remote_hashes: BTreeMap::new(),
+            remote_garbage: BTreeMap::new(),
+            reported_hashes: BTreeMap::new(),
+            reported_garbage: BTreeMap::new(),
+            outbox,
+        }
+    }
+
+    pub fn state(&self) -> SessionState {
+        self.state
+    }
+
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    pub fn local(&self) -> &Tetris {
+        &self.local
+    }
+
+    pub fn remote(&self) -> &Tetris {
+        &self.remote
+    }
+
+    pub fn local_mut(&mut self) -> &mut Tetris {
+        &mut self.local
+    }
+
+    pub fn remote_mut(&mut self) -> &mut Tetris {
+        &mut self.remote
+    }
+
+    /// Records a local action; it is sent with the next outgoing frame.
+    pub fn queue_input(&mut self, action: Action) {
+        if self.state == SessionState::Playing {
+            self.pending_local.push(action);
+        }
+    }
+
+    pub fn poll_outgoing(&mut self) -> Option<Message> {
+        self.outbox.pop_front()
+    }
+
+    pub fn receive(&mut self, message: Message) {
+        match message {
+            // Seeds are only exchanged once both seats are taken, otherwise
+            // the relay would have nobody to forward ours to.
+            Message::PeerJoined { .. } => {
+                if self.state == SessionState::Connecting && !self.seed_sent {
+                    self.seed_sent = true;
+                    self.outbox.push_back(Message::Seed {
+                        seed: self.local_seed,
+                        config: self.local.config_hash(),
+                    });
+                }
+            }
+            Message::Seed { seed, config } => {
+                if self.state != SessionState::Connecting {
+                    return;
+                }
+                if config != self.local.config_hash() {
+                    self.state = SessionState::Mismatched;
+                } else if self.remote_seed.is_none() {
+                    self.remote_seed = Some(seed);
+                    self.begin();
+                }
+            }
+            Message::Inputs { frame, actions } => {
+                self.remote_inputs.insert(frame, actions);
+            }
+            Message::Garbage { frame, lines } => {
+                self.reported_garbage.insert(frame, lines);
+                self.verify();
+            }
+            Message::Checksum { frame, hash } => {
+                self.reported_hashes.insert(frame, hash);
+                self.verify();
+            }
+            Message::Desync { frame } => self.mark_desync(frame),
+            Message::Leave => {
+                if self.state == SessionState::Playing || self.state == SessionState::Connecting {
+                    self.state = SessionState::Abandoned;
+                }
+            }
+            Message::Rejected { .. } => self.state = SessionState::Abandoned,
+            Message::Join { .. } | Message::Joined { .. } => {}
+        }
+    }
+
+    fn begin(&mut self) {
+        let remote_seed = match self.remote_seed {
+            Some(seed) => seed,
+            None => return,
+        };
+        // XOR is symmetric, so both sides arrive at the same match seed.
+        let seed = self.local_seed ^ remote_seed;
+        self.local.set_seed(seed);
+        self.remote.set_seed(seed);
+        self.local.start();
+        self.remote.start();
+        self.remote_garbage_rng = Rng::new(remote_seed);
+        // The first frames are inside the input delay and have no inputs.
+        for frame in 0..self.input_delay {
+            self.local_inputs.insert(frame, Vec::new());
+            self.remote_inputs.insert(frame, Vec::new());
+        }
+        self.state = SessionState::Playing;
+    }
+
+    /// Called once per local frame (60 Hz). Sends this frame's local inputs and
+    /// simulates as many frames as both players' inputs allow.
+    pub fn tick(&mut self) {
+        if self.state != SessionState::Playing {
+            return;
+        }
+        if self.next_send_frame <= self.frame + self.input_delay {
+            let actions = std::mem::take(&mut self.pending_local);
+            self.local_inputs.insert(self.next_send_frame, actions.clone());
+            self.outbox.push_back(Message::Inputs {
+                frame: self.next_send_frame,
+                actions,
+            });
+            self.next_send_frame += 1;
+        }
+        while self.state == SessionState::Playing && self.advance() {}
+    }
+
+    fn advance(&mut self) -> bool {
+        let frame = self.frame;
+        if !self.local_inputs.contains_key(&frame) || !self.remote_inputs.contains_key(&frame) {
+            return false;
+        }
+        let local_actions = self.local_inputs.remove(&frame).unwrap_or_default();
+        let remote_actions = self.remote_inputs.remove(&frame).unwrap_or_default();
+        for action in local_actions {
+            self.local.apply_action(action);
+        }
+        for action in remote_actions {
+            self.remote.apply_action(action);
+        }
+        self.local.step();
+        self.remote.step();
+
+        // Local garbage is routed to the peer's board and the other way round.
+        let local_attack = self.local.take_outgoing_garbage();
+        let remote_attack = self.remote.take_outgoing_garbage();
+        if local_attack > 0 {
+            let hole = self.local_garbage_rng.next_below(self.remote.width);
+            self.remote.queue_garbage(local_attack, hole);
+            self.outbox.push_back(Message::Garbage {
+                frame,
+                lines: local_attack,
+            });
+        }
+        if remote_attack > 0 {
+            let hole = self.remote_garbage_rng.next_below(self.local.width);
+            self.local.queue_garbage(remote_attack, hole);
+            self.remote_garbage.insert(frame, remote_attack);
+        }
+
+        if frame.is_multiple_of(CHECKSUM_INTERVAL) {
+            self.outbox.push_back(Message::Checksum {
+                frame,
+                hash: self.local.state_hash(),
+            });
+            self.remote_hashes.insert(frame, self.remote.state_hash());
+        }
+
+        self.frame += 1;
+        if self.local.is_game_over() || self.remote.is_game_over() {
+            self.state = SessionState::Finished;
+        }
+        self.verify();
+        true
+    }
+
+    // Compares what the peer reported against our simulation of its board.
+    // Reports for frames we have not simulated yet stay queued.
+    fn verify(&mut self) {
+        let simulated = self.frame;
+        let reported: Vec<(u32, u64)> = self
+            .reported_hashes
+            .range(..simulated)
+            .map(|(&frame, &hash)| (frame, hash))
+            .collect();
+        for (frame, hash) in reported {
+            self.reported_hashes.remove(&frame);
+            if self.remote_hashes.remove(&frame) != Some(hash) {
+                self.mark_desync(frame);
+                return;
+            }
+        }
+        let reported: Vec<(u32, u32)> = self
+            .reported_garbage
+            .range(..simulated)
+            .map(|(&frame, &lines)| (frame, lines))
+            .collect();
+        for (frame, lines) in reported {
+            self.reported_garbage.remove(&frame);
+            if self.remote_garbage.remove(&frame) != Some(lines) {
+                self.mark_desync(frame);
+                return;
+            }
+        }
+    }
+
+    fn mark_desync(&mut self, frame: u32) {
+        if let SessionState::Desynced(_) = self.state {
+            return;
+        }
+        self.state = SessionState::Desynced(frame);
+        self.outbox.push_back(Message::Desync { frame });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::{RulePreset, RuleSet};
+
+    fn session(name: &str, seed: u64) -> LockstepSession {
+        LockstepSession::new(Tetris::new_fallback(), Tetris::new_fallback(), "room", name, seed)
+    }
+
+    fn connect(a: &mut LockstepSession, b: &mut LockstepSession) {
+        a.receive(Message::PeerJoined { name: "b".to_string() });
+        b.receive(Message::PeerJoined { name: "a".to_string() });
+        deliver(a, b);
+        deliver(b, a);
+    }
+
+    // Stands in for the relay: passes everything except relay-only messages.
+    fn deliver(from: &mut LockstepSession, to: &mut LockstepSession) {
+        while let Some(message) = from.poll_outgoing() {
+            if !matches!(message, Message::Join { .. }) {
+                to.receive(message);
+            }
+        }
+    }
+
+    #[test]
+    fn test_peers_stay_in_sync() {
+        let mut a = session("a", 1234);
+        let mut b = session("b", 5678);
+        assert_eq!(a.state(), SessionState::Connecting);
+        connect(&mut a, &mut b);
+        assert_eq!(a.state(), SessionState::Playing);
+        assert_eq!(b.state(), SessionState::Playing);
+
+        let moves = [Action::MoveLeft, Action::RotateRight, Action::HardDrop];
+        for tick in 0..600 {
+            if tick % 20 == 0 {
+                a.queue_input(moves[tick / 20 % 3]);
+            }
+            if tick % 15 == 0 {
+                b.queue_input(moves[(tick / 15 + 1) % 3]);
+            }
+            a.tick();
+            b.tick();
+            deliver(&mut a, &mut b);
+            deliver(&mut b, &mut a);
+        }
+        // Both sides topped out one board on the same frame.
+        assert_eq!(a.state(), SessionState::Finished);
+        assert_eq!(b.state(), SessionState::Finished);
+        assert_eq!(a.frame(), b.frame());
+        assert_eq!(a.local().state_hash(), b.remote().state_hash());
+        assert_eq!(a.remote().state_hash(), b.local().state_hash());
+    }
+
+    #[test]
+    fn test_waits_for_remote_inputs() {
+        let mut a = session("a", 1);
+        let mut b = session("b", 2);
+        connect(&mut a, &mut b);
+        for _ in 0..10 {
+            a.tick();
+        }
+        // Only the frames covered by the input delay can run without the peer.
+        assert_eq!(a.frame(), DEFAULT_INPUT_DELAY);
+    }
+
+    #[test]
+    fn test_detects_desync() {
+        let mut a = session("a", 1);
+        let mut b = session("b", 2);
+        connect(&mut a, &mut b);
+        // Corrupt b's own board behind the session's back.
+        b.local_mut().board[19][0] = 1;
+        for _ in 0..40 {
+            a.tick();
+            b.tick();
+            deliver(&mut a, &mut b);
+            deliver(&mut b, &mut a);
+        }
+        assert!(matches!(a.state(), SessionState::Desynced(0)));
+    }
+
+    #[test]
+    fn test_refuses_different_settings() {
+        let mut a = session("a", 1);
+        let mut b = session("b", 2);
+        let mut settings = b.local().get_settings();
+        settings.are = 0.1;
+        b.local_mut().set_settings(settings);
+        connect(&mut a, &mut b);
+        assert_eq!(a.state(), SessionState::Mismatched);
+        assert_eq!(b.state(), SessionState::Mismatched);
+
+        let mut a = session("a", 1);
+        let mut b = session("b", 2);
+        b.local_mut().set_rules(&RuleSet::preset(RulePreset::Guideline));
+        connect(&mut a, &mut b);
+        assert_eq!(a.state(), SessionState::Mismatched);
+    }
+}
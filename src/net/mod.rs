@@ -0,0 +1,99 @@
+//! Versus play over the network. The transport is left to the host page: it
+//! forwards the strings from `poll_message` to a WebSocket connected to the
+//! relay (see the `relay` crate) and hands incoming frames to `receive`.
+
+mod lockstep;
+mod protocol;
+
+pub use lockstep::{LockstepSession, SessionState, CHECKSUM_INTERVAL, DEFAULT_INPUT_DELAY};
+pub use protocol::{Message, PROTOCOL_VERSION};
+
+use wasm_bindgen::prelude::*;
+
+use crate::rng::Rng;
+use crate::{Action, Tetris};
+
+#[wasm_bindgen]
+pub struct VersusSession {
+    session: LockstepSession,
+}
+
+#[wasm_bindgen]
+impl VersusSession {
+    #[wasm_bindgen(constructor)]
+    pub fn new(local_canvas_id: &str, remote_canvas_id: &str, room: &str, name: &str) -> VersusSession {
+        let seed = Rng::from_entropy().next_u64();
+        VersusSession {
+            session: LockstepSession::new(
+                Tetris::new(local_canvas_id),
+                Tetris::new(remote_canvas_id),
+                room,
+                name,
+                seed,
+            ),
+        }
+    }
+
+    pub fn input(&mut self, action: Action) {
+        self.session.queue_input(action);
+    }
+
+    /// Feeds one message received from the relay. Returns false if it could
+    /// not be parsed.
+    pub fn receive(&mut self, text: &str) -> bool {
+        match Message::decode(text) {
+            Some(message) => {
+                self.session.receive(message);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Next message to send to the relay, if any.
+    pub fn poll_message(&mut self) -> Option<String> {
+        self.session.poll_outgoing().map(|message| message.encode())
+    }
+
+    pub fn tick(&mut self) {
+        self.session.tick();
+    }
+
+    pub fn draw(&self) {
+        self.session.local().draw();
+        self.session.remote().draw();
+    }
+
+    pub fn get_frame(&self) -> u32 {
+        self.session.frame()
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.session.state() == SessionState::Playing
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.session.state() == SessionState::Finished
+    }
+
+    pub fn is_abandoned(&self) -> bool {
+        self.session.state() == SessionState::Abandoned
+    }
+
+    /// The peer's rules or settings differ from ours, so the match was refused.
+    pub fn is_mismatched(&self) -> bool {
+        self.session.state() == SessionState::Mismatched
+    }
+
+    /// Frame the simulations diverged on, if a desync was detected.
+    pub fn desync_frame(&self) -> Option<u32> {
+        match self.session.state() {
+            SessionState::Desynced(frame) => Some(frame),
+            _ => None,
+        }
+    }
+
+    pub fn local_won(&self) -> bool {
+        self.session.remote().is_game_over() && !self.session.local().is_game_over()
+    }
+}
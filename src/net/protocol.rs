@@ -0,0 +1,63 @@
+use serde::{Deserialize, Serialize};
+
+use crate::Action;
+
+/// Bumped whenever a message changes shape; peers on different versions are
+/// refused at join time.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Every message exchanged between a client and the relay. Messages travel as
+/// one JSON object per WebSocket text frame, tagged by `type`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Message {
+    /// First message from a client: asks the relay for a seat in `room`.
+    Join { room: String, name: String, version: u32 },
+    /// Relay reply to `Join` with the seat (0 or 1) the client got.
+    Joined { player: u8 },
+    /// Relay notice that the other seat has been taken.
+    PeerJoined { name: String },
+    /// Relay notice that the join was refused (room full, bad version, ...).
+    Rejected { reason: String },
+    /// Each side's half of the match seed; both halves are combined. `config`
+    /// hashes the sender's rules and settings, which must match our own.
+    Seed { seed: u64, config: u64 },
+    /// The actions a player performs on `frame`. Sent for every frame, even
+    /// when empty, so the peer knows it may advance.
+    Inputs { frame: u32, actions: Vec<Action> },
+    /// Garbage the sender's board produced on `frame`.
+    Garbage { frame: u32, lines: u32 },
+    /// Hash of the sender's own board after `frame`.
+    Checksum { frame: u32, hash: u64 },
+    /// The sender found the simulations disagreeing at `frame`.
+    Desync { frame: u32 },
+    /// The peer disconnected or quit.
+    Leave,
+}
+
+impl Message {
+    pub fn encode(&self) -> String {
+        serde_json::to_string(self).expect("messages always serialize")
+    }
+
+    pub fn decode(text: &str) -> Option<Message> {
+        serde_json::from_str(text).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let message = Message::Inputs {
+            frame: 12,
+            actions: vec![Action::MoveLeft, Action::HardDrop],
+        };
+        let text = message.encode();
+        assert!(text.contains("\"type\":\"inputs\""));
+        assert_eq!(Message::decode(&text), Some(message));
+        assert_eq!(Message::decode("not json"), None);
+    }
+}
@@ -0,0 +1,212 @@
+//! Particles for hard drops and line clears. They are purely visual: they
+//! draw from their own seeded generator, so the piece sequence and the
+//! state hash never depend on them, and the same seed and inputs always
+//! give the same particles.
+
+use crate::rng::Rng;
+use crate::{Piece, Tetris};
+
+const MAX_PARTICLES: usize = 400;
+const GRAVITY: f64 = 30.0; // Cells per second squared
+const SEED_SALT: u64 = 0x5041_5254_4943_4C45;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Particle {
+    pub(crate) x: f64, // Center, in cells
+    pub(crate) y: f64,
+    vx: f64, // Cells per second
+    vy: f64,
+    age: f64,
+    life: f64, // Seconds it lasts
+    pub(crate) color: u8,
+    pub(crate) size: f64, // In cells
+}
+
+impl Particle {
+    /// Fades out over its life.
+    pub(crate) fn opacity(&self) -> f64 {
+        (1.0 - self.age / self.life).clamp(0.0, 1.0)
+    }
+}
+
+/// Spawns `count` particles at random points of a rectangle of cells.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Emitter {
+    pub(crate) area: [f64; 4], // x, y, width, height
+    pub(crate) velocity: (f64, f64),
+    pub(crate) spread: (f64, f64), // Random extra velocity, up to half either way
+    pub(crate) life: f64,
+    pub(crate) size: f64,
+    pub(crate) color: u8,
+    pub(crate) count: usize,
+}
+
+/// A fixed-size pool of live particles. Emitting into a full pool drops
+/// the new particles.
+#[derive(Clone, Debug)]
+pub(crate) struct Particles {
+    live: Vec<Particle>,
+    rng: Rng,
+}
+
+impl Particles {
+    pub(crate) fn new(seed: u64) -> Particles {
+        Particles {
+            live: Vec::with_capacity(MAX_PARTICLES),
+            rng: Rng::new(seed ^ SEED_SALT),
+        }
+    }
+
+    // Uniform value in `-0.5..0.5`.
+    fn jitter(&mut self) -> f64 {
+        (self.rng.next_u64() >> 11) as f64 / (1u64 << 53) as f64 - 0.5
+    }
+
+    pub(crate) fn emit(&mut self, emitter: &Emitter) {
+        let [x, y, width, height] = emitter.area;
+        for _ in 0..emitter.count.min(MAX_PARTICLES - self.live.len()) {
+            let particle = Particle {
+                x: x + width * (self.jitter() + 0.5),
+                y: y + height * (self.jitter() + 0.5),
+                vx: emitter.velocity.0 + emitter.spread.0 * self.jitter(),
+                vy: emitter.velocity.1 + emitter.spread.1 * self.jitter(),
+                age: 0.0,
+                life: emitter.life * (0.75 + self.jitter() / 2.0),
+                color: emitter.color,
+                size: emitter.size,
+            };
+            self.live.push(particle);
+        }
+    }
+
+    pub(crate) fn update(&mut self, delta_time: f64) {
+        for particle in &mut self.live {
+            particle.vy += GRAVITY * delta_time;
+            particle.x += particle.vx * delta_time;
+            particle.y += particle.vy * delta_time;
+            particle.age += delta_time;
+        }
+        self.live.retain(|particle| particle.age < particle.life);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.live.clear();
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Particle> {
+        self.live.iter()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.live.is_empty()
+    }
+}
+
+impl Tetris {
+    /// A trail along each column a hard drop fell through, and sparks
+    /// where it landed.
+    pub(crate) fn emit_hard_drop(&mut self, piece: &Piece, from_y: i32) {
+        if !self.settings.particles {
+            return;
+        }
+        let width = piece.shape.first().map_or(0, Vec::len);
+        for column in 0..width {
+            let filled: Vec<usize> = (0..piece.shape.len()).filter(|&row| piece.shape[row][column] != 0).collect();
+            let (top, bottom) = match (filled.first(), filled.last()) {
+                (Some(&top), Some(&bottom)) => (top as f64, bottom as f64 + 1.0),
+                _ => continue,
+            };
+            let x = (piece.x + column as i32) as f64;
+            let distance = (piece.y - from_y) as f64;
+            if distance > 0.0 {
+                self.particles.emit(&Emitter {
+                    area: [x + 0.25, from_y as f64 + top, 0.5, distance],
+                    velocity: (0.0, -2.0),
+                    spread: (1.0, 2.0),
+                    life: 0.25,
+                    size: 0.1,
+                    color: piece.color,
+                    count: (distance as usize * 2).min(24),
+                });
+            }
+            self.particles.emit(&Emitter {
+                area: [x, piece.y as f64 + bottom, 1.0, 0.0],
+                velocity: (0.0, -6.0),
+                spread: (8.0, 4.0),
+                life: 0.4,
+                size: 0.12,
+                color: piece.color,
+                count: 4,
+            });
+        }
+    }
+
+    /// A burst from every block of the rows being cleared.
+    pub(crate) fn emit_clear(&mut self, lines: &[usize]) {
+        if !self.settings.particles {
+            return;
+        }
+        for &y in lines {
+            for x in 0..self.width {
+                self.particles.emit(&Emitter {
+                    area: [x as f64, y as f64, 1.0, 1.0],
+                    velocity: (0.0, -8.0),
+                    spread: (10.0, 6.0),
+                    life: 0.7,
+                    size: 0.15,
+                    color: self.board[y][x],
+                    count: 3,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Settings;
+
+    fn particles_after_drop(seed: u64) -> Vec<Particle> {
+        let mut game = Tetris::with_seed(seed);
+        game.start();
+        game.drop();
+        game.update(0.1);
+        game.particles.iter().copied().collect()
+    }
+
+    #[test]
+    fn test_seeded_and_expiring() {
+        let particles = particles_after_drop(7);
+        assert!(!particles.is_empty());
+        assert_eq!(particles, particles_after_drop(7));
+        assert_ne!(particles, particles_after_drop(8));
+
+        let mut pool = Particles::new(0);
+        pool.emit(&Emitter {
+            area: [0.0, 0.0, 1.0, 1.0],
+            velocity: (0.0, 0.0),
+            spread: (0.0, 0.0),
+            life: 1.0,
+            size: 0.1,
+            color: 1,
+            count: MAX_PARTICLES + 10,
+        });
+        assert_eq!(pool.iter().count(), MAX_PARTICLES);
+        pool.update(0.5);
+        assert!(pool.iter().all(|particle| particle.y > 0.5 && particle.opacity() < 1.0));
+        pool.update(1.0);
+        assert!(pool.is_empty());
+    }
+
+    #[test]
+    fn test_turned_off() {
+        let mut game = Tetris::with_seed(0);
+        let mut settings = Settings::default();
+        settings.set_particles(false);
+        game.set_settings(settings);
+        game.start();
+        game.drop();
+        assert!(game.particles.is_empty());
+    }
+}
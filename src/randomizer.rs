@@ -0,0 +1,158 @@
+//! Piece randomizers and the queue of pieces they deal ahead of play.
+
+use std::collections::VecDeque;
+
+use wasm_bindgen::prelude::*;
+
+use crate::rng::Rng;
+use crate::{Piece, Tetris};
+
+/// The most previews a rule set can show.
+pub(crate) const MAX_PREVIEWS: usize = 6;
+const KINDS: usize = 7;
+const TGM_HISTORY: usize = 4;
+const TGM_TRIES: usize = 4;
+const TGM_FIRST: [usize; 4] = [0, 2, 3, 4]; // I, T, L or J: never S, Z or O
+const Z_KIND: usize = 6;
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Randomizer {
+    /// Every kind equally likely, every time.
+    Random,
+    /// Rerolls once on a repeat of the last piece, as the NES game did.
+    Nes,
+    /// All seven kinds in a shuffled bag, then the next bag.
+    Bag,
+    /// Up to four rolls to avoid the last four pieces dealt, which start as
+    /// Z; the first piece is never S, Z or O.
+    Tgm,
+}
+
+/// What the randomizer remembers between pieces.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Generator {
+    bag: Vec<usize>,
+    history: VecDeque<usize>, // Latest last
+    dealt: bool,
+}
+
+impl Generator {
+    pub(crate) fn next(&mut self, randomizer: Randomizer, rng: &mut Rng) -> usize {
+        let kind = match randomizer {
+            Randomizer::Random => rng.next_below(KINDS),
+            Randomizer::Nes => {
+                let roll = rng.next_below(KINDS + 1); // The 8th value rerolls too
+                if roll == KINDS || self.history.back() == Some(&roll) {
+                    rng.next_below(KINDS)
+                } else {
+                    roll
+                }
+            }
+            Randomizer::Bag => {
+                if self.bag.is_empty() {
+                    self.bag = (0..KINDS).collect();
+                    for i in (1..KINDS).rev() {
+                        self.bag.swap(i, rng.next_below(i + 1));
+                    }
+                }
+                self.bag.pop().unwrap_or_default()
+            }
+            Randomizer::Tgm => {
+                if !self.dealt {
+                    self.history = VecDeque::from([Z_KIND; TGM_HISTORY]);
+                    TGM_FIRST[rng.next_below(TGM_FIRST.len())]
+                } else {
+                    let mut roll = rng.next_below(KINDS);
+                    for _ in 1..TGM_TRIES {
+                        if !self.history.contains(&roll) {
+                            break;
+                        }
+                        roll = rng.next_below(KINDS);
+                    }
+                    roll
+                }
+            }
+        };
+        self.history.push_back(kind);
+        if self.history.len() > TGM_HISTORY {
+            self.history.pop_front();
+        }
+        self.dealt = true;
+        kind
+    }
+
+    /// State that decides future pieces, for the state hash.
+    pub(crate) fn state(&self) -> Vec<u8> {
+        self.bag.iter().chain(&self.history).map(|&kind| kind as u8).collect()
+    }
+}
+
+impl Tetris {
+    fn generate_piece(&mut self) -> Piece {
+        let kind = self.generator.next(self.rules.randomizer, &mut self.rng);
+        self.new_piece(kind)
+    }
+
+    /// Takes the piece after `next_piece` off the queue, dealing another.
+    pub(crate) fn random_piece(&mut self) -> Piece {
+        let dealt = self.generate_piece();
+        self.queue.push_back(dealt);
+        self.queue.pop_front().expect("a piece was just dealt")
+    }
+
+    /// Starts the piece sequence over from the current rng, for a new seed
+    /// or randomizer.
+    pub(crate) fn deal_queue(&mut self) {
+        self.generator = Generator::default();
+        self.queue.clear();
+        self.next_piece = self.generate_piece();
+        while self.queue.len() < MAX_PREVIEWS - 1 {
+            let piece = self.generate_piece();
+            self.queue.push_back(piece);
+        }
+    }
+
+    /// The next pieces, as many as the rules preview.
+    pub(crate) fn previews(&self) -> Vec<&Piece> {
+        std::iter::once(&self.next_piece).chain(&self.queue).take(self.rules.previews).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deal(randomizer: Randomizer, count: usize) -> Vec<usize> {
+        let (mut generator, mut rng) = (Generator::default(), Rng::new(7));
+        (0..count).map(|_| generator.next(randomizer, &mut rng)).collect()
+    }
+
+    #[test]
+    fn test_randomizers() {
+        for bag in deal(Randomizer::Bag, 28).chunks(KINDS) {
+            let mut kinds = bag.to_vec();
+            kinds.sort();
+            assert_eq!(kinds, (0..KINDS).collect::<Vec<_>>());
+        }
+        let tgm = deal(Randomizer::Tgm, 200);
+        assert!(TGM_FIRST.contains(&tgm[0]));
+        let repeats = |kinds: &[usize]| kinds.windows(2).filter(|pair| pair[0] == pair[1]).count();
+        assert!(repeats(&tgm) < repeats(&deal(Randomizer::Random, 200)));
+        assert!(repeats(&deal(Randomizer::Nes, 200)) < repeats(&deal(Randomizer::Random, 200)));
+        assert_eq!(deal(Randomizer::Tgm, 50), deal(Randomizer::Tgm, 50));
+    }
+
+    #[test]
+    fn test_queue_keeps_the_sequence() {
+        // Dealing ahead doesn't change which pieces come, only when they're rolled.
+        let mut rng = Rng::new(3);
+        let expected: Vec<usize> = (0..10).map(|_| rng.next_below(KINDS)).collect();
+        let mut game = Tetris::with_seed(3);
+        let mut kinds = vec![game.next_piece.kind];
+        for _ in 0..9 {
+            kinds.push(game.random_piece().kind);
+        }
+        assert_eq!(kinds, expected);
+    }
+}
@@ -0,0 +1,584 @@
+//! Canvas2D board renderer. Every block style is painted once into a sprite
+//! sheet, so a cell is a single `draw_image`, and only cells whose contents
+//! changed since the last frame are repainted.
+
+use std::cell::Ref;
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement};
+
+use crate::animation::{fragments, ClearLook};
+use crate::storage::log;
+use crate::theme::PALETTE_SIZE;
+use crate::{BlockStyle, GhostStyle, Piece, Tetris, Theme};
+
+pub(crate) const BLOCK_SIZE: f64 = 30.0;
+
+/// A sprite in the sheet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Tile {
+    Grid,
+    Plain, // Background without grid lines
+    Outline,
+    Block(u8),
+    Fading(u8), // A block without its highlight, as some line clears end
+    Ghost(u8),
+}
+
+impl Tile {
+    fn origin(self) -> (f64, f64) {
+        let (column, row) = match self {
+            Tile::Grid => (0, 0),
+            Tile::Plain => (1, 0),
+            Tile::Outline => (2, 0),
+            Tile::Block(color) => (color, 1),
+            Tile::Fading(color) => (color, 2),
+            Tile::Ghost(color) => (color, 3),
+        };
+        (column as f64 * BLOCK_SIZE, row as f64 * BLOCK_SIZE)
+    }
+}
+
+/// Paints a `size` pixel square of empty board, with half of each grid
+/// line; the neighbouring cell paints the other half.
+#[allow(deprecated)]
+pub(crate) fn paint_background(ctx: &CanvasRenderingContext2d, theme: &Theme, x: f64, y: f64, size: f64, grid: bool) {
+    ctx.set_fill_style(&JsValue::from_str(&theme.background));
+    ctx.fill_rect(x, y, size, size);
+    if grid {
+        ctx.set_stroke_style(&JsValue::from_str(&theme.grid));
+        ctx.set_line_width(1.0);
+        ctx.stroke_rect(x, y, size, size);
+    }
+}
+
+/// Paints a block of `color` in the theme's block style. Without the
+/// highlight, as used while a row clears, only the base shape is drawn.
+#[allow(deprecated)]
+pub(crate) fn paint_block(
+    ctx: &CanvasRenderingContext2d,
+    theme: &Theme,
+    color: u8,
+    (x, y): (f64, f64),
+    size: f64,
+    highlight: bool,
+) {
+    let unit = size / 15.0; // 2 pixels at the usual 30 pixel cell
+    let base = JsValue::from_str(theme.piece(color));
+    match theme.block {
+        BlockStyle::Flat => {
+            ctx.set_fill_style(&base);
+            ctx.fill_rect(x + unit, y + unit, size - 2.0 * unit, size - 2.0 * unit);
+        }
+        BlockStyle::Gradient => {
+            let gradient = ctx.create_linear_gradient(x, y, x + size, y + size);
+            let _ = gradient.add_color_stop(0.0, theme.piece(color));
+            let _ = gradient.add_color_stop(1.0, "#000");
+            ctx.set_fill_style(&gradient);
+            ctx.fill_rect(x + unit, y + unit, size - 2.0 * unit, size - 2.0 * unit);
+            if highlight {
+                ctx.set_fill_style(&JsValue::from_str(theme.highlight(color)));
+                ctx.fill_rect(x + 2.0 * unit, y + 2.0 * unit, size - 4.0 * unit, size - 4.0 * unit);
+            }
+        }
+        BlockStyle::Beveled => {
+            let (left, top, right, bottom) = (x + unit / 2.0, y + unit / 2.0, x + size - unit / 2.0, y + size - unit / 2.0);
+            ctx.set_fill_style(&base);
+            ctx.fill_rect(left, top, right - left, bottom - top);
+            if highlight {
+                let bevel = size / 6.0;
+                let (inner_left, inner_top) = (left + bevel, top + bevel);
+                let (inner_right, inner_bottom) = (right - bevel, bottom - bevel);
+                let polygon = |points: [(f64, f64); 6], fill: &str| {
+                    ctx.begin_path();
+                    ctx.move_to(points[0].0, points[0].1);
+                    for &(px, py) in &points[1..] {
+                        ctx.line_to(px, py);
+                    }
+                    ctx.close_path();
+                    ctx.set_fill_style(&JsValue::from_str(fill));
+                    ctx.fill();
+                };
+                // Lit from the top left.
+                let lit = [(left, top), (right, top), (inner_right, inner_top), (inner_left, inner_top), (inner_left, inner_bottom), (left, bottom)];
+                polygon(lit, theme.highlight(color));
+                let shaded = [(right, bottom), (left, bottom), (inner_left, inner_bottom), (inner_right, inner_bottom), (inner_right, inner_top), (right, top)];
+                polygon(shaded, "rgba(0, 0, 0, 0.35)");
+            }
+        }
+        BlockStyle::Outlined => {
+            ctx.set_stroke_style(&base);
+            ctx.set_line_width(unit);
+            ctx.stroke_rect(x + 1.5 * unit, y + 1.5 * unit, size - 3.0 * unit, size - 3.0 * unit);
+            if highlight && !theme.patterns {
+                ctx.set_fill_style(&base);
+                ctx.fill_rect(x + size * 0.35, y + size * 0.35, size * 0.3, size * 0.3);
+            }
+        }
+    }
+    if theme.patterns {
+        // Outlined blocks are hollow, so their glyph takes the block color.
+        let glyph = if theme.block == BlockStyle::Outlined { theme.piece(color) } else { &theme.glyph };
+        paint_glyph(ctx, color, (x, y), size, glyph);
+    }
+}
+
+/// Marks a block with a shape unique to its piece kind: a bar for I, a
+/// ring for O, a triangle for T, slashes for L and J, a dot for S, a plus
+/// for Z and a cross for garbage.
+#[allow(deprecated)]
+fn paint_glyph(ctx: &CanvasRenderingContext2d, color: u8, (x, y): (f64, f64), size: f64, glyph: &str) {
+    let (cx, cy, r) = (x + size / 2.0, y + size / 2.0, size * 0.18);
+    ctx.set_stroke_style(&JsValue::from_str(glyph));
+    ctx.set_fill_style(&JsValue::from_str(glyph));
+    ctx.set_line_width(size / 12.0);
+    ctx.begin_path();
+    let lines: &[[f64; 4]] = match color {
+        1 => &[[-1.0, 0.0, 1.0, 0.0]],
+        4 => &[[-1.0, 1.0, 1.0, -1.0]],
+        5 => &[[-1.0, -1.0, 1.0, 1.0]],
+        7 => &[[-1.0, 0.0, 1.0, 0.0], [0.0, -1.0, 0.0, 1.0]],
+        8 => &[[-1.0, -1.0, 1.0, 1.0], [-1.0, 1.0, 1.0, -1.0]],
+        _ => &[],
+    };
+    for &[x0, y0, x1, y1] in lines {
+        ctx.move_to(cx + x0 * r, cy + y0 * r);
+        ctx.line_to(cx + x1 * r, cy + y1 * r);
+    }
+    match color {
+        2 => {
+            let _ = ctx.arc(cx, cy, r, 0.0, std::f64::consts::TAU);
+        }
+        3 => {
+            ctx.move_to(cx, cy - r);
+            ctx.line_to(cx + r, cy + r);
+            ctx.line_to(cx - r, cy + r);
+            ctx.close_path();
+        }
+        6 => {
+            let _ = ctx.arc(cx, cy, r * 0.5, 0.0, std::f64::consts::TAU);
+            ctx.fill();
+        }
+        _ => {}
+    }
+    ctx.stroke();
+}
+
+/// Paints a ghost block, to be drawn at the theme's ghost opacity.
+#[allow(deprecated)]
+pub(crate) fn paint_ghost(ctx: &CanvasRenderingContext2d, theme: &Theme, color: u8, x: f64, y: f64, size: f64) {
+    let unit = size / 15.0;
+    let base = JsValue::from_str(theme.piece(color));
+    match theme.ghost {
+        GhostStyle::Filled => {
+            ctx.set_fill_style(&base);
+            ctx.fill_rect(x + unit, y + unit, size - 2.0 * unit, size - 2.0 * unit);
+        }
+        GhostStyle::Outline => {
+            ctx.set_stroke_style(&base);
+            ctx.set_line_width(unit);
+            ctx.stroke_rect(x + 1.5 * unit, y + 1.5 * unit, size - 3.0 * unit, size - 3.0 * unit);
+        }
+    }
+    if theme.patterns {
+        let glyph = if theme.ghost == GhostStyle::Outline { theme.piece(color) } else { &theme.glyph };
+        paint_glyph(ctx, color, (x, y), size, glyph);
+    }
+}
+
+/// Hidden canvas holding every tile of a theme, painted once per scale.
+pub(crate) struct Sprites {
+    sheet: HtmlCanvasElement,
+    scale: f64, // Device pixels per board unit
+    theme: Theme,
+}
+
+impl Sprites {
+    #[allow(deprecated)]
+    pub(crate) fn new(scale: f64, theme: &Theme) -> Option<Sprites> {
+        let sheet = web_sys::window()?
+            .document()?
+            .create_element("canvas")
+            .ok()?
+            .dyn_into::<HtmlCanvasElement>()
+            .ok()?;
+        sheet.set_width((BLOCK_SIZE * scale * (PALETTE_SIZE + 1) as f64).ceil() as u32);
+        sheet.set_height((BLOCK_SIZE * scale * 4.0).ceil() as u32);
+        let ctx = sheet.get_context("2d").ok()??.dyn_into::<CanvasRenderingContext2d>().ok()?;
+        let _ = ctx.scale(scale, scale);
+
+        let (x, y) = Tile::Grid.origin();
+        paint_background(&ctx, theme, x, y, BLOCK_SIZE, true);
+        let (x, y) = Tile::Plain.origin();
+        paint_background(&ctx, theme, x, y, BLOCK_SIZE, false);
+
+        let (x, y) = Tile::Outline.origin();
+        ctx.set_stroke_style(&JsValue::from_str("rgba(255, 255, 255, 0.8)"));
+        ctx.set_line_width(2.0);
+        ctx.stroke_rect(x + 3.0, y + 3.0, BLOCK_SIZE - 6.0, BLOCK_SIZE - 6.0);
+
+        for color in 1..=PALETTE_SIZE as u8 {
+            paint_block(&ctx, theme, color, Tile::Block(color).origin(), BLOCK_SIZE, true);
+            paint_block(&ctx, theme, color, Tile::Fading(color).origin(), BLOCK_SIZE, false);
+            let (x, y) = Tile::Ghost(color).origin();
+            paint_ghost(&ctx, theme, color, x, y, BLOCK_SIZE);
+        }
+        Some(Sprites {
+            sheet,
+            scale,
+            theme: theme.clone(),
+        })
+    }
+
+    /// Draws `tile` with its top left corner at cell (`x`, `y`).
+    pub(crate) fn draw(&self, ctx: &CanvasRenderingContext2d, tile: Tile, x: f64, y: f64) {
+        self.draw_part(ctx, tile, [0.0, 0.0, 1.0], [x, y, 1.0]);
+    }
+
+    /// Draws the square of `tile` at `[left, top, size]`, in fractions of a
+    /// cell, into the square `[x, y, size]` of the board, in cells.
+    pub(crate) fn draw_part(&self, ctx: &CanvasRenderingContext2d, tile: Tile, part: [f64; 3], [x, y, size]: [f64; 3]) {
+        let (sx, sy) = tile.origin();
+        let source = BLOCK_SIZE * self.scale;
+        let _ = ctx.draw_image_with_html_canvas_element_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
+            &self.sheet,
+            sx * self.scale + part[0] * source,
+            sy * self.scale + part[1] * source,
+            part[2] * source,
+            part[2] * source,
+            x * BLOCK_SIZE,
+            y * BLOCK_SIZE,
+            size * BLOCK_SIZE,
+            size * BLOCK_SIZE,
+        );
+    }
+}
+
+/// Everything drawn in one board cell, bottom layer first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Cell {
+    block: u8,
+    ghost: u8,
+    outline: bool,
+    piece: u8,
+}
+
+/// What applies to the whole canvas. Any change repaints every cell.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct View {
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) block: u32, // Device pixels per cell
+    pub(crate) grid: bool,
+    pub(crate) overlay: bool, // Pause screen, finesse flash or HUD over the board
+    pub(crate) animating: bool, // Rows clearing or falling, or particles flying
+}
+
+/// The cells currently on the canvas, to work out which ones changed.
+#[derive(Debug, Default)]
+pub(crate) struct Shown {
+    cells: Vec<Option<Cell>>, // None when something else drew over the cell
+    view: Option<View>,
+    drawn: usize,
+}
+
+impl Shown {
+    /// Records `cells` as shown and returns whether the whole canvas needs
+    /// repainting, plus the indices of the cells to repaint.
+    pub(crate) fn update(&mut self, cells: Vec<Cell>, view: View) -> (bool, Vec<usize>) {
+        // An overlay is translucent, so it has to go on top of fresh cells,
+        // and animated blocks stray outside their own cells.
+        let full = self.view != Some(view) || view.overlay || view.animating || self.cells.len() != cells.len();
+        let dirty: Vec<usize> = (0..cells.len())
+            .filter(|&i| full || self.cells[i] != Some(cells[i]))
+            .collect();
+        self.cells = cells.into_iter().map(Some).collect();
+        self.view = Some(view);
+        self.drawn = dirty.len();
+        (full, dirty)
+    }
+}
+
+fn mark_piece(cells: &mut [Cell], width: usize, piece: &Piece, mut mark: impl FnMut(&mut Cell)) {
+    for (y, row) in piece.shape.iter().enumerate() {
+        for (x, &value) in row.iter().enumerate() {
+            let (x, y) = (piece.x + x as i32, piece.y + y as i32);
+            if value != 0 && x >= 0 && (x as usize) < width && y >= 0 {
+                if let Some(cell) = cells.get_mut(y as usize * width + x as usize) {
+                    mark(cell);
+                }
+            }
+        }
+    }
+}
+
+/// The contents of every cell, row by row.
+pub(crate) fn frame_cells(game: &Tetris) -> Vec<Cell> {
+    let mut cells = vec![Cell::default(); game.width * game.height];
+    for (y, row) in game.board.iter().enumerate() {
+        for (x, &block) in row.iter().enumerate() {
+            if block == 0 {
+                continue;
+            }
+            if block as usize > PALETTE_SIZE {
+                log(&format!("Invalid color index in board: {}", block));
+                continue;
+            }
+            cells[y * game.width + x] = Cell {
+                block,
+                ..Cell::default()
+            };
+        }
+    }
+    if game.shows_ghost() {
+        if let Some(ghost) = game.ghost_piece() {
+            mark_piece(&mut cells, game.width, &ghost, |cell| cell.ghost = ghost.color);
+        }
+    }
+    if let Some(hint) = game.hint_outline() {
+        mark_piece(&mut cells, game.width, &hint, |cell| cell.outline = true);
+    }
+    if let Some(ref piece) = game.current_piece {
+        mark_piece(&mut cells, game.width, piece, |cell| cell.piece = piece.color);
+    }
+    cells
+}
+
+impl Tetris {
+    /// The sprite sheet at `scale`, repainted when the scale changed.
+    pub(crate) fn sprites_at(&self, scale: f64) -> Option<Ref<'_, Sprites>> {
+        let stale = self.sprites.borrow().as_ref().is_none_or(|sprites| sprites.scale != scale || sprites.theme != self.theme);
+        if stale {
+            *self.sprites.borrow_mut() = Sprites::new(scale, &self.theme);
+            self.invalidate();
+        }
+        Ref::filter_map(self.sprites.borrow(), Option::as_ref).ok()
+    }
+
+    #[allow(deprecated)]
+    pub(crate) fn draw_cells(&self, ctx: &CanvasRenderingContext2d) {
+        let canvas = match ctx.canvas() {
+            Some(canvas) => canvas,
+            None => return,
+        };
+        let layout = self.fit_board(&canvas);
+        let scale = layout.scale();
+        let sprites = match self.sprites_at(scale) {
+            Some(sprites) => sprites,
+            None => return,
+        };
+        let _ = ctx.set_transform(scale, 0.0, 0.0, scale, 0.0, 0.0);
+        let width = BLOCK_SIZE * self.width as f64;
+        let height = BLOCK_SIZE * self.height as f64;
+        let view = View {
+            width: canvas.width(),
+            height: canvas.height(),
+            block: (layout.block * layout.ratio).round() as u32,
+            grid: self.settings.grid,
+            overlay: self.paused || self.countdown > 0.0 || self.finesse_flash_alpha() > 0.0 || self.hud_over_board(),
+            animating: self.is_animating() || !self.particles.is_empty(),
+        };
+        let cells = frame_cells(self);
+        let (full, dirty) = self.shown.borrow_mut().update(cells.clone(), view);
+
+        if full {
+            ctx.clear_rect(0.0, 0.0, canvas.width() as f64 / scale, canvas.height() as f64 / scale);
+            // Draw light grey border
+            ctx.set_fill_style(&JsValue::from_str(&self.theme.border));
+            ctx.fill_rect(-2.0, -2.0, width + 4.0, height + 4.0);
+        }
+        let background = if self.settings.grid { Tile::Grid } else { Tile::Plain };
+        let mut shattered = Vec::new(); // Drawn last, as fragments fly over other cells
+        for i in dirty {
+            let cell = cells[i];
+            let (x, y) = ((i % self.width) as f64, (i / self.width) as f64);
+            sprites.draw(ctx, background, x, y);
+            if cell.block != 0 {
+                let (column, row) = (i % self.width, i / self.width);
+                if self.clearing_lines.contains(&row) {
+                    let look = self.clear_look(column, row);
+                    if look.shatter {
+                        shattered.push((column, row, cell.block));
+                    } else {
+                        self.draw_clearing(ctx, &sprites, cell.block, (x, y), look);
+                    }
+                } else {
+                    sprites.draw(ctx, Tile::Block(cell.block), x, y - self.fall_offset(row));
+                }
+            }
+            if cell.ghost != 0 {
+                ctx.set_global_alpha(self.theme.ghost_alpha);
+                sprites.draw(ctx, Tile::Ghost(cell.ghost), x, y);
+                ctx.set_global_alpha(1.0);
+            }
+            if cell.outline {
+                sprites.draw(ctx, Tile::Outline, x, y);
+            }
+            if cell.piece != 0 {
+                sprites.draw(ctx, Tile::Block(cell.piece), x, y);
+            }
+        }
+
+        let progress = self.clear_progress();
+        for (column, row, block) in shattered {
+            for fragment in fragments(column, row, progress) {
+                ctx.set_global_alpha(fragment.opacity);
+                let part = [fragment.corner.0, fragment.corner.1, 0.5];
+                sprites.draw_part(ctx, Tile::Block(block), part, [fragment.x, fragment.y, 0.5]);
+            }
+            ctx.set_global_alpha(1.0);
+        }
+        self.draw_particles(ctx);
+
+        self.draw_finesse_flash(ctx, width, height);
+        self.draw_hud(ctx);
+
+        // Draw pause overlay if paused
+        if self.paused {
+            ctx.set_fill_style(&JsValue::from_str("rgba(0, 0, 0, 0.7)"));
+            ctx.fill_rect(0.0, 0.0, width, height);
+            ctx.set_fill_style(&JsValue::from_str("#FFD700")); // Gold color for "PAUSE"
+            ctx.set_font("40px Arial");
+            ctx.set_text_align("center");
+            let _ = ctx.fill_text("PAUSE", width / 2.0, height / 2.0);
+        } else if let Some(label) = self.countdown_label() {
+            ctx.set_fill_style(&JsValue::from_str("rgba(0, 0, 0, 0.4)"));
+            ctx.fill_rect(0.0, 0.0, width, height);
+            ctx.set_fill_style(&JsValue::from_str("#FFD700"));
+            ctx.set_font("40px Arial");
+            ctx.set_text_align("center");
+            let _ = ctx.fill_text(label, width / 2.0, height / 2.0);
+        }
+    }
+}
+
+impl Tetris {
+    // A block of a clearing row, part way through its effect.
+    #[allow(deprecated)]
+    fn draw_clearing(&self, ctx: &CanvasRenderingContext2d, sprites: &Sprites, block: u8, (x, y): (f64, f64), look: ClearLook) {
+        if look.opacity <= 0.0 || look.scale <= 0.0 {
+            return;
+        }
+        let tile = if look.highlight { Tile::Block(block) } else { Tile::Fading(block) };
+        let inset = (1.0 - look.scale) / 2.0;
+        let square = [x + inset, y + inset, look.scale];
+        ctx.set_global_alpha(look.opacity);
+        sprites.draw_part(ctx, tile, [0.0, 0.0, 1.0], square);
+        if look.flash > 0.0 {
+            ctx.set_global_alpha(look.opacity * look.flash);
+            ctx.set_fill_style(&JsValue::from_str("#fff"));
+            ctx.fill_rect(square[0] * BLOCK_SIZE, square[1] * BLOCK_SIZE, square[2] * BLOCK_SIZE, square[2] * BLOCK_SIZE);
+        }
+        ctx.set_global_alpha(1.0);
+    }
+
+    #[allow(deprecated)]
+    fn draw_particles(&self, ctx: &CanvasRenderingContext2d) {
+        for particle in self.particles.iter() {
+            ctx.set_global_alpha(particle.opacity());
+            ctx.set_fill_style(&JsValue::from_str(self.theme.piece(particle.color)));
+            let size = particle.size * BLOCK_SIZE;
+            ctx.fill_rect(particle.x * BLOCK_SIZE - size / 2.0, particle.y * BLOCK_SIZE - size / 2.0, size, size);
+        }
+        ctx.set_global_alpha(1.0);
+    }
+}
+
+#[wasm_bindgen]
+impl Tetris {
+    /// Repaints the whole board on the next `draw`. Call after drawing over
+    /// the board canvas.
+    pub fn invalidate(&self) {
+        self.shown.borrow_mut().view = None;
+    }
+
+    /// Repaints the cells under a rectangle of the board on the next `draw`.
+    /// Measured in board units, 30 per cell at any zoom.
+    pub fn invalidate_area(&self, x: f64, y: f64, width: f64, height: f64) {
+        let mut shown = self.shown.borrow_mut();
+        let columns = (x / BLOCK_SIZE).floor().max(0.0) as usize..((x + width) / BLOCK_SIZE).ceil().max(0.0) as usize;
+        let rows = (y / BLOCK_SIZE).floor().max(0.0) as usize..((y + height) / BLOCK_SIZE).ceil().max(0.0) as usize;
+        for row in rows.clone().take_while(|&row| row < self.height) {
+            for column in columns.clone().take_while(|&column| column < self.width) {
+                if let Some(cell) = shown.cells.get_mut(row * self.width + column) {
+                    *cell = None;
+                }
+            }
+        }
+    }
+
+    /// Number of cells the last `draw` repainted.
+    pub fn get_cells_drawn(&self) -> usize {
+        self.shown.borrow().drawn
+    }
+
+    /// Milliseconds `draw` takes, averaged over recent frames. Pages can
+    /// show it in a debug overlay to compare boards, renderers and themes.
+    pub fn get_draw_time(&self) -> f64 {
+        self.draw_time.get()
+    }
+}
+
+impl Tetris {
+    // Folds one frame into the average; a tenth of each new frame.
+    pub(crate) fn record_draw_time(&self, milliseconds: f64) {
+        let average = self.draw_time.get();
+        self.draw_time.set(if average > 0.0 { average + (milliseconds - average) * 0.1 } else { milliseconds });
+    }
+}
+
+/// The page's high resolution clock, in milliseconds.
+pub(crate) fn now() -> Option<f64> {
+    Some(web_sys::window()?.performance()?.now())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VIEW: View = View {
+        width: 300,
+        height: 1200,
+        block: 30,
+        grid: true,
+        overlay: false,
+        animating: false,
+    };
+
+    #[test]
+    fn test_repaints_changed_cells() {
+        let mut game = Tetris::with_seed(0);
+        game.height = 40;
+        game.board = vec![vec![0; 10]; 40];
+        for row in &mut game.board[20..] {
+            *row = vec![1, 1, 1, 1, 1, 1, 1, 1, 1, 0];
+        }
+        game.start();
+        let repaint = |game: &Tetris| game.shown.borrow_mut().update(frame_cells(game), VIEW);
+
+        assert_eq!(repaint(&game), (true, (0..400).collect()));
+        assert_eq!(repaint(&game), (false, vec![]));
+
+        // Only the piece and its ghost move, at most four cells each way.
+        game.move_left();
+        let (full, dirty) = repaint(&game);
+        assert!(!full);
+        assert!(!dirty.is_empty() && dirty.len() <= 16);
+        assert_eq!(game.get_cells_drawn(), dirty.len());
+
+        game.invalidate_area(0.0, 1170.0, 60.0, 30.0);
+        assert_eq!(repaint(&game), (false, vec![390, 391]));
+        let view = View { grid: false, ..VIEW };
+        assert_eq!(game.shown.borrow_mut().update(frame_cells(&game), view).1.len(), 400);
+    }
+
+    #[test]
+    fn test_draw_time_average() {
+        let game = Tetris::with_seed(0);
+        game.record_draw_time(4.0);
+        assert_eq!(game.get_draw_time(), 4.0);
+        game.record_draw_time(14.0);
+        assert_eq!(game.get_draw_time(), 5.0);
+    }
+}
@@ -0,0 +1,292 @@
+//! Heads-up display drawn into the board canvas, for pages that embed the
+//! engine without their own UI: score with a rolling counter, level, lines,
+//! timer, pieces per second and action text such as "TETRIS" that fades
+//! out. Drawn by the Canvas2D renderer; off by default.
+
+use std::collections::VecDeque;
+
+use wasm_bindgen::prelude::*;
+use web_sys::CanvasRenderingContext2d;
+
+use crate::render::canvas::BLOCK_SIZE;
+use crate::Tetris;
+
+const POPUP_LIFE: f64 = 1.5; // Seconds, the last third fading out
+const MAX_POPUPS: usize = 4;
+const COLUMNS_RANGE: (usize, usize) = (3, 12);
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HudPosition {
+    Off,
+    /// A panel right of the board; the board shrinks to make room.
+    Side,
+    /// Over the top of the board.
+    Overlay,
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HudItem {
+    Score,
+    Level,
+    Lines,
+    Timer,
+    Pps,
+}
+
+/// Where the HUD goes and what it shows.
+#[wasm_bindgen]
+#[derive(Clone, Debug, PartialEq)]
+pub struct HudLayout {
+    position: HudPosition,
+    columns: usize, // Width of the side panel, in cells
+    items: Vec<HudItem>,
+    actions: bool,
+}
+
+impl Default for HudLayout {
+    fn default() -> HudLayout {
+        HudLayout {
+            position: HudPosition::Off,
+            columns: 5,
+            items: vec![HudItem::Score, HudItem::Level, HudItem::Lines, HudItem::Timer, HudItem::Pps],
+            actions: true,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl HudLayout {
+    /// Every item and action text, in a side panel.
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> HudLayout {
+        HudLayout {
+            position: HudPosition::Side,
+            ..HudLayout::default()
+        }
+    }
+
+    pub fn get_position(&self) -> HudPosition {
+        self.position
+    }
+
+    pub fn set_position(&mut self, position: HudPosition) {
+        self.position = position;
+    }
+
+    pub fn get_columns(&self) -> usize {
+        self.columns
+    }
+
+    pub fn set_columns(&mut self, columns: usize) {
+        self.columns = columns.clamp(COLUMNS_RANGE.0, COLUMNS_RANGE.1);
+    }
+
+    /// Adds `item` at the end, if it isn't shown already.
+    pub fn show(&mut self, item: HudItem) {
+        if !self.items.contains(&item) {
+            self.items.push(item);
+        }
+    }
+
+    pub fn hide(&mut self, item: HudItem) {
+        self.items.retain(|&shown| shown != item);
+    }
+
+    pub fn is_shown(&self, item: HudItem) -> bool {
+        self.items.contains(&item)
+    }
+
+    pub fn get_actions(&self) -> bool {
+        self.actions
+    }
+
+    /// Whether clears announce themselves, like "T-SPIN DOUBLE".
+    pub fn set_actions(&mut self, actions: bool) {
+        self.actions = actions;
+    }
+}
+
+impl HudLayout {
+    /// Cells of canvas width the HUD takes beside the board.
+    pub(crate) fn extra_columns(&self) -> usize {
+        if self.position == HudPosition::Side {
+            self.columns
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Hud {
+    score: f64, // Shown score, catching up with the real one
+    popups: VecDeque<(String, f64)>, // Text and age in seconds
+}
+
+/// What a clear announces. Plain singles to triples go unmentioned.
+pub(crate) fn action_texts(lines: u32, tspin: bool, b2b: bool, combo: u32) -> Vec<String> {
+    let mut texts = Vec::new();
+    let count = ["", " SINGLE", " DOUBLE", " TRIPLE"];
+    if tspin {
+        texts.push(format!("T-SPIN{}", count.get(lines as usize).copied().unwrap_or("")));
+    } else if lines == 4 {
+        texts.push("TETRIS".to_string());
+    }
+    if b2b {
+        texts.push("B2B".to_string());
+    }
+    if combo > 0 {
+        texts.push(format!("{} COMBO", combo));
+    }
+    texts
+}
+
+/// Minutes, seconds and hundredths, like "1:05.30".
+pub(crate) fn format_time(seconds: f64) -> String {
+    let hundredths = (seconds.max(0.0) * 100.0) as u64;
+    format!("{}:{:02}.{:02}", hundredths / 6000, hundredths / 100 % 60, hundredths % 100)
+}
+
+impl Tetris {
+    pub(crate) fn announce(&mut self, texts: Vec<String>) {
+        for text in texts {
+            if self.hud.popups.len() == MAX_POPUPS {
+                self.hud.popups.pop_front();
+            }
+            self.hud.popups.push_back((text, 0.0));
+        }
+    }
+
+    pub(crate) fn update_hud(&mut self, delta_time: f64) {
+        let target = self.score as f64;
+        // Closes most of the gap in a quarter second, whatever the gap.
+        self.hud.score += (target - self.hud.score) * (1.0 - (-12.0 * delta_time).exp());
+        if (target - self.hud.score).abs() < 1.0 {
+            self.hud.score = target;
+        }
+        for popup in &mut self.hud.popups {
+            popup.1 += delta_time;
+        }
+        self.hud.popups.retain(|&(_, age)| age < POPUP_LIFE);
+    }
+
+    /// Whether the HUD draws over board cells, so they need repainting.
+    pub(crate) fn hud_over_board(&self) -> bool {
+        self.hud_layout.position == HudPosition::Overlay
+    }
+
+    fn hud_value(&self, item: HudItem) -> (&'static str, String) {
+        match item {
+            HudItem::Score => ("SCORE", format!("{}", self.hud.score.floor() as u64)),
+            HudItem::Level => ("LEVEL", format!("{}", self.get_level())),
+            HudItem::Lines => ("LINES", format!("{}", self.cleared_lanes)),
+            HudItem::Timer => ("TIME", format_time(self.stats.elapsed)),
+            HudItem::Pps => ("PPS", format!("{:.2}", self.stats.get_pps())),
+        }
+    }
+
+    #[allow(deprecated)]
+    pub(crate) fn draw_hud(&self, ctx: &CanvasRenderingContext2d) {
+        let layout = &self.hud_layout;
+        let (board_width, board_height) = (BLOCK_SIZE * self.width as f64, BLOCK_SIZE * self.height as f64);
+        let (left, width) = match layout.position {
+            HudPosition::Off => return,
+            HudPosition::Side => {
+                let left = board_width + BLOCK_SIZE / 2.0;
+                let width = BLOCK_SIZE * layout.columns as f64 - BLOCK_SIZE / 2.0;
+                ctx.set_fill_style(&JsValue::from_str(&self.theme.background));
+                ctx.fill_rect(left, 0.0, width, board_height);
+                (left, width)
+            }
+            HudPosition::Overlay => {
+                let rows = layout.items.len() as f64 * 0.8 + 0.4;
+                ctx.set_fill_style(&JsValue::from_str("rgba(0, 0, 0, 0.5)"));
+                ctx.fill_rect(0.0, 0.0, board_width, BLOCK_SIZE * rows);
+                (0.0, board_width)
+            }
+        };
+        let text = JsValue::from_str(&self.theme.border);
+        ctx.set_fill_style(&text);
+        ctx.set_text_baseline("top");
+        let pad = BLOCK_SIZE * 0.25;
+        let mut y = pad;
+        for &item in &layout.items {
+            let (label, value) = self.hud_value(item);
+            if layout.position == HudPosition::Side {
+                ctx.set_text_align("left");
+                ctx.set_font(&format!("{}px Arial", BLOCK_SIZE * 0.45));
+                let _ = ctx.fill_text(label, left + pad, y);
+                ctx.set_font(&format!("bold {}px Arial", BLOCK_SIZE * 0.75));
+                let _ = ctx.fill_text(&value, left + pad, y + BLOCK_SIZE * 0.5);
+                y += BLOCK_SIZE * 1.5;
+            } else {
+                ctx.set_font(&format!("{}px Arial", BLOCK_SIZE * 0.55));
+                ctx.set_text_align("left");
+                let _ = ctx.fill_text(label, left + pad, y);
+                ctx.set_text_align("right");
+                let _ = ctx.fill_text(&value, left + width - pad, y);
+                y += BLOCK_SIZE * 0.8;
+            }
+        }
+
+        if !layout.actions {
+            return;
+        }
+        // Side panels list them under the items; overlays center them on the board.
+        let (center, mut y) = match layout.position {
+            HudPosition::Side => (left + width / 2.0, y + BLOCK_SIZE / 2.0),
+            _ => (board_width / 2.0, board_height / 3.0),
+        };
+        ctx.set_text_align("center");
+        ctx.set_font(&format!("bold {}px Arial", BLOCK_SIZE * 0.6));
+        for (popup, age) in &self.hud.popups {
+            ctx.set_global_alpha(((POPUP_LIFE - age) / (POPUP_LIFE / 3.0)).clamp(0.0, 1.0));
+            let _ = ctx.fill_text(popup, center, y);
+            y += BLOCK_SIZE * 0.8;
+        }
+        ctx.set_global_alpha(1.0);
+    }
+}
+
+#[wasm_bindgen]
+impl Tetris {
+    pub fn get_hud(&self) -> HudLayout {
+        self.hud_layout.clone()
+    }
+
+    /// Draws the HUD into the board canvas with the next `draw`.
+    pub fn set_hud(&mut self, layout: &HudLayout) {
+        self.hud_layout = layout.clone();
+        self.invalidate();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_action_texts() {
+        assert_eq!(action_texts(2, true, true, 0), vec!["T-SPIN DOUBLE", "B2B"]);
+        assert_eq!(action_texts(4, false, false, 3), vec!["TETRIS", "3 COMBO"]);
+        assert!(action_texts(1, false, false, 0).is_empty());
+        assert_eq!(format_time(65.3), "1:05.30");
+    }
+
+    #[test]
+    fn test_rolling_score_and_popups() {
+        let mut game = Tetris::with_seed(0);
+        game.score = 1000;
+        game.update_hud(1.0 / 60.0);
+        assert!(game.hud.score > 0.0 && game.hud.score < 1000.0);
+        game.update_hud(1.0);
+        assert_eq!(game.hud.score, 1000.0);
+
+        game.announce(action_texts(4, false, true, 1));
+        assert_eq!(game.hud.popups.len(), 3);
+        game.update_hud(POPUP_LIFE);
+        assert!(game.hud.popups.is_empty());
+    }
+}
@@ -0,0 +1,127 @@
+//! Fitting the board to its canvas. The page sizes the canvas with CSS; the
+//! backing store is matched to that box in device pixels and the board is
+//! drawn as large as fits, with a whole number of device pixels per cell so
+//! blocks stay sharp. Everything is drawn in board units, `BLOCK_SIZE` per
+//! cell, scaled by the context transform.
+
+use wasm_bindgen::prelude::*;
+use web_sys::HtmlCanvasElement;
+
+use crate::render::canvas::BLOCK_SIZE;
+use crate::storage::log;
+use crate::Tetris;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Layout {
+    pub(crate) block: f64, // CSS pixels per cell
+    pub(crate) ratio: f64, // Device pixels per CSS pixel
+}
+
+impl Default for Layout {
+    fn default() -> Layout {
+        Layout {
+            block: BLOCK_SIZE,
+            ratio: 1.0,
+        }
+    }
+}
+
+impl Layout {
+    /// The largest block that fits `columns` x `rows` cells into a box of
+    /// `width` x `height` CSS pixels.
+    pub(crate) fn fit(width: f64, height: f64, columns: usize, rows: usize, ratio: f64) -> Layout {
+        let ratio = if ratio > 0.0 { ratio } else { 1.0 };
+        let cells = (width / columns.max(1) as f64).min(height / rows.max(1) as f64);
+        let device = (cells * ratio).floor().max(1.0);
+        Layout {
+            block: device / ratio,
+            ratio,
+        }
+    }
+
+    /// Device pixels per board unit.
+    pub(crate) fn scale(&self) -> f64 {
+        self.block * self.ratio / BLOCK_SIZE
+    }
+}
+
+fn device_pixel_ratio() -> f64 {
+    web_sys::window().map_or(1.0, |win| win.device_pixel_ratio())
+}
+
+/// Gives `canvas` a backing store of `width` x `height` CSS pixels at the
+/// device pixel ratio. Resizing clears the canvas, so it only happens when
+/// the size changed.
+fn size_backing_store(canvas: &HtmlCanvasElement, width: f64, height: f64, ratio: f64) {
+    let (width, height) = ((width * ratio).round() as u32, (height * ratio).round() as u32);
+    if canvas.width() != width {
+        canvas.set_width(width);
+    }
+    if canvas.height() != height {
+        canvas.set_height(height);
+    }
+}
+
+impl Tetris {
+    /// Re-reads the board canvas's CSS size and the device pixel ratio, and
+    /// resizes its backing store to match. Cheap when nothing changed, so it
+    /// runs every frame and picks up window resizes and zooming. A side HUD
+    /// takes columns of its own.
+    pub(crate) fn fit_board(&self, canvas: &HtmlCanvasElement) -> Layout {
+        let (width, height) = (canvas.client_width() as f64, canvas.client_height() as f64);
+        if width > 0.0 && height > 0.0 {
+            let ratio = device_pixel_ratio();
+            size_backing_store(canvas, width, height, ratio);
+            let columns = self.width + self.hud_layout.extra_columns();
+            self.layout.set(Layout::fit(width, height, columns, self.height, ratio));
+        }
+        self.layout.get()
+    }
+
+    /// Sizes a preview canvas to `columns` x `rows` cells at the board's
+    /// block size, so previews scale with the board. The preview is fitted
+    /// on its own, in whole device pixels at the current pixel ratio, so it
+    /// stays sharp whichever renderer drew the board.
+    pub(crate) fn fit_preview(&self, canvas: &HtmlCanvasElement, columns: usize, rows: usize) -> Layout {
+        let block = self.layout.get().block;
+        let layout = Layout::fit(block * columns as f64, block * rows as f64, columns, rows, device_pixel_ratio());
+        let (width, height) = (layout.block * columns as f64, layout.block * rows as f64);
+        let style = canvas.style();
+        if style.set_property("width", &format!("{}px", width)).is_err()
+            || style.set_property("height", &format!("{}px", height)).is_err()
+        {
+            log("Failed to size the preview canvas");
+        }
+        size_backing_store(canvas, width, height, layout.ratio);
+        layout
+    }
+}
+
+#[wasm_bindgen]
+impl Tetris {
+    /// CSS pixels per cell the board was last drawn at. Pages can size the
+    /// rest of their layout from it.
+    pub fn get_block_size(&self) -> f64 {
+        self.layout.get().block
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit() {
+        let layout = Layout::fit(300.0, 600.0, 10, 20, 1.0);
+        assert_eq!((layout.block, layout.scale()), (30.0, 1.0));
+
+        // A retina phone: the height limits, in whole device pixels.
+        let layout = Layout::fit(390.0, 500.0, 10, 20, 3.0);
+        assert_eq!(layout.block * layout.ratio, 75.0);
+        assert_eq!(layout.block, 25.0);
+        assert_eq!(layout.scale(), 2.5);
+
+        let layout = Layout::fit(301.0, 1000.0, 10, 40, 1.5);
+        assert_eq!(layout.block * layout.ratio, 37.0);
+    }
+}
@@ -0,0 +1,52 @@
+//! Renderer selection. Canvas2D is always available; WebGL2 needs the
+//! `webgl` cargo feature.
+
+pub(crate) mod canvas;
+pub(crate) mod hud;
+pub(crate) mod layout;
+#[cfg(feature = "webgl")]
+pub(crate) mod webgl;
+
+use wasm_bindgen::prelude::*;
+
+use crate::storage::log;
+use crate::Tetris;
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Renderer {
+    Canvas2d,
+    /// Every cell in one instanced draw call. Falls back to Canvas2d when
+    /// WebGL2 is unavailable or the crate was built without `webgl`.
+    WebGl2,
+}
+
+#[wasm_bindgen]
+impl Tetris {
+    /// Like `new`, drawing with the chosen renderer.
+    pub fn with_renderer(canvas_id: &str, renderer: Renderer) -> Tetris {
+        if renderer == Renderer::WebGl2 {
+            #[cfg(feature = "webgl")]
+            match webgl::GlRenderer::new(canvas_id) {
+                Some(gl) => {
+                    return Tetris {
+                        gl: Some(gl),
+                        ..Tetris::new_fallback()
+                    }
+                }
+                None => log("WebGL2 is unavailable; drawing with Canvas2D"),
+            }
+            #[cfg(not(feature = "webgl"))]
+            log("Built without the webgl feature; drawing with Canvas2D");
+        }
+        Tetris::new(canvas_id)
+    }
+
+    pub fn get_renderer(&self) -> Renderer {
+        #[cfg(feature = "webgl")]
+        if self.gl.is_some() {
+            return Renderer::WebGl2;
+        }
+        Renderer::Canvas2d
+    }
+}
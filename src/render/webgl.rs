@@ -0,0 +1,459 @@
+//! WebGL2 board renderer. Every frame is one instanced draw call: each
+//! instance is a rectangle in board cells textured from an atlas that is
+//! painted with the same 2D canvas calls as the Canvas2D sprites, once per
+//! theme and cell size. Atlas tiles are as many device pixels as the cells
+//! they are drawn into, so blocks stay sharp on HiDPI screens.
+
+use std::cell::RefCell;
+
+use js_sys::Float32Array;
+use wasm_bindgen::JsCast;
+use web_sys::{
+    CanvasRenderingContext2d, HtmlCanvasElement, WebGl2RenderingContext as Gl, WebGlBuffer, WebGlProgram,
+    WebGlShader, WebGlTexture, WebGlUniformLocation, WebGlVertexArrayObject,
+};
+
+use crate::animation::fragments;
+use crate::storage::log;
+use crate::render::canvas::{paint_background, paint_block, paint_ghost};
+use crate::theme::PALETTE_SIZE;
+use crate::{Piece, Tetris, Theme};
+
+const TILE: f64 = 32.0; // Atlas units per tile; the atlas is painted scaled to the cell size
+const ATLAS_WIDTH: f64 = TILE * (PALETTE_SIZE + 1) as f64;
+const ATLAS_HEIGHT: f64 = TILE * 10.0;
+// Atlas rows of tiles. Block rows are indexed by cell value.
+const ROW_MISC: usize = 0;
+const ROW_BLOCK: usize = 1;
+const ROW_FADING: usize = 2; // Blocks without the highlight, for rows being cleared
+const ROW_GHOST: usize = 3;
+const LABEL_TOP: f64 = TILE * 4.0; // Below the tiles, two rows each
+const LABELS: [&str; 3] = ["PAUSE", "READY", "GO"];
+// Columns of ROW_MISC.
+const GRID: usize = 0;
+const PLAIN: usize = 1;
+const BLACK: usize = 2;
+const RED: usize = 3;
+const OUTLINE: usize = 4;
+const WHITE: usize = 5;
+
+const FLOATS_PER_INSTANCE: usize = 9;
+
+const VERTEX_SHADER: &str = r#"#version 300 es
+layout(location = 0) in vec2 a_corner;
+layout(location = 1) in vec4 a_rect;
+layout(location = 2) in vec4 a_uv;
+layout(location = 3) in float a_alpha;
+uniform vec2 u_cells;
+out vec2 v_uv;
+out float v_alpha;
+void main() {
+    vec2 clip = (a_rect.xy + a_corner * a_rect.zw) / u_cells * 2.0 - 1.0;
+    gl_Position = vec4(clip.x, -clip.y, 0.0, 1.0);
+    v_uv = mix(a_uv.xy, a_uv.zw, a_corner);
+    v_alpha = a_alpha;
+}
+"#;
+
+const FRAGMENT_SHADER: &str = r#"#version 300 es
+precision mediump float;
+in vec2 v_uv;
+in float v_alpha;
+uniform sampler2D u_atlas;
+out vec4 color;
+void main() {
+    vec4 texel = texture(u_atlas, v_uv);
+    color = vec4(texel.rgb, texel.a * v_alpha);
+}
+"#;
+
+/// One textured rectangle, in board cells.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Instance {
+    rect: [f32; 4], // x, y, width, height
+    uv: [f32; 4],   // Atlas corners, 0.0 to 1.0
+    alpha: f32,
+}
+
+impl Instance {
+    fn cell(x: i32, y: i32, uv: [f32; 4], alpha: f64) -> Instance {
+        Instance {
+            rect: [x as f32, y as f32, 1.0, 1.0],
+            uv,
+            alpha: alpha as f32,
+        }
+    }
+}
+
+// Texture coordinates of a whole tile, `texel` being the atlas units per
+// texel.
+fn tile(column: usize, row: usize, texel: f64) -> [f32; 4] {
+    region(column as f64 * TILE, row as f64 * TILE, TILE, TILE, texel)
+}
+
+// Atlas units to texture coordinates, half a texel in from each edge so
+// neighbouring tiles never bleed in.
+fn region(x: f64, y: f64, width: f64, height: f64, texel: f64) -> [f32; 4] {
+    let inset = texel / 2.0;
+    [
+        ((x + inset) / ATLAS_WIDTH) as f32,
+        ((y + inset) / ATLAS_HEIGHT) as f32,
+        ((x + width - inset) / ATLAS_WIDTH) as f32,
+        ((y + height - inset) / ATLAS_HEIGHT) as f32,
+    ]
+}
+
+fn piece_cells(piece: &Piece, uv: [f32; 4], alpha: f64, instances: &mut Vec<Instance>) {
+    for (y, row) in piece.shape.iter().enumerate() {
+        for (x, &cell) in row.iter().enumerate() {
+            if cell != 0 {
+                instances.push(Instance::cell(piece.x + x as i32, piece.y + y as i32, uv, alpha));
+            }
+        }
+    }
+}
+
+/// Everything `draw()` shows, as instances in back-to-front order, for an
+/// atlas of `texel` atlas units per texel.
+pub(crate) fn instances(game: &Tetris, texel: f64) -> Vec<Instance> {
+    let tile = |column: usize, row: usize| tile(column, row, texel);
+    let region = |x: f64, y: f64, width: f64, height: f64| region(x, y, width, height, texel);
+    let mut instances = Vec::with_capacity(game.width * game.height + 16);
+    let background = tile(if game.settings.grid { GRID } else { PLAIN }, ROW_MISC);
+    for y in 0..game.height {
+        for x in 0..game.width {
+            instances.push(Instance::cell(x as i32, y as i32, background, 1.0));
+        }
+    }
+
+    let mut shattered = Vec::new(); // Fragments fly over other cells, so they go on top
+    for (y, row) in game.board.iter().enumerate() {
+        let clearing = game.clearing_lines.contains(&y);
+        for (x, &cell) in row.iter().enumerate() {
+            if cell == 0 || cell as usize > PALETTE_SIZE {
+                continue;
+            }
+            if !clearing {
+                let lift = game.fall_offset(y);
+                instances.push(Instance {
+                    rect: [x as f32, (y as f64 - lift) as f32, 1.0, 1.0],
+                    ..Instance::cell(x as i32, y as i32, tile(cell as usize, ROW_BLOCK), 1.0)
+                });
+                continue;
+            }
+            let look = game.clear_look(x, y);
+            if look.shatter {
+                for fragment in fragments(x, y, game.clear_progress()) {
+                    let (left, top) = (cell as f64 * TILE, ROW_BLOCK as f64 * TILE);
+                    shattered.push(Instance {
+                        rect: [fragment.x as f32, fragment.y as f32, 0.5, 0.5],
+                        uv: region(left + fragment.corner.0 * TILE, top + fragment.corner.1 * TILE, TILE / 2.0, TILE / 2.0),
+                        alpha: fragment.opacity as f32,
+                    });
+                }
+                continue;
+            }
+            let skin = if look.highlight { ROW_BLOCK } else { ROW_FADING };
+            let inset = (1.0 - look.scale) / 2.0;
+            let rect = [(x as f64 + inset) as f32, (y as f64 + inset) as f32, look.scale as f32, look.scale as f32];
+            instances.push(Instance {
+                rect,
+                uv: tile(cell as usize, skin),
+                alpha: look.opacity as f32,
+            });
+            if look.flash > 0.0 {
+                instances.push(Instance {
+                    rect,
+                    uv: tile(WHITE, ROW_MISC),
+                    alpha: (look.opacity * look.flash) as f32,
+                });
+            }
+        }
+    }
+    instances.append(&mut shattered);
+    for particle in game.particles.iter() {
+        // The middle of the block tile, which is its plain color in every style.
+        let (left, top) = (particle.color as f64 * TILE, ROW_BLOCK as f64 * TILE);
+        let size = particle.size as f32;
+        instances.push(Instance {
+            rect: [particle.x as f32 - size / 2.0, particle.y as f32 - size / 2.0, size, size],
+            uv: region(left + TILE * 0.4, top + TILE * 0.4, TILE * 0.2, TILE * 0.2),
+            alpha: particle.opacity() as f32,
+        });
+    }
+
+    if game.shows_ghost() {
+        if let Some(ghost) = game.ghost_piece() {
+            piece_cells(&ghost, tile(ghost.color as usize, ROW_GHOST), game.theme.ghost_alpha, &mut instances);
+        }
+    }
+    if let Some(hint) = game.hint_outline() {
+        piece_cells(&hint, tile(OUTLINE, ROW_MISC), 1.0, &mut instances);
+    }
+    if let Some(ref piece) = game.current_piece {
+        piece_cells(piece, tile(piece.color as usize, ROW_BLOCK), 1.0, &mut instances);
+    }
+
+    let board = [0.0, 0.0, game.width as f32, game.height as f32];
+    let flash = game.finesse_flash_alpha();
+    if flash > 0.0 {
+        instances.push(Instance {
+            rect: board,
+            uv: tile(RED, ROW_MISC),
+            alpha: flash as f32,
+        });
+    }
+    let label = match game.countdown_label() {
+        _ if game.paused => Some(("PAUSE", 0.7)),
+        Some(label) => Some((label, 0.4)),
+        None => None,
+    };
+    if let Some((label, dim)) = label {
+        instances.push(Instance {
+            rect: board,
+            uv: tile(BLACK, ROW_MISC),
+            alpha: dim,
+        });
+        // The label keeps its atlas size at the usual 30 pixel cells.
+        let (width, height) = (ATLAS_WIDTH / 30.0, TILE * 2.0 / 30.0);
+        let top = LABEL_TOP + TILE * 2.0 * LABELS.iter().position(|&name| name == label).unwrap_or(0) as f64;
+        instances.push(Instance {
+            rect: [
+                ((game.width as f64 - width) / 2.0) as f32,
+                ((game.height as f64 - height) / 2.0) as f32,
+                width as f32,
+                height as f32,
+            ],
+            uv: region(0.0, top, ATLAS_WIDTH, TILE * 2.0),
+            alpha: 1.0,
+        });
+    }
+    instances
+}
+
+pub(crate) struct GlRenderer {
+    canvas: HtmlCanvasElement,
+    gl: Gl,
+    program: WebGlProgram,
+    vao: WebGlVertexArrayObject,
+    instance_buffer: WebGlBuffer,
+    atlas: RefCell<(WebGlTexture, Theme, f64)>, // Repainted when the theme or its scale changes
+    cells: Option<WebGlUniformLocation>,
+    atlas_unit: Option<WebGlUniformLocation>,
+}
+
+impl GlRenderer {
+    pub(crate) fn new(canvas_id: &str) -> Option<GlRenderer> {
+        let canvas = web_sys::window()?
+            .document()?
+            .get_element_by_id(canvas_id)?
+            .dyn_into::<HtmlCanvasElement>()
+            .ok()?;
+        let gl = canvas.get_context("webgl2").ok()??.dyn_into::<Gl>().ok()?;
+        let program = link(&gl)?;
+
+        let vao = gl.create_vertex_array()?;
+        gl.bind_vertex_array(Some(&vao));
+        let corners = gl.create_buffer()?;
+        gl.bind_buffer(Gl::ARRAY_BUFFER, Some(&corners));
+        let quad: [f32; 8] = [0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 1.0];
+        gl.buffer_data_with_array_buffer_view(Gl::ARRAY_BUFFER, &Float32Array::from(&quad[..]), Gl::STATIC_DRAW);
+        gl.enable_vertex_attrib_array(0);
+        gl.vertex_attrib_pointer_with_i32(0, 2, Gl::FLOAT, false, 0, 0);
+
+        let instance_buffer = gl.create_buffer()?;
+        gl.bind_buffer(Gl::ARRAY_BUFFER, Some(&instance_buffer));
+        let stride = (FLOATS_PER_INSTANCE * 4) as i32;
+        for (location, size, offset) in [(1, 4, 0), (2, 4, 16), (3, 1, 32)] {
+            gl.enable_vertex_attrib_array(location);
+            gl.vertex_attrib_pointer_with_i32(location, size, Gl::FLOAT, false, stride, offset);
+            gl.vertex_attrib_divisor(location, 1);
+        }
+        gl.bind_vertex_array(None);
+
+        let theme = Theme::default();
+        let atlas = RefCell::new((build_atlas(&gl, &theme, 1.0)?, theme, 1.0));
+        let cells = gl.get_uniform_location(&program, "u_cells");
+        let atlas_unit = gl.get_uniform_location(&program, "u_atlas");
+        Some(GlRenderer {
+            canvas,
+            gl,
+            program,
+            vao,
+            instance_buffer,
+            atlas,
+            cells,
+            atlas_unit,
+        })
+    }
+
+    pub(crate) fn draw(&self, game: &Tetris) {
+        let gl = &self.gl;
+        let layout = game.fit_board(&self.canvas);
+        let cell = layout.block * layout.ratio;
+        let scale = cell / TILE; // Device pixels per atlas unit
+        let instances = instances(game, 1.0 / scale);
+        let mut data = Vec::with_capacity(instances.len() * FLOATS_PER_INSTANCE);
+        for instance in &instances {
+            data.extend_from_slice(&instance.rect);
+            data.extend_from_slice(&instance.uv);
+            data.push(instance.alpha);
+        }
+
+        // The board sits in the top left corner; GL counts rows from the bottom.
+        let (width, height) = ((cell * game.width as f64) as i32, (cell * game.height as f64) as i32);
+        gl.viewport(0, self.canvas.height() as i32 - height, width, height);
+        gl.clear_color(0.0, 0.0, 0.0, 1.0);
+        gl.clear(Gl::COLOR_BUFFER_BIT);
+        gl.enable(Gl::BLEND);
+        gl.blend_func(Gl::SRC_ALPHA, Gl::ONE_MINUS_SRC_ALPHA);
+
+        gl.use_program(Some(&self.program));
+        gl.bind_vertex_array(Some(&self.vao));
+        gl.bind_buffer(Gl::ARRAY_BUFFER, Some(&self.instance_buffer));
+        gl.buffer_data_with_array_buffer_view(Gl::ARRAY_BUFFER, &Float32Array::from(&data[..]), Gl::DYNAMIC_DRAW);
+        gl.active_texture(Gl::TEXTURE0);
+        let mut atlas = self.atlas.borrow_mut();
+        if atlas.1 != game.theme || atlas.2 != scale {
+            match build_atlas(gl, &game.theme, scale) {
+                Some(texture) => {
+                    gl.delete_texture(Some(&atlas.0));
+                    *atlas = (texture, game.theme.clone(), scale);
+                }
+                None => log("Keeping the previous atlas"),
+            }
+        }
+        gl.bind_texture(Gl::TEXTURE_2D, Some(&atlas.0));
+        gl.uniform1i(self.atlas_unit.as_ref(), 0);
+        gl.uniform2f(self.cells.as_ref(), game.width as f32, game.height as f32);
+        gl.draw_arrays_instanced(Gl::TRIANGLE_STRIP, 0, 4, instances.len() as i32);
+        gl.bind_vertex_array(None);
+    }
+}
+
+fn compile(gl: &Gl, kind: u32, source: &str) -> Option<WebGlShader> {
+    let shader = gl.create_shader(kind)?;
+    gl.shader_source(&shader, source);
+    gl.compile_shader(&shader);
+    if gl.get_shader_parameter(&shader, Gl::COMPILE_STATUS).as_bool() != Some(true) {
+        log(&format!("Shader failed to compile: {}", gl.get_shader_info_log(&shader).unwrap_or_default()));
+        return None;
+    }
+    Some(shader)
+}
+
+fn link(gl: &Gl) -> Option<WebGlProgram> {
+    let vertex = compile(gl, Gl::VERTEX_SHADER, VERTEX_SHADER)?;
+    let fragment = compile(gl, Gl::FRAGMENT_SHADER, FRAGMENT_SHADER)?;
+    let program = gl.create_program()?;
+    gl.attach_shader(&program, &vertex);
+    gl.attach_shader(&program, &fragment);
+    gl.link_program(&program);
+    if gl.get_program_parameter(&program, Gl::LINK_STATUS).as_bool() != Some(true) {
+        log(&format!("Shader program failed to link: {}", gl.get_program_info_log(&program).unwrap_or_default()));
+        return None;
+    }
+    Some(program)
+}
+
+// Paints the theme's block skins into an offscreen 2D canvas, `scale`
+// pixels per atlas unit, and uploads it.
+#[allow(deprecated)]
+fn build_atlas(gl: &Gl, theme: &Theme, scale: f64) -> Option<WebGlTexture> {
+    let canvas = web_sys::window()?
+        .document()?
+        .create_element("canvas")
+        .ok()?
+        .dyn_into::<HtmlCanvasElement>()
+        .ok()?;
+    canvas.set_width((ATLAS_WIDTH * scale).round() as u32);
+    canvas.set_height((ATLAS_HEIGHT * scale).round() as u32);
+    let ctx = canvas.get_context("2d").ok()??.dyn_into::<CanvasRenderingContext2d>().ok()?;
+    let _ = ctx.set_transform(scale, 0.0, 0.0, scale, 0.0, 0.0);
+    let origin = |column: usize, row: usize| (column as f64 * TILE, row as f64 * TILE);
+
+    let (x, y) = origin(GRID, ROW_MISC);
+    paint_background(&ctx, theme, x, y, TILE, true);
+    let (x, y) = origin(PLAIN, ROW_MISC);
+    paint_background(&ctx, theme, x, y, TILE, false);
+    let (x, y) = origin(BLACK, ROW_MISC);
+    ctx.set_fill_style(&"#000".into());
+    ctx.fill_rect(x, y, TILE, TILE);
+    let (x, y) = origin(RED, ROW_MISC);
+    ctx.set_fill_style(&"#f00".into());
+    ctx.fill_rect(x, y, TILE, TILE);
+    let (x, y) = origin(WHITE, ROW_MISC);
+    ctx.set_fill_style(&"#fff".into());
+    ctx.fill_rect(x, y, TILE, TILE);
+    let (x, y) = origin(OUTLINE, ROW_MISC);
+    ctx.set_stroke_style(&"rgba(255, 255, 255, 0.8)".into());
+    ctx.set_line_width(2.0);
+    ctx.stroke_rect(x + 3.0, y + 3.0, TILE - 6.0, TILE - 6.0);
+
+    for color in 1..=PALETTE_SIZE {
+        paint_block(&ctx, theme, color as u8, origin(color, ROW_BLOCK), TILE, true);
+        paint_block(&ctx, theme, color as u8, origin(color, ROW_FADING), TILE, false);
+        let (x, y) = origin(color, ROW_GHOST);
+        paint_ghost(&ctx, theme, color as u8, x, y, TILE);
+    }
+
+    ctx.set_fill_style(&"#FFD700".into());
+    ctx.set_font("40px Arial");
+    ctx.set_text_align("center");
+    ctx.set_text_baseline("middle");
+    for (i, label) in LABELS.iter().enumerate() {
+        let _ = ctx.fill_text(label, ATLAS_WIDTH / 2.0, LABEL_TOP + TILE * (2 * i + 1) as f64);
+    }
+
+    let texture = gl.create_texture()?;
+    gl.bind_texture(Gl::TEXTURE_2D, Some(&texture));
+    if gl
+        .tex_image_2d_with_u32_and_u32_and_html_canvas_element(
+            Gl::TEXTURE_2D,
+            0,
+            Gl::RGBA as i32,
+            Gl::RGBA,
+            Gl::UNSIGNED_BYTE,
+            &canvas,
+        )
+        .is_err()
+    {
+        log("Failed to upload the block atlas");
+        return None;
+    }
+    gl.tex_parameteri(Gl::TEXTURE_2D, Gl::TEXTURE_MIN_FILTER, Gl::LINEAR as i32);
+    gl.tex_parameteri(Gl::TEXTURE_2D, Gl::TEXTURE_MAG_FILTER, Gl::LINEAR as i32);
+    gl.tex_parameteri(Gl::TEXTURE_2D, Gl::TEXTURE_WRAP_S, Gl::CLAMP_TO_EDGE as i32);
+    gl.tex_parameteri(Gl::TEXTURE_2D, Gl::TEXTURE_WRAP_T, Gl::CLAMP_TO_EDGE as i32);
+    Some(texture)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instances() {
+        let mut game = Tetris::with_seed(0);
+        game.start();
+        let cells = game.width * game.height;
+        // Background, ghost and the piece itself.
+        assert_eq!(instances(&game, 1.0).len(), cells + 8);
+
+        game.board[19][0] = 7;
+        game.pause();
+        let all = instances(&game, 1.0);
+        assert_eq!(all.len(), cells + 11);
+        assert_eq!(all[cells], Instance::cell(0, 19, tile(7, ROW_BLOCK, 1.0), 1.0));
+        assert_eq!(all.last().unwrap().alpha, 1.0); // The PAUSE label is on top
+    }
+
+    #[test]
+    fn test_atlas_inset_is_half_a_texel() {
+        // At 75 device pixels a cell, a texel is 32 / 75 atlas units.
+        let [left, top, right, _] = tile(1, 0, TILE / 75.0);
+        assert!(((left as f64 * ATLAS_WIDTH) - (TILE + 16.0 / 75.0)).abs() < 1e-4);
+        assert!((top as f64 * ATLAS_HEIGHT - 16.0 / 75.0).abs() < 1e-4);
+        assert!((right as f64 * ATLAS_WIDTH - (2.0 * TILE - 16.0 / 75.0)).abs() < 1e-4);
+    }
+}
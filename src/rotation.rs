@@ -0,0 +1,98 @@
+//! Half-turn rotation and its wall kicks.
+
+use wasm_bindgen::prelude::*;
+
+use crate::{Action, Tetris};
+
+/// Offsets tried, in order, when a 180° rotation does not fit in place.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kick180 {
+    /// Rotate in place or not at all.
+    None,
+    /// The SRS+ table: kicks depend on the orientation being left.
+    SrsPlus,
+}
+
+// (dx, dy) with y pointing up, as kick tables are usually written. Indexed
+// by the orientation the piece starts in.
+const SRS_PLUS_180: [[(i32, i32); 6]; 4] = [
+    [(0, 0), (0, 1), (1, 1), (-1, 1), (1, 0), (-1, 0)],   // 0 -> 2
+    [(0, 0), (1, 0), (1, 2), (1, 1), (0, 2), (0, 1)],     // R -> L
+    [(0, 0), (0, -1), (-1, -1), (1, -1), (-1, 0), (1, 0)], // 2 -> 0
+    [(0, 0), (-1, 0), (-1, 2), (-1, 1), (0, 2), (0, 1)],  // L -> R
+];
+
+impl Kick180 {
+    pub(crate) fn kicks(self, rotation: u8) -> &'static [(i32, i32)] {
+        match self {
+            Kick180::None => &[(0, 0)],
+            Kick180::SrsPlus => &SRS_PLUS_180[rotation as usize % 4],
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl Tetris {
+    /// Turns the current piece half way round, trying each offset of the
+    /// rules' half-turn kick table until one fits.
+    pub fn rotate_180(&mut self) {
+        if self.paused || self.delay_input(Action::Rotate180) {
+            return;
+        }
+        if !self.clearing_lines.is_empty() {
+            return; // Wait for animation to finish
+        }
+        self.record_input(Action::Rotate180);
+        self.turn(2);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{default_shapes, is_tspin, rotate, T_KIND};
+
+    // A T pointing up (half turned from spawn) above a T-slot whose top is
+    // covered on the left, so it only fits by kicking down.
+    fn game_with_slot() -> Tetris {
+        let mut game = Tetris::with_seed(0);
+        game.start();
+        game.board[19] = vec![1, 1, 1, 1, 0, 1, 1, 1, 1, 1];
+        game.board[17][3] = 1;
+        let mut piece = Tetris::create_piece(&default_shapes(), T_KIND);
+        piece.shape = rotate(&piece.shape, 2);
+        piece.rotation = 2;
+        piece.x = 3;
+        piece.y = 17;
+        game.current_piece = Some(piece);
+        game
+    }
+
+    #[test]
+    fn test_rotate_180_kicks_into_tspin() {
+        let mut game = game_with_slot();
+        game.rotate_180();
+        let piece = game.current_piece.clone().unwrap();
+        assert_eq!((piece.x, piece.y, piece.rotation), (3, 18, 0));
+        assert!(is_tspin(&piece, &game.board, game.width, game.height));
+
+        game.drop();
+        assert_eq!(game.get_single_count(), 1);
+        assert_eq!(game.get_stats().get_attack(), 2); // T-spin single
+    }
+
+    #[test]
+    fn test_rotate_180_without_kicks() {
+        let mut game = game_with_slot();
+        game.rules.kick_180 = Kick180::None;
+        game.rotate_180();
+        let piece = game.current_piece.as_ref().unwrap();
+        assert_eq!((piece.y, piece.rotation), (17, 2));
+
+        game.current_piece = Some(Tetris::create_piece(&default_shapes(), T_KIND));
+        game.apply_action(Action::Rotate180);
+        let piece = game.current_piece.as_ref().unwrap();
+        assert_eq!(piece.shape, vec![vec![0, 1, 0], vec![1, 1, 1]]);
+    }
+}
@@ -0,0 +1,461 @@
+//! Rotation systems: what a piece looks like facing each way, where it
+//! spawns and which kicks a quarter turn tries.
+//!
+//! - Classic turns the piece's bounding box in place, with no kicks.
+//! - SRS turns each piece about the center of a fixed box and kicks by the
+//!   Guideline tables.
+//! - ARS keeps pieces resting on the bottom of their box in every state and
+//!   kicks one column right, then left, as in TGM. The TGM3 variant adds
+//!   floor kicks for the I and T and a two-column wall kick for the I.
+//! - NES turns pieces through the ARS states but never kicks.
+
+use wasm_bindgen::prelude::*;
+
+use crate::rotation::Kick180;
+use crate::{collides, default_shapes, rotate, Piece, Tetris, T_KIND};
+
+const I_KIND: usize = 0;
+const O_KIND: usize = 1;
+const L_KIND: usize = 3;
+const J_KIND: usize = 4;
+const S_KIND: usize = 5;
+const Z_KIND: usize = 6;
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rotation {
+    /// Turns the piece's bounding box in place, with no kicks.
+    Classic,
+    /// The Super Rotation System of the Guideline games.
+    Srs,
+    /// The Arika Rotation System of TGM.
+    Ars,
+    /// The NES game's rotation: the ARS states, without kicks.
+    Nes,
+    /// ARS as TGM3 extends it, with I and T floor kicks.
+    Ars3,
+}
+
+impl Rotation {
+    pub(crate) fn system(self) -> &'static dyn RotationSystem {
+        match self {
+            Rotation::Classic => &Classic,
+            Rotation::Srs => &Srs,
+            Rotation::Ars => &Ars,
+            Rotation::Nes => &Nes,
+            Rotation::Ars3 => &Ars3,
+        }
+    }
+}
+
+pub(crate) trait RotationSystem {
+    /// The cells of `kind` facing `rotation` quarter turns clockwise from spawn.
+    fn shape(&self, kind: usize, rotation: u8) -> Vec<Vec<u8>>;
+
+    /// Offsets (dx, dy), with y pointing up, to try in order when `piece`
+    /// turns into `turned` on `board`. `turned` is the new state in place.
+    fn kicks(&self, piece: &Piece, turned: &Piece, board: &[Vec<u8>]) -> Vec<(i32, i32)>;
+
+    /// The shape `piece` takes in state `rotation`.
+    fn turned_shape(&self, piece: &Piece, rotation: u8) -> Vec<Vec<u8>> {
+        self.shape(piece.kind, rotation)
+    }
+
+    /// A new piece of `kind`, centered on a board `width` cells wide with
+    /// its top cells in the top row.
+    fn spawn(&self, kind: usize, width: usize) -> Piece {
+        let shape = self.shape(kind, 0);
+        let top = shape.iter().take_while(|row| row.iter().all(|&cell| cell == 0)).count();
+        Piece {
+            x: (width.saturating_sub(shape[0].len()) / 2) as i32,
+            y: -(top as i32),
+            color: (kind + 1) as u8,
+            kind,
+            rotation: 0,
+            shape,
+        }
+    }
+}
+
+/// `piece` turned `direction` quarter turns (1 clockwise, -1 counter-clockwise,
+/// 2 half way round) at the first kick that fits, or None. Half turns take
+/// their kicks from `kick_180`, quarter turns from the rotation system.
+pub(crate) fn turn(system: &dyn RotationSystem, kick_180: Kick180, piece: &Piece, direction: i32, board: &[Vec<u8>]) -> Option<Piece> {
+    let rotation = (piece.rotation as i32 + direction).rem_euclid(4) as u8;
+    let mut turned = Piece {
+        shape: system.turned_shape(piece, rotation),
+        rotation,
+        ..piece.clone()
+    };
+    let kicks = if direction == 2 {
+        kick_180.kicks(piece.rotation).to_vec()
+    } else {
+        system.kicks(piece, &turned, board)
+    };
+    let (width, height) = (board[0].len(), board.len());
+    for (dx, dy) in kicks {
+        turned.x = piece.x + dx;
+        turned.y = piece.y - dy;
+        if !collides(&turned, board, width, height) {
+            return Some(turned);
+        }
+    }
+    None
+}
+
+/// The original rotation: a transpose of the piece's cells, in place.
+struct Classic;
+
+impl RotationSystem for Classic {
+    fn shape(&self, kind: usize, rotation: u8) -> Vec<Vec<u8>> {
+        rotate(&default_shapes()[kind], rotation as i32)
+    }
+
+    fn kicks(&self, _: &Piece, _: &Piece, _: &[Vec<u8>]) -> Vec<(i32, i32)> {
+        vec![(0, 0)]
+    }
+
+    // Turns whatever shape the piece has, as the transpose always did.
+    fn turned_shape(&self, piece: &Piece, rotation: u8) -> Vec<Vec<u8>> {
+        rotate(&piece.shape, (rotation as i32 - piece.rotation as i32).rem_euclid(4))
+    }
+
+    fn spawn(&self, kind: usize, _: usize) -> Piece {
+        Tetris::create_piece(&default_shapes(), kind)
+    }
+}
+
+// Spawn states in their boxes: flat side down, as in the Guideline.
+fn srs_boxes() -> Vec<Vec<Vec<u8>>> {
+    vec![
+        vec![vec![0, 0, 0, 0], vec![1, 1, 1, 1], vec![0, 0, 0, 0], vec![0, 0, 0, 0]], // I
+        vec![vec![1, 1], vec![1, 1]], // O
+        vec![vec![0, 1, 0], vec![1, 1, 1], vec![0, 0, 0]], // T
+        vec![vec![0, 0, 1], vec![1, 1, 1], vec![0, 0, 0]], // L
+        vec![vec![1, 0, 0], vec![1, 1, 1], vec![0, 0, 0]], // J
+        vec![vec![0, 1, 1], vec![1, 1, 0], vec![0, 0, 0]], // S
+        vec![vec![1, 1, 0], vec![0, 1, 1], vec![0, 0, 0]], // Z
+    ]
+}
+
+// Guideline kick tables, by the state turned from. y points up.
+const SRS_CW: [[(i32, i32); 5]; 4] = [
+    [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)], // 0 -> R
+    [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],     // R -> 2
+    [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],    // 2 -> L
+    [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],  // L -> 0
+];
+const SRS_CCW: [[(i32, i32); 5]; 4] = [
+    [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],    // 0 -> L
+    [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],     // R -> 0
+    [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)], // 2 -> R
+    [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],  // L -> 2
+];
+const SRS_I_CW: [[(i32, i32); 5]; 4] = [
+    [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)], // 0 -> R
+    [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)], // R -> 2
+    [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)], // 2 -> L
+    [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)], // L -> 0
+];
+const SRS_I_CCW: [[(i32, i32); 5]; 4] = [
+    [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)], // 0 -> L
+    [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)], // R -> 0
+    [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)], // 2 -> R
+    [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)], // L -> 2
+];
+
+struct Srs;
+
+impl RotationSystem for Srs {
+    fn shape(&self, kind: usize, rotation: u8) -> Vec<Vec<u8>> {
+        rotate(&srs_boxes()[kind], rotation as i32)
+    }
+
+    fn kicks(&self, piece: &Piece, turned: &Piece, _: &[Vec<u8>]) -> Vec<(i32, i32)> {
+        let clockwise = turned.rotation == (piece.rotation + 1) % 4;
+        let from = piece.rotation as usize % 4;
+        let table = match (piece.kind, clockwise) {
+            (O_KIND, _) => return vec![(0, 0)],
+            (I_KIND, true) => &SRS_I_CW,
+            (I_KIND, false) => &SRS_I_CCW,
+            (_, true) => &SRS_CW,
+            (_, false) => &SRS_CCW,
+        };
+        table[from].to_vec()
+    }
+}
+
+// Spawn states in their boxes: flat side up, resting on the bottom.
+fn ars_boxes() -> Vec<Vec<Vec<u8>>> {
+    vec![
+        vec![vec![0, 0, 0, 0], vec![1, 1, 1, 1], vec![0, 0, 0, 0], vec![0, 0, 0, 0]], // I
+        vec![vec![1, 1], vec![1, 1]], // O
+        vec![vec![0, 0, 0], vec![1, 1, 1], vec![0, 1, 0]], // T
+        vec![vec![0, 0, 0], vec![1, 1, 1], vec![1, 0, 0]], // L
+        vec![vec![0, 0, 0], vec![1, 1, 1], vec![0, 0, 1]], // J
+        vec![vec![0, 0, 0], vec![0, 1, 1], vec![1, 1, 0]], // S
+        vec![vec![0, 0, 0], vec![1, 1, 0], vec![0, 1, 1]], // Z
+    ]
+}
+
+// Moves every row of a box down one, the bottom row being empty.
+fn drop_row(mut shape: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+    shape.rotate_right(1);
+    shape
+}
+
+struct Ars;
+
+impl RotationSystem for Ars {
+    fn shape(&self, kind: usize, rotation: u8) -> Vec<Vec<u8>> {
+        let spawn = &ars_boxes()[kind];
+        match (kind, rotation % 4) {
+            (O_KIND, _) => spawn.clone(),
+            // I, S and Z have two states, and Z stands in the right two columns.
+            (I_KIND | S_KIND, rotation) => rotate(spawn, (rotation % 2) as i32),
+            (Z_KIND, rotation) if rotation % 2 == 0 => spawn.clone(),
+            (Z_KIND, _) => {
+                let mut shape = rotate(spawn, 1);
+                for row in &mut shape {
+                    row.rotate_right(1);
+                }
+                shape
+            }
+            // Upside down, the three-wide pieces drop back to the bottom.
+            (_, 2) => drop_row(rotate(spawn, 2)),
+            (_, rotation) => rotate(spawn, rotation as i32),
+        }
+    }
+
+    /// In place, then one right, then one left; no floor kicks, and the I
+    /// never kicks. L, J and T don't kick when the first blocked cell, in
+    /// reading order, is in the center column.
+    fn kicks(&self, piece: &Piece, turned: &Piece, board: &[Vec<u8>]) -> Vec<(i32, i32)> {
+        if piece.kind == I_KIND || piece.kind == O_KIND {
+            return vec![(0, 0)];
+        }
+        if matches!(piece.kind, T_KIND | L_KIND | J_KIND) && first_blocked_column(turned, board) == Some(1) {
+            return vec![(0, 0)];
+        }
+        vec![(0, 0), (1, 0), (-1, 0)]
+    }
+}
+
+struct Ars3;
+
+impl RotationSystem for Ars3 {
+    fn shape(&self, kind: usize, rotation: u8) -> Vec<Vec<u8>> {
+        Ars.shape(kind, rotation)
+    }
+
+    /// TGM's kicks, then: an I that has landed may stand up one or two rows
+    /// higher, and lying down kicks one column right, one left or two
+    /// right; a T may kick up one row.
+    fn kicks(&self, piece: &Piece, turned: &Piece, board: &[Vec<u8>]) -> Vec<(i32, i32)> {
+        let (width, height) = (board[0].len(), board.len());
+        match piece.kind {
+            I_KIND if turned.rotation % 2 == 1 => {
+                if collides(&Piece { y: piece.y + 1, ..piece.clone() }, board, width, height) {
+                    vec![(0, 0), (0, 1), (0, 2)]
+                } else {
+                    vec![(0, 0)]
+                }
+            }
+            I_KIND => vec![(0, 0), (1, 0), (-1, 0), (2, 0)],
+            T_KIND => {
+                let mut kicks = Ars.kicks(piece, turned, board);
+                kicks.push((0, 1));
+                kicks
+            }
+            _ => Ars.kicks(piece, turned, board),
+        }
+    }
+}
+
+struct Nes;
+
+impl RotationSystem for Nes {
+    fn shape(&self, kind: usize, rotation: u8) -> Vec<Vec<u8>> {
+        Ars.shape(kind, rotation)
+    }
+
+    fn kicks(&self, _: &Piece, _: &Piece, _: &[Vec<u8>]) -> Vec<(i32, i32)> {
+        vec![(0, 0)]
+    }
+}
+
+// Column within the box of the first cell of `piece`, row by row, that is
+// off the board or filled.
+fn first_blocked_column(piece: &Piece, board: &[Vec<u8>]) -> Option<usize> {
+    let (width, height) = (board[0].len() as i32, board.len() as i32);
+    for (y, row) in piece.shape.iter().enumerate() {
+        for (x, &cell) in row.iter().enumerate() {
+            let (board_x, board_y) = (piece.x + x as i32, piece.y + y as i32);
+            let blocked = board_x < 0
+                || board_x >= width
+                || board_y >= height
+                || (board_y >= 0 && board[board_y as usize][board_x as usize] != 0);
+            if cell != 0 && blocked {
+                return Some(x);
+            }
+        }
+    }
+    None
+}
+
+impl Tetris {
+    /// A new piece of `kind`, as the rules' rotation system spawns it.
+    pub(crate) fn new_piece(&self, kind: usize) -> Piece {
+        self.rules.rotation.system().spawn(kind, self.width)
+    }
+
+    /// Turns the current piece a quarter or half turn, kicking as the rules allow.
+    pub(crate) fn turn(&mut self, direction: i32) {
+        let system = self.rules.rotation.system();
+        let turned = match self.current_piece {
+            Some(ref piece) => turn(system, self.rules.kick_180, piece, direction, &self.board),
+            None => return,
+        };
+        if let Some(turned) = turned {
+            self.current_piece = Some(turned);
+            self.last_move_rotation = true;
+            self.push_event(crate::events::GameEvent::Rotate);
+            self.moved();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board() -> Vec<Vec<u8>> {
+        vec![vec![0; 10]; 20]
+    }
+
+    fn cells(piece: &Piece) -> Vec<(i32, i32)> {
+        let mut cells = Vec::new();
+        for (y, row) in piece.shape.iter().enumerate() {
+            for (x, &cell) in row.iter().enumerate() {
+                if cell != 0 {
+                    cells.push((piece.x + x as i32, piece.y + y as i32));
+                }
+            }
+        }
+        cells
+    }
+
+    #[test]
+    fn test_states() {
+        for system in [Rotation::Classic, Rotation::Srs, Rotation::Ars, Rotation::Nes, Rotation::Ars3].map(Rotation::system) {
+            for kind in 0..7 {
+                let piece = system.spawn(kind, 10);
+                assert_eq!(cells(&piece).iter().map(|cell| cell.1).min(), Some(0));
+                for rotation in 0..4 {
+                    let count: u8 = system.shape(kind, rotation).iter().flatten().sum();
+                    assert_eq!(count, 4);
+                }
+            }
+        }
+        // ARS states rest on the bottom of the box; the T stays put when flipped.
+        let t = Ars.spawn(T_KIND, 10);
+        assert_eq!(t.shape, vec![vec![0, 0, 0], vec![1, 1, 1], vec![0, 1, 0]]);
+        assert_eq!(Ars.shape(T_KIND, 2), vec![vec![0, 0, 0], vec![0, 1, 0], vec![1, 1, 1]]);
+        assert_eq!(Ars.shape(S_KIND, 2), Ars.shape(S_KIND, 0));
+        assert_eq!(Ars.shape(Z_KIND, 1), vec![vec![0, 0, 1], vec![0, 1, 1], vec![0, 1, 0]]);
+        // SRS turns about the box center: the I stays in its box.
+        let i = Srs.spawn(I_KIND, 10);
+        assert_eq!(cells(&i), vec![(3, 0), (4, 0), (5, 0), (6, 0)]);
+        let turned = turn(&Srs, Kick180::None, &i, 1, &board()).unwrap();
+        assert_eq!(cells(&turned), vec![(5, -1), (5, 0), (5, 1), (5, 2)]);
+    }
+
+    #[test]
+    fn test_ars_kicks() {
+        // A T upright against the left wall kicks right to turn flat.
+        let mut board = board();
+        let mut t = Ars.spawn(T_KIND, 10);
+        t.shape = Ars.shape(T_KIND, 3);
+        t.rotation = 3;
+        t.x = -1;
+        t.y = 10;
+        let turned = turn(&Ars, Kick180::None, &t, 1, &board).unwrap();
+        assert_eq!((turned.x, turned.rotation), (0, 0));
+
+        // An L blocked in the center column first doesn't kick...
+        let mut l = Ars.spawn(L_KIND, 10);
+        l.y = 10;
+        board[10][4] = 8;
+        assert!(turn(&Ars, Kick180::None, &l, 1, &board).is_none());
+        // ...but blocked only at the side, it kicks away.
+        board[10][4] = 0;
+        board[10][3] = 8;
+        let turned = turn(&Ars, Kick180::None, &l, 1, &board).unwrap();
+        assert_eq!(turned.x, l.x + 1);
+
+        // No floor kicks: a flat I on the floor can't stand up.
+        let mut i = Ars.spawn(I_KIND, 10);
+        i.y = 18;
+        assert!(turn(&Ars, Kick180::None, &i, 1, &board).is_none());
+
+        // NES turns the same way, but never kicks off the wall.
+        assert!(turn(&Nes, Kick180::None, &t, 1, &board).is_none());
+        t.x = 0;
+        assert_eq!(turn(&Nes, Kick180::None, &t, 1, &board).unwrap().shape, Ars.shape(T_KIND, 0));
+    }
+
+    #[test]
+    fn test_ars3_floor_kicks() {
+        // A T with its stem in a one-cell hole flips flat by stepping up a row.
+        let empty = board();
+        let mut board = board();
+        board[19] = vec![1, 1, 1, 1, 0, 1, 1, 1, 1, 1];
+        let mut t = Ars3.spawn(T_KIND, 10);
+        t.shape = Ars3.shape(T_KIND, 1);
+        t.rotation = 1;
+        t.y = 17;
+        assert!(turn(&Ars, Kick180::None, &t, 1, &board).is_none());
+        let turned = turn(&Ars3, Kick180::None, &t, 1, &board).unwrap();
+        assert_eq!((turned.x, turned.y, turned.rotation), (t.x, 16, 2));
+
+        // A flat I on the floor stands up two rows higher.
+        let mut i = Ars3.spawn(I_KIND, 10);
+        i.y = 17;
+        let turned = turn(&Ars3, Kick180::None, &i, 1, &board).unwrap();
+        assert_eq!(turned.y, 15);
+        // Standing against the left wall, it lies down two columns over.
+        let mut i = turned;
+        i.x = -2;
+        let turned = turn(&Ars3, Kick180::None, &i, 1, &empty).unwrap();
+        assert_eq!(turned.x, 0);
+    }
+
+    #[test]
+    fn test_srs_floor_kick() {
+        // A flat T on the floor turns upright by stepping up and left.
+        let mut t = Srs.spawn(T_KIND, 10);
+        t.y = 18;
+        let turned = turn(&Srs, Kick180::None, &t, 1, &board()).unwrap();
+        assert_eq!((turned.x, turned.y), (t.x - 1, 17));
+        assert!(Ars.kicks(&t, &turned, &board()).iter().all(|&(_, dy)| dy == 0));
+    }
+
+    #[test]
+    fn test_game_turns_by_the_rules() {
+        let mut game = Tetris::with_seed(0);
+        game.set_rule_preset(crate::RulePreset::Tgm1);
+        game.start();
+        let kind = game.current_piece.as_ref().unwrap().kind;
+        game.rotate_right();
+        let piece = game.current_piece.as_ref().unwrap();
+        assert_eq!(piece.shape, Ars.shape(kind, 1));
+
+        // A box hanging off the left wall locks into column 0.
+        let mut z = Ars.spawn(Z_KIND, 10);
+        z.shape = Ars.shape(Z_KIND, 1);
+        z.x = -1;
+        game.current_piece = Some(z);
+        game.drop();
+        assert_eq!(game.board[19][0], Z_KIND as u8 + 1);
+    }
+}
@@ -0,0 +1,538 @@
+//! Rule sets: how pieces are dealt, turn, fall, lock and score, and what
+//! ends the game. Presets reproduce well-known games; changing any rule
+//! makes a custom set.
+
+use wasm_bindgen::prelude::*;
+
+use crate::randomizer::{Randomizer, MAX_PREVIEWS};
+use crate::rotation::Kick180;
+use crate::rotation_system::Rotation;
+use crate::{Tetris, FRAME_TIME, GRAVITY_FRAMES};
+
+/// Guideline lock out: a piece that locks entirely within the top rows,
+/// where pieces spawn, ends the game.
+pub(crate) const SKYLINE: i32 = 2;
+const LOCK_DELAY_RANGE: (f64, f64) = (0.0, 5.0);
+const MAX_LOCK_RESETS: u32 = 100;
+const INSTANT_ROWS: u32 = 20; // 20G: twenty rows a frame, so straight to the floor
+// Frames per row on the NES, by level from 0. Level 29 and up fall every frame.
+const NES_FRAMES: [u32; 29] = [48, 43, 38, 33, 28, 23, 18, 13, 8, 6, 5, 5, 5, 4, 4, 4, 3, 3, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2];
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RulePreset {
+    /// This game's own rules: instant lock, flat scoring and one preview.
+    Original,
+    Guideline,
+    Nes,
+    Tgm1,
+    /// Anything else.
+    Custom,
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Gravity {
+    /// One row a second at every level.
+    Fixed,
+    /// The Guideline curve: (0.8 - (level - 1) * 0.007) ^ (level - 1) seconds a row.
+    Guideline,
+    /// The NES speed table.
+    Nes,
+    /// 20G: pieces fall to the floor as they spawn.
+    Instant,
+}
+
+impl Gravity {
+    /// Every how many frames the piece falls, and by how many rows.
+    pub(crate) fn at(self, level: u32) -> (u32, u32) {
+        match self {
+            Gravity::Fixed => (GRAVITY_FRAMES, 1),
+            Gravity::Guideline => {
+                let level = level.max(1) as f64;
+                let frames = (0.8 - (level - 1.0) * 0.007).max(0.0).powf(level - 1.0) * 60.0;
+                if frames >= 1.0 {
+                    (frames.round() as u32, 1)
+                } else {
+                    (1, ((1.0 / frames).round() as u32).min(INSTANT_ROWS))
+                }
+            }
+            Gravity::Nes => (NES_FRAMES.get(level.saturating_sub(1) as usize).copied().unwrap_or(1), 1),
+            Gravity::Instant => (1, INSTANT_ROWS),
+        }
+    }
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scoring {
+    /// 100, 300, 600 or 1000 a clear, whatever the level.
+    Original,
+    /// Level times 100, 300, 500 or 800, more for T-spins, back-to-backs
+    /// and combos, plus points for dropped rows.
+    Guideline,
+    /// Level times 40, 100, 300 or 1200, plus a point a soft-dropped row.
+    Nes,
+    /// The TGM formula: the level and lines cleared, by the lines, by the
+    /// combo, quadrupled for clearing the board. Without soft drop points.
+    Tgm,
+}
+
+/// A clear, as scoring sees it.
+pub(crate) struct Clear {
+    pub(crate) lines: u32,
+    pub(crate) tspin: bool,
+    pub(crate) b2b: bool,   // Follows another Tetris or T-spin clear
+    pub(crate) combo: u32,  // Clears in a row before this one
+    pub(crate) perfect: bool, // Leaves the board empty
+}
+
+impl Scoring {
+    /// `level` is the level before the clear and `total` the lines cleared before it.
+    pub(crate) fn clear_points(self, clear: &Clear, level: u32, total: u32) -> u32 {
+        let lines = clear.lines.min(4) as usize;
+        match self {
+            Scoring::Original => [0, 100, 300, 600, 1000][lines],
+            Scoring::Guideline => {
+                let base = if clear.tspin { [400, 800, 1200, 1600, 1600][lines] } else { [0, 100, 300, 500, 800][lines] };
+                let base = if clear.b2b { base * 3 / 2 } else { base };
+                (base + 50 * clear.combo) * level
+            }
+            Scoring::Nes => [0, 40, 100, 300, 1200][lines] * level,
+            Scoring::Tgm => {
+                let bravo = if clear.perfect { 4 } else { 1 };
+                (total + clear.lines).div_ceil(4) * clear.lines * (clear.combo + 1) * bravo
+            }
+        }
+    }
+
+    pub(crate) fn drop_points(self, rows: u32, hard: bool) -> u32 {
+        match self {
+            Scoring::Guideline if hard => rows * 2,
+            Scoring::Guideline => rows,
+            Scoring::Nes if !hard => rows,
+            _ => 0,
+        }
+    }
+}
+
+/// Everything that decides how a game plays.
+#[wasm_bindgen]
+#[derive(Clone, Debug, PartialEq)]
+pub struct RuleSet {
+    preset: RulePreset,
+    pub(crate) randomizer: Randomizer,
+    pub(crate) rotation: Rotation,
+    pub(crate) kick_180: Kick180,
+    pub(crate) lock_delay: f64, // Seconds a grounded piece waits before locking; 0 locks at once
+    pub(crate) lock_resets: u32, // Moves and turns that restart the lock delay, per row reached
+    pub(crate) gravity: Gravity,
+    pub(crate) scoring: Scoring,
+    pub(crate) previews: usize,
+    pub(crate) hold: bool,
+    pub(crate) ghost: bool,    // Allowed at all; the ghost setting still applies
+    pub(crate) lock_out: bool, // Locking entirely above the skyline ends the game
+    pub(crate) garbage_out: bool, // Garbage pushing blocks off the top ends the game
+}
+
+impl Default for RuleSet {
+    fn default() -> RuleSet {
+        RuleSet::preset(RulePreset::Original)
+    }
+}
+
+#[wasm_bindgen]
+impl RuleSet {
+    /// The original rules, to customise.
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> RuleSet {
+        RuleSet {
+            preset: RulePreset::Custom,
+            ..RuleSet::default()
+        }
+    }
+
+    /// The rules of a preset. `Custom` gives the original rules.
+    pub fn preset(preset: RulePreset) -> RuleSet {
+        let original = RuleSet {
+            preset,
+            randomizer: Randomizer::Random,
+            rotation: Rotation::Classic,
+            kick_180: Kick180::SrsPlus,
+            lock_delay: 0.0,
+            lock_resets: 0,
+            gravity: Gravity::Fixed,
+            scoring: Scoring::Original,
+            previews: 1,
+            hold: true,
+            ghost: true,
+            lock_out: false,
+            garbage_out: true,
+        };
+        match preset {
+            RulePreset::Original | RulePreset::Custom => original,
+            RulePreset::Guideline => RuleSet {
+                randomizer: Randomizer::Bag,
+                rotation: Rotation::Srs,
+                lock_delay: 0.5,
+                lock_resets: 15,
+                gravity: Gravity::Guideline,
+                scoring: Scoring::Guideline,
+                previews: MAX_PREVIEWS,
+                lock_out: true,
+                ..original
+            },
+            RulePreset::Nes => RuleSet {
+                randomizer: Randomizer::Nes,
+                rotation: Rotation::Nes,
+                kick_180: Kick180::None,
+                gravity: Gravity::Nes,
+                scoring: Scoring::Nes,
+                hold: false,
+                ghost: false,
+                ..original
+            },
+            RulePreset::Tgm1 => RuleSet {
+                randomizer: Randomizer::Tgm,
+                rotation: Rotation::Ars,
+                kick_180: Kick180::None,
+                lock_delay: 0.5,
+                gravity: Gravity::Instant,
+                scoring: Scoring::Tgm,
+                hold: false,
+                ghost: false,
+                ..original
+            },
+        }
+    }
+
+    pub fn get_preset(&self) -> RulePreset {
+        self.preset
+    }
+
+    pub fn get_randomizer(&self) -> Randomizer {
+        self.randomizer
+    }
+
+    pub fn set_randomizer(&mut self, randomizer: Randomizer) {
+        self.randomizer = randomizer;
+        self.preset = RulePreset::Custom;
+    }
+
+    pub fn get_rotation(&self) -> Rotation {
+        self.rotation
+    }
+
+    pub fn set_rotation(&mut self, rotation: Rotation) {
+        self.rotation = rotation;
+        self.preset = RulePreset::Custom;
+    }
+
+    pub fn get_kick_180(&self) -> Kick180 {
+        self.kick_180
+    }
+
+    /// The kicks a half turn tries when it doesn't fit in place.
+    pub fn set_kick_180(&mut self, table: Kick180) {
+        self.kick_180 = table;
+        self.preset = RulePreset::Custom;
+    }
+
+    pub fn get_lock_delay(&self) -> f64 {
+        self.lock_delay
+    }
+
+    /// Seconds a piece rests on the stack before it locks. 0 locks as it lands.
+    pub fn set_lock_delay(&mut self, seconds: f64) {
+        if !seconds.is_nan() {
+            self.lock_delay = seconds.clamp(LOCK_DELAY_RANGE.0, LOCK_DELAY_RANGE.1);
+            self.preset = RulePreset::Custom;
+        }
+    }
+
+    pub fn get_lock_resets(&self) -> u32 {
+        self.lock_resets
+    }
+
+    /// How many moves or turns on the stack restart the lock delay before
+    /// the piece reaches a lower row.
+    pub fn set_lock_resets(&mut self, resets: u32) {
+        self.lock_resets = resets.min(MAX_LOCK_RESETS);
+        self.preset = RulePreset::Custom;
+    }
+
+    pub fn get_gravity(&self) -> Gravity {
+        self.gravity
+    }
+
+    pub fn set_gravity(&mut self, gravity: Gravity) {
+        self.gravity = gravity;
+        self.preset = RulePreset::Custom;
+    }
+
+    pub fn get_scoring(&self) -> Scoring {
+        self.scoring
+    }
+
+    pub fn set_scoring(&mut self, scoring: Scoring) {
+        self.scoring = scoring;
+        self.preset = RulePreset::Custom;
+    }
+
+    pub fn get_previews(&self) -> usize {
+        self.previews
+    }
+
+    /// Pieces shown by `draw_next`, from 0 to 6.
+    pub fn set_previews(&mut self, previews: usize) {
+        self.previews = previews.min(MAX_PREVIEWS);
+        self.preset = RulePreset::Custom;
+    }
+
+    pub fn get_hold(&self) -> bool {
+        self.hold
+    }
+
+    pub fn set_hold(&mut self, hold: bool) {
+        self.hold = hold;
+        self.preset = RulePreset::Custom;
+    }
+
+    pub fn get_ghost(&self) -> bool {
+        self.ghost
+    }
+
+    pub fn set_ghost(&mut self, ghost: bool) {
+        self.ghost = ghost;
+        self.preset = RulePreset::Custom;
+    }
+
+    pub fn get_lock_out(&self) -> bool {
+        self.lock_out
+    }
+
+    pub fn set_lock_out(&mut self, lock_out: bool) {
+        self.lock_out = lock_out;
+        self.preset = RulePreset::Custom;
+    }
+
+    pub fn get_garbage_out(&self) -> bool {
+        self.garbage_out
+    }
+
+    /// Whether garbage pushing blocks off the top ends the game, or they
+    /// are lost.
+    pub fn set_garbage_out(&mut self, garbage_out: bool) {
+        self.garbage_out = garbage_out;
+        self.preset = RulePreset::Custom;
+    }
+}
+
+impl Tetris {
+    /// Every how many frames gravity pulls, and by how many rows.
+    pub(crate) fn gravity(&self) -> (u32, u32) {
+        self.rules.gravity.at(self.get_level())
+    }
+
+    pub(crate) fn shows_ghost(&self) -> bool {
+        self.settings.ghost && self.rules.ghost
+    }
+
+    /// One row of soft drop, scored by the rules.
+    pub(crate) fn soft_drop(&mut self) {
+        let falls = !self.paused && self.countdown <= 0.0 && self.clearing_lines.is_empty() && self.can_fall();
+        self.move_down();
+        if falls {
+            self.score += self.rules.scoring.drop_points(1, false);
+        }
+    }
+
+    /// Pulls the piece down by the rules' gravity as time passes. Stops
+    /// once the piece lands, so a long frame can't drop the pieces after it.
+    pub(crate) fn update_gravity(&mut self, delta_time: f64) {
+        if self.paused || self.game_over || self.countdown > 0.0 || self.current_piece.is_none() || !self.clearing_lines.is_empty() {
+            return;
+        }
+        let (frames, rows) = self.gravity();
+        let interval = frames as f64 * FRAME_TIME;
+        self.gravity_time += delta_time;
+        while self.gravity_time >= interval - 1e-9 {
+            self.gravity_time -= interval;
+            for _ in 1..rows {
+                if !self.can_fall() {
+                    break;
+                }
+                self.move_down();
+            }
+            if !self.can_fall() {
+                self.gravity_time = 0.0;
+                self.move_down(); // Locks, without a lock delay
+                break;
+            }
+            self.move_down();
+        }
+    }
+
+    /// Forgets the lock delay, for a new piece.
+    pub(crate) fn reset_lock(&mut self) {
+        self.lock_time = 0.0;
+        self.lock_resets = 0;
+        self.lowest_y = self.current_piece.as_ref().map_or(0, |piece| piece.y);
+    }
+
+    /// A move or turn succeeded: restarts the lock delay of a grounded
+    /// piece while it has resets left.
+    pub(crate) fn moved(&mut self) {
+        if self.lock_time > 0.0 && self.lock_resets < self.rules.lock_resets {
+            self.lock_time = 0.0;
+            self.lock_resets += 1;
+        }
+    }
+
+    /// Counts a grounded piece's lock delay, locking it when it runs out.
+    /// Reaching a new lowest row restarts the delay and the resets.
+    pub(crate) fn update_lock(&mut self, delta_time: f64) {
+        if self.paused || self.game_over || self.countdown > 0.0 || !self.clearing_lines.is_empty() || self.rules.lock_delay <= 0.0 {
+            return;
+        }
+        let y = match self.current_piece {
+            Some(ref piece) => piece.y,
+            None => return,
+        };
+        if y > self.lowest_y {
+            self.lowest_y = y;
+            self.lock_time = 0.0;
+            self.lock_resets = 0;
+        }
+        if self.can_fall() {
+            return;
+        }
+        self.lock_time += delta_time;
+        if self.lock_time >= self.rules.lock_delay - 1e-9 {
+            self.lock_piece();
+        }
+    }
+
+    /// Whether the piece just locked broke a top-out rule.
+    pub(crate) fn locked_out(&self) -> bool {
+        let bottom = |piece: &crate::Piece| piece.y + piece.shape.iter().rposition(|row| row.contains(&1)).unwrap_or(0) as i32;
+        self.rules.lock_out && self.current_piece.as_ref().is_some_and(|piece| bottom(piece) < SKYLINE)
+    }
+}
+
+#[wasm_bindgen]
+impl Tetris {
+    pub fn get_rules(&self) -> RuleSet {
+        self.rules.clone()
+    }
+
+    /// Plays by `rules`. Only takes effect before `start()`, as the rules
+    /// decide which pieces are dealt.
+    pub fn set_rules(&mut self, rules: &RuleSet) {
+        if self.in_play() {
+            return;
+        }
+        self.rules = rules.clone();
+        self.rng = crate::rng::Rng::new(self.seed);
+        self.deal_queue();
+    }
+
+    /// Plays by a preset's rules, in one call. Only before `start()`.
+    pub fn set_rule_preset(&mut self, preset: RulePreset) {
+        self.set_rules(&RuleSet::preset(preset));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{default_shapes, Action};
+
+    #[test]
+    fn test_presets() {
+        assert_eq!(RuleSet::default().get_preset(), RulePreset::Original);
+        let mut rules = RuleSet::preset(RulePreset::Nes);
+        assert!(!rules.get_hold());
+        assert_eq!((rules.get_rotation(), rules.get_kick_180()), (Rotation::Nes, Kick180::None));
+        rules.set_previews(10);
+        assert_eq!((rules.get_previews(), rules.get_preset()), (MAX_PREVIEWS, RulePreset::Custom));
+
+        assert_eq!(Gravity::Nes.at(1), (48, 1));
+        assert_eq!(Gravity::Nes.at(40), (1, 1));
+        assert_eq!(Gravity::Guideline.at(1), (60, 1));
+        assert!(Gravity::Guideline.at(15).1 > 1);
+        assert_eq!(Gravity::Instant.at(1), (1, INSTANT_ROWS));
+
+        let tetris = Clear {
+            lines: 4,
+            tspin: false,
+            b2b: true,
+            combo: 1,
+            perfect: false,
+        };
+        assert_eq!(Scoring::Original.clear_points(&tetris, 3, 0), 1000);
+        assert_eq!(Scoring::Guideline.clear_points(&tetris, 2, 0), (1200 + 50) * 2);
+        assert_eq!(Scoring::Nes.clear_points(&tetris, 2, 0), 2400);
+        assert_eq!(Scoring::Tgm.clear_points(&tetris, 1, 8), 3 * 4 * 2);
+    }
+
+    #[test]
+    fn test_one_call_selects_a_preset() {
+        let mut game = Tetris::with_seed(5);
+        game.set_rule_preset(RulePreset::Guideline);
+        game.start();
+        assert_eq!(game.previews().len(), MAX_PREVIEWS);
+        // The first bag deals all seven kinds.
+        let mut kinds: Vec<usize> = game.previews().iter().map(|piece| piece.kind).collect();
+        kinds.push(game.current_piece.as_ref().unwrap().kind);
+        kinds.sort();
+        assert_eq!(kinds, (0..7).collect::<Vec<_>>());
+
+        // Rules are fixed once the game is on.
+        game.set_rule_preset(RulePreset::Nes);
+        assert_eq!(game.get_rules().get_preset(), RulePreset::Guideline);
+    }
+
+    #[test]
+    fn test_gravity_runs_in_update() {
+        let mut game = Tetris::with_seed(0);
+        game.start();
+        let y = game.current_piece.as_ref().unwrap().y;
+        game.update(0.5);
+        assert_eq!(game.current_piece.as_ref().unwrap().y, y);
+        game.update(0.5);
+        assert_eq!(game.current_piece.as_ref().unwrap().y, y + 1);
+
+        // 20G lands the piece on the first frame.
+        let mut game = Tetris::with_seed(0);
+        game.set_rule_preset(RulePreset::Tgm1);
+        game.start();
+        game.update(FRAME_TIME);
+        assert!(!game.can_fall());
+        assert_eq!(game.stats.pieces_placed, 0);
+    }
+
+    #[test]
+    fn test_lock_delay() {
+        let mut game = Tetris::with_seed(0);
+        game.set_rule_preset(RulePreset::Guideline);
+        game.start();
+        game.current_piece = Some(Tetris::create_piece(&default_shapes(), 1));
+        while game.can_fall() {
+            game.soft_drop();
+        }
+        assert_eq!(game.get_score(), 18); // A point a row
+        game.move_down(); // Gravity on the floor doesn't lock
+        assert_eq!(game.stats.pieces_placed, 0);
+        for _ in 0..20 {
+            game.step();
+        }
+        game.apply_action(Action::MoveLeft); // Restarts the delay
+        for _ in 0..29 {
+            game.step();
+        }
+        assert_eq!(game.stats.pieces_placed, 0);
+        game.step();
+        assert_eq!(game.stats.pieces_placed, 1);
+    }
+}
@@ -0,0 +1,551 @@
+//! Player preferences: handling, visuals and controls. Stored as JSON in
+//! localStorage and applied to a running game with `Tetris::set_settings`.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+use crate::storage::{log, LocalStorage, Storage};
+use crate::theme::THEMES;
+use crate::{Action, ClearEffect, ClearKind, Tetris, Theme};
+
+pub const STORAGE_KEY: &str = "tetris-wasm.settings";
+
+const DAS_RANGE: (f64, f64) = (0.0, 1000.0);
+const ARR_RANGE: (f64, f64) = (0.0, 500.0);
+const SDF_RANGE: (f64, f64) = (1.0, 100.0);
+const ANIMATION_SPEED_RANGE: (f64, f64) = (0.1, 10.0);
+const CLEAR_DURATION_RANGE: (f64, f64) = (0.0, 2.0);
+const FALL_DURATION_RANGE: (f64, f64) = (0.0, 1.0);
+const COUNTDOWN_RANGE: (f64, f64) = (0.0, 5.0);
+const ARE_RANGE: (f64, f64) = (0.0, 1.0);
+const VOLUME_RANGE: (f64, f64) = (0.0, 1.0);
+const DEADZONE_RANGE: (f64, f64) = (0.05, 0.95);
+
+#[wasm_bindgen]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub(crate) das: f64, // Milliseconds a move is held before it repeats
+    pub(crate) arr: f64, // Milliseconds between repeats; 0 shifts straight to the wall
+    pub(crate) sdf: f64, // Soft drop speed as a multiple of gravity
+    pub(crate) ghost: bool,
+    pub(crate) grid: bool,
+    pub(crate) animation_speed: f64, // 2.0 plays line clears twice as fast
+    pub(crate) clear_duration: f64,  // Seconds a line clear plays; 0 clears at once
+    pub(crate) fall_duration: f64,   // Seconds the rows above take to drop into place
+    pub(crate) clear_effects: BTreeMap<ClearKind, ClearEffect>,
+    pub(crate) countdown: f64, // Seconds of READY / GO before play and after unpausing
+    pub(crate) are: f64,       // Seconds between a lock and the next spawn
+    pub(crate) line_are: f64,  // The same, after a line clear has played
+    pub(crate) particles: bool, // Sparks on hard drops and bursts on clears
+    pub(crate) master_volume: f64, // 0 to 1, with the `audio` feature
+    pub(crate) sfx_volume: f64,
+    pub(crate) music_volume: f64,
+    pub(crate) theme: String,
+    pub(crate) themes: Vec<Theme>, // Custom themes, selectable by name
+    pub(crate) patterns: bool,     // A glyph per piece kind inside every block
+    // KeyboardEvent.code values, or gamepad codes such as "GamepadButton0"
+    pub(crate) keybindings: BTreeMap<Action, Vec<String>>,
+    pub(crate) pause_keys: Vec<String>,
+    pub(crate) deadzone: f64, // How far a stick must tilt to count as pressed
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        let keys = |codes: &[&str]| codes.iter().map(|code| code.to_string()).collect::<Vec<_>>();
+        let keybindings = BTreeMap::from([
+            (Action::MoveLeft, keys(&["KeyA", "ArrowLeft", "GamepadButton14", "GamepadAxis0-"])),
+            (Action::MoveRight, keys(&["KeyD", "ArrowRight", "GamepadButton15", "GamepadAxis0+"])),
+            (Action::SoftDrop, keys(&["KeyS", "ArrowDown", "GamepadButton13", "GamepadAxis1+"])),
+            (Action::HardDrop, keys(&["KeyW", "ArrowUp", "GamepadButton12"])),
+            (Action::RotateLeft, keys(&["KeyO", "KeyZ", "GamepadButton1"])),
+            (Action::RotateRight, keys(&["KeyP", "KeyX", "GamepadButton0"])),
+            (Action::Rotate180, keys(&["KeyQ", "GamepadButton3"])),
+            (Action::Hold, keys(&["KeyC", "ShiftLeft", "GamepadButton4", "GamepadButton5"])),
+        ]);
+        Settings {
+            das: 167.0,
+            arr: 33.0,
+            sdf: 20.0,
+            ghost: true,
+            grid: true,
+            animation_speed: 1.0,
+            clear_duration: 0.3,
+            fall_duration: 0.1,
+            clear_effects: BTreeMap::from([
+                (ClearKind::Lines, ClearEffect::Fade),
+                (ClearKind::Tetris, ClearEffect::Flash),
+                (ClearKind::TSpin, ClearEffect::Shatter),
+            ]),
+            countdown: 0.0,
+            are: 0.0,
+            line_are: 0.0,
+            particles: true,
+            master_volume: 0.8,
+            sfx_volume: 1.0,
+            music_volume: 0.5,
+            theme: THEMES[0].to_string(),
+            themes: Vec::new(),
+            patterns: false,
+            keybindings,
+            deadzone: 0.5,
+            pause_keys: keys(&["Space", "Escape", "GamepadButton9"]),
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl Settings {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Settings {
+        Settings::default()
+    }
+
+    /// Settings saved in localStorage, or the defaults.
+    pub fn load() -> Settings {
+        Settings::load_from(&LocalStorage::new())
+    }
+
+    pub fn save(&self) {
+        self.save_to(&mut LocalStorage::new());
+    }
+
+    pub fn get_das(&self) -> f64 {
+        self.das
+    }
+
+    pub fn set_das(&mut self, das: f64) {
+        self.das = clamp(das, DAS_RANGE, self.das);
+    }
+
+    pub fn get_arr(&self) -> f64 {
+        self.arr
+    }
+
+    pub fn set_arr(&mut self, arr: f64) {
+        self.arr = clamp(arr, ARR_RANGE, self.arr);
+    }
+
+    pub fn get_sdf(&self) -> f64 {
+        self.sdf
+    }
+
+    pub fn set_sdf(&mut self, sdf: f64) {
+        self.sdf = clamp(sdf, SDF_RANGE, self.sdf);
+    }
+
+    pub fn get_ghost(&self) -> bool {
+        self.ghost
+    }
+
+    pub fn set_ghost(&mut self, ghost: bool) {
+        self.ghost = ghost;
+    }
+
+    pub fn get_grid(&self) -> bool {
+        self.grid
+    }
+
+    pub fn set_grid(&mut self, grid: bool) {
+        self.grid = grid;
+    }
+
+    pub fn get_animation_speed(&self) -> f64 {
+        self.animation_speed
+    }
+
+    pub fn set_animation_speed(&mut self, speed: f64) {
+        self.animation_speed = clamp(speed, ANIMATION_SPEED_RANGE, self.animation_speed);
+    }
+
+    pub fn get_clear_duration(&self) -> f64 {
+        self.clear_duration
+    }
+
+    /// Seconds a line clear plays before the rows collapse. 0 turns the
+    /// effect off.
+    pub fn set_clear_duration(&mut self, seconds: f64) {
+        self.clear_duration = clamp(seconds, CLEAR_DURATION_RANGE, self.clear_duration);
+    }
+
+    pub fn get_fall_duration(&self) -> f64 {
+        self.fall_duration
+    }
+
+    /// Seconds the rows above a clear take to fall into place. 0 snaps them.
+    pub fn set_fall_duration(&mut self, seconds: f64) {
+        self.fall_duration = clamp(seconds, FALL_DURATION_RANGE, self.fall_duration);
+    }
+
+    pub fn get_clear_effect(&self, kind: ClearKind) -> ClearEffect {
+        self.clear_effect(kind)
+    }
+
+    pub fn set_clear_effect(&mut self, kind: ClearKind, effect: ClearEffect) {
+        self.clear_effects.insert(kind, effect);
+    }
+
+    pub fn get_countdown(&self) -> f64 {
+        self.countdown
+    }
+
+    /// Seconds of READY / GO before the first piece and after unpausing.
+    pub fn set_countdown(&mut self, seconds: f64) {
+        self.countdown = clamp(seconds, COUNTDOWN_RANGE, self.countdown);
+    }
+
+    pub fn get_are(&self) -> f64 {
+        self.are
+    }
+
+    /// Entry delay: seconds after a lock before the next piece spawns.
+    /// Rotations and holds pressed meanwhile apply as it spawns.
+    pub fn set_are(&mut self, seconds: f64) {
+        self.are = clamp(seconds, ARE_RANGE, self.are);
+    }
+
+    pub fn get_line_are(&self) -> f64 {
+        self.line_are
+    }
+
+    /// Entry delay after a line clear, counted once the clear has played.
+    pub fn set_line_are(&mut self, seconds: f64) {
+        self.line_are = clamp(seconds, ARE_RANGE, self.line_are);
+    }
+
+    pub fn get_particles(&self) -> bool {
+        self.particles
+    }
+
+    pub fn set_particles(&mut self, particles: bool) {
+        self.particles = particles;
+    }
+
+    pub fn get_master_volume(&self) -> f64 {
+        self.master_volume
+    }
+
+    pub fn set_master_volume(&mut self, volume: f64) {
+        self.master_volume = clamp(volume, VOLUME_RANGE, self.master_volume);
+    }
+
+    pub fn get_sfx_volume(&self) -> f64 {
+        self.sfx_volume
+    }
+
+    /// Sound effects, relative to the master volume.
+    pub fn set_sfx_volume(&mut self, volume: f64) {
+        self.sfx_volume = clamp(volume, VOLUME_RANGE, self.sfx_volume);
+    }
+
+    pub fn get_music_volume(&self) -> f64 {
+        self.music_volume
+    }
+
+    /// Background music, relative to the master volume.
+    pub fn set_music_volume(&mut self, volume: f64) {
+        self.music_volume = clamp(volume, VOLUME_RANGE, self.music_volume);
+    }
+
+    pub fn get_deadzone(&self) -> f64 {
+        self.deadzone
+    }
+
+    pub fn set_deadzone(&mut self, deadzone: f64) {
+        self.deadzone = clamp(deadzone, DEADZONE_RANGE, self.deadzone);
+    }
+
+    pub fn get_patterns(&self) -> bool {
+        self.patterns
+    }
+
+    /// Marks every block with its piece kind, for players who can't tell
+    /// the colors apart.
+    pub fn set_patterns(&mut self, patterns: bool) {
+        self.patterns = patterns;
+    }
+
+    pub fn get_theme(&self) -> String {
+        self.theme.clone()
+    }
+
+    /// Unknown theme names are ignored.
+    pub fn set_theme(&mut self, theme: &str) {
+        if self.find_theme(theme).is_some() {
+            self.theme = theme.to_string();
+        }
+    }
+
+    /// Built-in themes first, then custom ones.
+    pub fn get_theme_names(&self) -> Vec<String> {
+        let builtin = THEMES.iter().map(|name| name.to_string());
+        builtin.chain(self.themes.iter().map(|theme| theme.name.clone())).collect()
+    }
+
+    /// Adds a custom theme, replacing any custom theme of the same name.
+    /// Built-in themes can't be replaced.
+    pub fn add_theme(&mut self, theme: &Theme) {
+        if THEMES.contains(&theme.name.as_str()) {
+            return;
+        }
+        let mut theme = theme.clone();
+        theme.validate();
+        self.themes.retain(|custom| custom.name != theme.name);
+        self.themes.push(theme);
+    }
+
+    pub fn get_keys(&self, action: Action) -> Vec<String> {
+        self.keybindings.get(&action).cloned().unwrap_or_default()
+    }
+
+    /// Binds `keys` to `action`, taking them away from any other action.
+    pub fn set_keys(&mut self, action: Action, keys: Vec<String>) {
+        self.unbind(&keys);
+        self.keybindings.insert(action, dedup(keys));
+    }
+
+    /// Adds one more key for `action`.
+    pub fn add_key(&mut self, action: Action, key: &str) {
+        let mut keys = self.get_keys(action);
+        keys.push(key.to_string());
+        self.set_keys(action, keys);
+    }
+
+    pub fn get_pause_keys(&self) -> Vec<String> {
+        self.pause_keys.clone()
+    }
+
+    pub fn set_pause_keys(&mut self, keys: Vec<String>) {
+        self.unbind(&keys);
+        self.pause_keys = dedup(keys);
+    }
+
+    pub fn is_pause_key(&self, code: &str) -> bool {
+        self.pause_keys.iter().any(|key| key == code)
+    }
+
+    /// Action bound to a `KeyboardEvent.code`, if any.
+    pub fn action_for_key(&self, code: &str) -> Option<Action> {
+        self.keybindings
+            .iter()
+            .find(|(_, keys)| keys.iter().any(|key| key == code))
+            .map(|(&action, _)| action)
+    }
+
+    /// The keyboard keys bound to `action`, for a controls list, e.g.
+    /// "A, ←". Gamepad buttons are left out.
+    pub fn get_key_labels(&self, action: Action) -> String {
+        key_labels(&self.get_keys(action))
+    }
+
+    pub fn get_pause_key_labels(&self) -> String {
+        key_labels(&self.pause_keys)
+    }
+}
+
+impl Settings {
+    fn find_theme(&self, name: &str) -> Option<Theme> {
+        Theme::builtin(name).or_else(|| self.themes.iter().find(|theme| theme.name == name).cloned())
+    }
+
+    pub(crate) fn clear_effect(&self, kind: ClearKind) -> ClearEffect {
+        self.clear_effects.get(&kind).copied().unwrap_or(ClearEffect::Fade)
+    }
+
+    /// The selected theme's colors and styles.
+    pub(crate) fn resolved_theme(&self) -> Theme {
+        Theme {
+            patterns: self.patterns,
+            ..self.find_theme(&self.theme).unwrap_or_default()
+        }
+    }
+
+    pub fn load_from(storage: &impl Storage) -> Settings {
+        let text = match storage.get(STORAGE_KEY) {
+            Some(text) => text,
+            None => return Settings::default(),
+        };
+        match serde_json::from_str::<Settings>(&text) {
+            Ok(mut settings) => {
+                settings.validate();
+                settings
+            }
+            Err(err) => {
+                log(&format!("Ignoring stored settings: {}", err));
+                Settings::default()
+            }
+        }
+    }
+
+    pub fn save_to(&self, storage: &mut impl Storage) {
+        let text = serde_json::to_string(self).expect("settings always serialize");
+        if let Err(err) = storage.set(STORAGE_KEY, &text) {
+            log(&err);
+        }
+    }
+
+    /// Clamps out-of-range values, drops keys bound to more
+    /// than one action and fills in actions and clear effects missing
+    /// from older saves.
+    pub fn validate(&mut self) {
+        let defaults = Settings::default();
+        self.das = clamp(self.das, DAS_RANGE, defaults.das);
+        self.arr = clamp(self.arr, ARR_RANGE, defaults.arr);
+        self.sdf = clamp(self.sdf, SDF_RANGE, defaults.sdf);
+        self.animation_speed = clamp(self.animation_speed, ANIMATION_SPEED_RANGE, defaults.animation_speed);
+        self.clear_duration = clamp(self.clear_duration, CLEAR_DURATION_RANGE, defaults.clear_duration);
+        self.fall_duration = clamp(self.fall_duration, FALL_DURATION_RANGE, defaults.fall_duration);
+        self.countdown = clamp(self.countdown, COUNTDOWN_RANGE, defaults.countdown);
+        self.are = clamp(self.are, ARE_RANGE, defaults.are);
+        self.line_are = clamp(self.line_are, ARE_RANGE, defaults.line_are);
+        for kind in ClearKind::ALL {
+            self.clear_effects.entry(kind).or_insert(defaults.clear_effects[&kind]);
+        }
+        self.master_volume = clamp(self.master_volume, VOLUME_RANGE, defaults.master_volume);
+        self.sfx_volume = clamp(self.sfx_volume, VOLUME_RANGE, defaults.sfx_volume);
+        self.music_volume = clamp(self.music_volume, VOLUME_RANGE, defaults.music_volume);
+        self.deadzone = clamp(self.deadzone, DEADZONE_RANGE, defaults.deadzone);
+        self.themes.retain(|theme| !THEMES.contains(&theme.name.as_str()));
+        for theme in &mut self.themes {
+            theme.validate();
+        }
+        if self.find_theme(&self.theme).is_none() {
+            self.theme = defaults.theme;
+        }
+
+        self.pause_keys = dedup(std::mem::take(&mut self.pause_keys));
+        let mut seen = self.pause_keys.clone();
+        for keys in self.keybindings.values_mut() {
+            keys.retain(|key| {
+                let duplicate = seen.contains(key);
+                seen.push(key.clone());
+                !duplicate
+            });
+        }
+        for action in Action::ALL {
+            self.keybindings.entry(action).or_insert_with(|| {
+                defaults.keybindings[&action].iter().filter(|key| !seen.contains(key)).cloned().collect()
+            });
+        }
+    }
+
+    // Takes `keys` away from every action and from pausing.
+    fn unbind(&mut self, keys: &[String]) {
+        for bound in self.keybindings.values_mut() {
+            bound.retain(|key| !keys.contains(key));
+        }
+        self.pause_keys.retain(|key| !keys.contains(key));
+    }
+}
+
+fn key_labels(keys: &[String]) -> String {
+    keys.iter().filter_map(|code| key_label(code)).collect::<Vec<_>>().join(", ")
+}
+
+// A `KeyboardEvent.code` as printed on the key; `None` for gamepad inputs.
+fn key_label(code: &str) -> Option<String> {
+    if code.starts_with("Gamepad") {
+        return None;
+    }
+    let label = match code {
+        "ArrowLeft" => "←",
+        "ArrowRight" => "→",
+        "ArrowUp" => "↑",
+        "ArrowDown" => "↓",
+        "ShiftLeft" => "Left Shift",
+        "ShiftRight" => "Right Shift",
+        "ControlLeft" => "Left Ctrl",
+        "ControlRight" => "Right Ctrl",
+        _ => code.strip_prefix("Key").or_else(|| code.strip_prefix("Digit")).unwrap_or(code),
+    };
+    Some(label.to_string())
+}
+
+fn dedup(keys: Vec<String>) -> Vec<String> {
+    let mut unique = Vec::new();
+    for key in keys {
+        if !unique.contains(&key) {
+            unique.push(key);
+        }
+    }
+    unique
+}
+
+// Out-of-range values are clamped; NaN falls back to `fallback`.
+fn clamp(value: f64, (min, max): (f64, f64), fallback: f64) -> f64 {
+    if value.is_nan() {
+        fallback
+    } else {
+        value.clamp(min, max)
+    }
+}
+
+#[wasm_bindgen]
+impl Tetris {
+    pub fn get_settings(&self) -> Settings {
+        self.settings.clone()
+    }
+
+    /// Takes effect immediately, including in a game in progress. Handling,
+    /// the countdown, entry delays and clear timing are simulated, so versus
+    /// peers whose values differ are refused when they join.
+    pub fn set_settings(&mut self, mut settings: Settings) {
+        settings.validate();
+        self.theme = settings.resolved_theme();
+        if !settings.particles {
+            self.particles.clear();
+        }
+        self.settings = settings;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    #[test]
+    fn test_round_trip() {
+        let mut settings = Settings::default();
+        settings.set_das(100.0);
+        settings.set_ghost(false);
+        settings.set_keys(Action::Hold, vec!["KeyA".to_string()]);
+        let mut storage = MemoryStorage::new();
+        settings.save_to(&mut storage);
+
+        let loaded = Settings::load_from(&storage);
+        assert_eq!(loaded, settings);
+        assert_eq!(loaded.action_for_key("KeyA"), Some(Action::Hold));
+        assert!(!loaded.get_keys(Action::MoveLeft).contains(&"KeyA".to_string()));
+    }
+
+    #[test]
+    fn test_key_labels() {
+        let mut settings = Settings::default();
+        assert_eq!(settings.get_key_labels(Action::MoveLeft), "A, ←");
+        assert_eq!(settings.get_key_labels(Action::Hold), "C, Left Shift");
+        assert_eq!(settings.get_pause_key_labels(), "Space, Escape");
+        settings.set_keys(Action::Hold, vec!["Digit1".to_string(), "GamepadButton4".to_string()]);
+        assert_eq!(settings.get_key_labels(Action::Hold), "1");
+    }
+
+    #[test]
+    fn test_validates_on_load() {
+        let mut storage = MemoryStorage::new();
+        let stored = r#"{"das": -5, "sdf": 1000, "theme": "neon",
+            "keybindings": {"MoveLeft": ["KeyQ"], "MoveRight": ["KeyQ", "KeyE"]}}"#;
+        storage.set(STORAGE_KEY, stored).unwrap();
+        let settings = Settings::load_from(&storage);
+        assert_eq!(settings.get_das(), 0.0);
+        assert_eq!(settings.get_sdf(), 100.0);
+        assert_eq!(settings.get_theme(), "classic");
+        assert_eq!(settings.get_keys(Action::MoveRight), vec!["KeyE".to_string()]);
+        assert_eq!(settings.action_for_key("KeyQ"), Some(Action::MoveLeft));
+        assert_eq!(settings.action_for_key("KeyC"), Some(Action::Hold));
+
+        storage.set(STORAGE_KEY, "not json").unwrap();
+        assert_eq!(Settings::load_from(&storage), Settings::default());
+    }
+}
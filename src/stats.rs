@@ -0,0 +1,189 @@
+//! Per-game statistics beyond the line-clear counters.
+
+use wasm_bindgen::prelude::*;
+
+use crate::Tetris;
+
+const PIECE_KINDS: usize = 7;
+
+#[wasm_bindgen]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stats {
+    pub(crate) pieces_placed: u32,
+    pub(crate) keys_pressed: u32,
+    pub(crate) attack: u32,
+    pub(crate) piece_counts: [u32; PIECE_KINDS],
+    pub(crate) max_combo: u32,
+    pub(crate) max_b2b: u32,
+    pub(crate) holds: u32,
+    pub(crate) hints_used: u32,
+    pub(crate) finesse_faults: u32,
+    pub(crate) elapsed: f64, // Seconds of unpaused play
+}
+
+#[wasm_bindgen]
+impl Stats {
+    pub fn get_pieces_placed(&self) -> u32 {
+        self.pieces_placed
+    }
+
+    pub fn get_keys_pressed(&self) -> u32 {
+        self.keys_pressed
+    }
+
+    /// Garbage lines generated, before any cancelling.
+    pub fn get_attack(&self) -> u32 {
+        self.attack
+    }
+
+    /// Pieces of `kind` placed, in the order I, O, T, L, J, S, Z.
+    pub fn get_piece_count(&self, kind: usize) -> u32 {
+        self.piece_counts.get(kind).copied().unwrap_or(0)
+    }
+
+    pub fn get_max_combo(&self) -> u32 {
+        self.max_combo
+    }
+
+    pub fn get_max_b2b(&self) -> u32 {
+        self.max_b2b
+    }
+
+    pub fn get_holds(&self) -> u32 {
+        self.holds
+    }
+
+    pub fn get_hints_used(&self) -> u32 {
+        self.hints_used
+    }
+
+    pub fn get_finesse_faults(&self) -> u32 {
+        self.finesse_faults
+    }
+
+    pub fn get_elapsed(&self) -> f64 {
+        self.elapsed
+    }
+
+    /// Pieces per second.
+    pub fn get_pps(&self) -> f64 {
+        if self.elapsed > 0.0 {
+            self.pieces_placed as f64 / self.elapsed
+        } else {
+            0.0
+        }
+    }
+
+    /// Keys per piece.
+    pub fn get_kpp(&self) -> f64 {
+        if self.pieces_placed > 0 {
+            self.keys_pressed as f64 / self.pieces_placed as f64
+        } else {
+            0.0
+        }
+    }
+
+    /// Attack per minute.
+    pub fn get_apm(&self) -> f64 {
+        if self.elapsed > 0.0 {
+            self.attack as f64 * 60.0 / self.elapsed
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Summary handed out when a game ends.
+#[wasm_bindgen]
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameResult {
+    pub score: u32,
+    pub lines: u32,
+    pub game_over: bool,
+    stats: Stats,
+}
+
+#[wasm_bindgen]
+impl GameResult {
+    pub fn get_stats(&self) -> Stats {
+        self.stats.clone()
+    }
+}
+
+#[wasm_bindgen]
+impl Tetris {
+    pub fn get_stats(&self) -> Stats {
+        self.stats.clone()
+    }
+
+    pub fn get_result(&self) -> GameResult {
+        GameResult {
+            score: self.score,
+            lines: self.cleared_lanes,
+            game_over: self.game_over,
+            stats: self.stats.clone(),
+        }
+    }
+}
+
+impl Tetris {
+    pub(crate) fn update_stats(&mut self, delta_time: f64) {
+        if self.in_play() && !self.paused && !self.game_over {
+            self.stats.elapsed += delta_time;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{default_shapes, Action};
+
+    #[test]
+    fn test_rates() {
+        let stats = Stats {
+            pieces_placed: 30,
+            keys_pressed: 90,
+            attack: 10,
+            elapsed: 20.0,
+            ..Stats::default()
+        };
+        assert_eq!(stats.get_pps(), 1.5);
+        assert_eq!(stats.get_kpp(), 3.0);
+        assert_eq!(stats.get_apm(), 30.0);
+        assert_eq!(Stats::default().get_pps(), 0.0);
+    }
+
+    #[test]
+    fn test_tracks_game() {
+        let mut game = Tetris::with_seed(0);
+        game.start();
+        game.update(2.0);
+        game.current_piece = Some(Tetris::create_piece(&default_shapes(), 2));
+        game.apply_action(Action::MoveLeft);
+        game.apply_action(Action::HardDrop);
+        game.apply_action(Action::Hold);
+        game.pause();
+        game.update(5.0);
+
+        let stats = game.get_result().get_stats();
+        assert_eq!(stats.get_pieces_placed(), 1);
+        assert_eq!(stats.get_piece_count(2), 1);
+        assert_eq!(stats.get_keys_pressed(), 3);
+        assert_eq!(stats.get_holds(), 1);
+        assert_eq!(stats.get_elapsed(), 2.0);
+    }
+
+    #[test]
+    fn test_counts_entry_delay() {
+        let mut game = Tetris::with_seed(0);
+        let mut settings = game.get_settings();
+        settings.are = 0.5;
+        game.set_settings(settings);
+        game.start();
+        game.drop();
+        assert!(game.current_piece.is_none());
+        game.update(0.25);
+        assert_eq!(game.get_stats().get_elapsed(), 0.25);
+    }
+}
@@ -0,0 +1,71 @@
+//! Key-value persistence. The browser build stores into `window.localStorage`;
+//! native tests and tools use the in-memory store.
+
+use std::collections::HashMap;
+
+pub trait Storage {
+    fn get(&self, key: &str) -> Option<String>;
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String>;
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStorage {
+    values: HashMap<String, String>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        MemoryStorage::default()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn get(&self, key: &str) -> Option<String> {
+        self.values.get(key).cloned()
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        self.values.insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+}
+
+/// `window.localStorage`. Reads and writes fail quietly when storage is
+/// unavailable (private browsing, disabled cookies, ...).
+#[derive(Clone, Debug, Default)]
+pub struct LocalStorage {
+    storage: Option<web_sys::Storage>,
+}
+
+impl LocalStorage {
+    pub fn new() -> LocalStorage {
+        let storage = web_sys::window().and_then(|win| win.local_storage().ok().flatten());
+        if storage.is_none() {
+            web_sys::console::log_1(&"localStorage is unavailable; nothing will be saved".into());
+        }
+        LocalStorage { storage }
+    }
+}
+
+impl Storage for LocalStorage {
+    fn get(&self, key: &str) -> Option<String> {
+        self.storage.as_ref()?.get_item(key).ok().flatten()
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match self.storage {
+            Some(ref storage) => storage
+                .set_item(key, value)
+                .map_err(|_| format!("Failed to write '{}' to localStorage", key)),
+            None => Err("localStorage is unavailable".to_string()),
+        }
+    }
+}
+
+/// Console logging that also works in native tests.
+pub(crate) fn log(message: &str) {
+    #[cfg(target_arch = "wasm32")]
+    web_sys::console::log_1(&message.into());
+    #[cfg(not(target_arch = "wasm32"))]
+    eprintln!("{}", message);
+}
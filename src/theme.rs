@@ -0,0 +1,327 @@
+//! Color palettes and block skins. A few themes are built in; custom ones
+//! are read from JSON, saved with the settings and picked by name.
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+use crate::storage::log;
+use crate::Tetris;
+
+/// Cell values 1 to 7 are the pieces I, O, T, L, J, S and Z, and 8 is
+/// garbage; 0 is empty.
+pub(crate) const PALETTE_SIZE: usize = 8;
+
+/// Themes every game knows by name. The last four are accessibility
+/// presets, best combined with `Settings::set_patterns`.
+pub const THEMES: &[&str] = &[
+    "classic",
+    "midnight",
+    "pastel",
+    "gameboy",
+    "high-contrast",
+    "deuteranopia",
+    "protanopia",
+    "tritanopia",
+];
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BlockStyle {
+    Flat,
+    /// Fades to black from the top left, with a lighter center.
+    Gradient,
+    Beveled,
+    Outlined,
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GhostStyle {
+    Filled,
+    Outline,
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Theme {
+    pub(crate) name: String,
+    pub(crate) pieces: Vec<String>,     // CSS colors by cell value, from 1
+    pub(crate) highlights: Vec<String>, // Lighter shades of `pieces`
+    pub(crate) background: String,
+    pub(crate) grid: String,
+    pub(crate) border: String,
+    pub(crate) ghost: GhostStyle,
+    pub(crate) ghost_alpha: f64,
+    pub(crate) block: BlockStyle,
+    pub(crate) glyph: String, // Color of the per-kind patterns
+    #[serde(skip)]
+    pub(crate) patterns: bool, // From the settings, not part of the theme
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        Theme::builtin(THEMES[0]).expect("the first theme is built in")
+    }
+}
+
+fn colors(colors: [&str; PALETTE_SIZE]) -> Vec<String> {
+    colors.iter().map(|color| color.to_string()).collect()
+}
+
+// Highlights for `#rrggbb` colors: 40% of the way to white.
+fn lighter(colors: &[String]) -> Vec<String> {
+    colors
+        .iter()
+        .map(|color| {
+            let channel = |i: usize| u8::from_str_radix(&color[i..i + 2], 16).unwrap_or(0) as f64;
+            let lift = |value: f64| (value + (255.0 - value) * 0.4).round() as u8;
+            format!("#{:02x}{:02x}{:02x}", lift(channel(1)), lift(channel(3)), lift(channel(5)))
+        })
+        .collect()
+}
+
+// The look shared by the colorblind presets: dark board, beveled blocks.
+fn accessible(pieces: [&str; PALETTE_SIZE]) -> Theme {
+    let pieces = colors(pieces);
+    Theme {
+        name: String::new(),
+        highlights: lighter(&pieces),
+        pieces,
+        background: "#101010".to_string(),
+        grid: "#303030".to_string(),
+        border: "#d3d3d3".to_string(),
+        ghost: GhostStyle::Outline,
+        ghost_alpha: 0.8,
+        block: BlockStyle::Beveled,
+        glyph: "rgba(0, 0, 0, 0.6)".to_string(),
+        patterns: false,
+    }
+}
+
+impl Theme {
+    pub(crate) fn builtin(name: &str) -> Option<Theme> {
+        let theme = match name {
+            "classic" => Theme {
+                name: String::new(),
+                pieces: colors(["#ff5555", "#55ff55", "#5555ff", "#ffff55", "#ff55ff", "#55ffff", "#ffaa55", "#888888"]),
+                highlights: colors(["#ff9999", "#99ff99", "#9999ff", "#ffff99", "#ff99ff", "#99ffff", "#ffcc99", "#aaaaaa"]),
+                background: "#1C2526".to_string(),
+                grid: "#2A3435".to_string(),
+                border: "#d3d3d3".to_string(),
+                ghost: GhostStyle::Filled,
+                ghost_alpha: 0.3,
+                block: BlockStyle::Gradient,
+                glyph: "rgba(0, 0, 0, 0.5)".to_string(),
+                patterns: false,
+            },
+            "midnight" => Theme {
+                name: String::new(),
+                pieces: colors(["#e0455e", "#3ccf91", "#4a7dff", "#f2c84b", "#b35cff", "#2fc4e0", "#ff8a3d", "#59627a"]),
+                highlights: colors(["#ff8fa0", "#8af0c2", "#9cb8ff", "#ffe599", "#d9a8ff", "#8de8f7", "#ffbf8f", "#8a93ab"]),
+                background: "#0b1020".to_string(),
+                grid: "#1b2540".to_string(),
+                border: "#3a4a70".to_string(),
+                ghost: GhostStyle::Outline,
+                ghost_alpha: 0.7,
+                block: BlockStyle::Beveled,
+                glyph: "rgba(0, 0, 0, 0.4)".to_string(),
+                patterns: false,
+            },
+            "pastel" => Theme {
+                name: String::new(),
+                pieces: colors(["#f4a6a6", "#a8dcb0", "#a6b8f4", "#f4e3a6", "#d9a6f4", "#a6e6f4", "#f4c6a6", "#c4bfb6"]),
+                highlights: colors(["#f9cccc", "#cdebd2", "#ccd7f9", "#f9efcc", "#ebccf9", "#ccf0f9", "#f9dfcc", "#dcd8d1"]),
+                background: "#f6f2ea".to_string(),
+                grid: "#e4ddd0".to_string(),
+                border: "#bfb6a3".to_string(),
+                ghost: GhostStyle::Filled,
+                ghost_alpha: 0.35,
+                block: BlockStyle::Flat,
+                glyph: "rgba(0, 0, 0, 0.4)".to_string(),
+                patterns: false,
+            },
+            "gameboy" => Theme {
+                name: String::new(),
+                pieces: colors(["#0f380f", "#306230", "#0f380f", "#306230", "#0f380f", "#306230", "#0f380f", "#306230"]),
+                highlights: colors(["#8bac0f", "#9bbc0f", "#8bac0f", "#9bbc0f", "#8bac0f", "#9bbc0f", "#8bac0f", "#8bac0f"]),
+                background: "#9bbc0f".to_string(),
+                grid: "#8bac0f".to_string(),
+                border: "#306230".to_string(),
+                ghost: GhostStyle::Outline,
+                ghost_alpha: 0.6,
+                block: BlockStyle::Outlined,
+                glyph: "#9bbc0f".to_string(),
+                patterns: false,
+            },
+            "high-contrast" => Theme {
+                pieces: colors(["#00ffff", "#ffff00", "#ff00ff", "#ff8000", "#3070ff", "#00ff00", "#ff0000", "#ffffff"]),
+                highlights: colors(["#00ffff", "#ffff00", "#ff00ff", "#ff8000", "#3070ff", "#00ff00", "#ff0000", "#ffffff"]),
+                background: "#000000".to_string(),
+                grid: "#404040".to_string(),
+                border: "#ffffff".to_string(),
+                ghost_alpha: 1.0,
+                block: BlockStyle::Flat,
+                glyph: "#000000".to_string(),
+                ..accessible(["#000000"; PALETTE_SIZE])
+            },
+            // Okabe and Ito's palette, which keeps hues apart without red-green contrast.
+            "deuteranopia" => accessible(["#56b4e9", "#f0e442", "#cc79a7", "#e69f00", "#0072b2", "#009e73", "#d55e00", "#999999"]),
+            // Reds look dark to protanopes, so no piece relies on a pure red.
+            "protanopia" => accessible(["#648fff", "#ffb000", "#785ef0", "#fe6100", "#1e3a8a", "#00c2a0", "#dc267f", "#999999"]),
+            // Blues and yellows are confused, so kinds differ in red and lightness.
+            "tritanopia" => accessible(["#00a0a8", "#f0f0f0", "#e8384f", "#ff9db0", "#005f66", "#8c1c13", "#b0a0d8", "#777777"]),
+            _ => return None,
+        };
+        Some(Theme {
+            name: name.to_string(),
+            ..theme
+        })
+    }
+
+    /// Piece color for a cell value from 1 to `PALETTE_SIZE`.
+    pub(crate) fn piece(&self, color: u8) -> &str {
+        &self.pieces[(color as usize).clamp(1, PALETTE_SIZE) - 1]
+    }
+
+    pub(crate) fn highlight(&self, color: u8) -> &str {
+        &self.highlights[(color as usize).clamp(1, PALETTE_SIZE) - 1]
+    }
+
+    /// Fills in what a hand-written theme left out or got wrong.
+    pub(crate) fn validate(&mut self) {
+        let defaults = Theme::default();
+        if self.name.trim().is_empty() {
+            self.name = "custom".to_string();
+        }
+        for (colors, fallback) in [(&mut self.pieces, &defaults.pieces), (&mut self.highlights, &defaults.highlights)] {
+            colors.truncate(PALETTE_SIZE);
+            let missing = colors.len();
+            colors.extend(fallback[missing..].iter().cloned());
+        }
+        if self.ghost_alpha.is_nan() {
+            self.ghost_alpha = defaults.ghost_alpha;
+        }
+        self.ghost_alpha = self.ghost_alpha.clamp(0.0, 1.0);
+    }
+}
+
+#[wasm_bindgen]
+impl Theme {
+    /// A built-in theme, or classic for unknown names.
+    pub fn named(name: &str) -> Theme {
+        Theme::builtin(name).unwrap_or_default()
+    }
+
+    /// Reads a theme such as `{"name": "ice", "pieces": ["#9cf", ...],
+    /// "block": "beveled"}`. Missing fields come from classic. Returns
+    /// undefined if the text is not a theme.
+    pub fn from_json(text: &str) -> Option<Theme> {
+        match serde_json::from_str::<Theme>(text) {
+            Ok(mut theme) => {
+                theme.validate();
+                Some(theme)
+            }
+            Err(err) => {
+                log(&format!("Ignoring theme: {}", err));
+                None
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("themes always serialize")
+    }
+
+    pub fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn get_block_style(&self) -> BlockStyle {
+        self.block
+    }
+
+    pub fn set_block_style(&mut self, style: BlockStyle) {
+        self.block = style;
+    }
+
+    pub fn get_ghost_style(&self) -> GhostStyle {
+        self.ghost
+    }
+
+    pub fn set_ghost_style(&mut self, style: GhostStyle) {
+        self.ghost = style;
+    }
+}
+
+#[wasm_bindgen]
+impl Tetris {
+    pub fn get_theme(&self) -> Theme {
+        self.theme.clone()
+    }
+
+    /// Switches to `theme`, remembering it in the settings if it is custom.
+    pub fn set_theme(&mut self, theme: &Theme) {
+        let mut settings = self.settings.clone();
+        settings.add_theme(theme);
+        settings.set_theme(&theme.name);
+        self.set_settings(settings);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{default_shapes, Settings};
+
+    #[test]
+    fn test_custom_theme() {
+        let theme = Theme::from_json(r##"{"name": "ice", "pieces": ["#9cf", "#fff"], "block": "outlined", "ghost_alpha": 3}"##).unwrap();
+        assert_eq!(theme.get_name(), "ice");
+        assert_eq!(theme.piece(2), "#fff");
+        assert_eq!(theme.piece(3), "#5555ff"); // From classic
+        assert_eq!(theme.piece(8), "#888888");
+        assert_eq!(theme.block, BlockStyle::Outlined);
+        assert_eq!(theme.ghost_alpha, 1.0);
+        assert_eq!(Theme::from_json(&theme.to_json()), Some(theme.clone()));
+        assert_eq!(Theme::from_json("[1, 2]"), None);
+
+        let mut game = Tetris::with_seed(0);
+        game.set_theme(&theme);
+        assert_eq!(game.get_theme(), theme);
+        assert_eq!(game.get_settings().get_theme(), "ice");
+        game.set_theme(&Theme::named("midnight"));
+        assert_eq!(game.get_theme().block, BlockStyle::Beveled);
+        assert!(game.get_settings().get_theme_names().contains(&"ice".to_string()));
+    }
+
+    #[test]
+    fn test_presets_tell_kinds_apart() {
+        for name in THEMES {
+            let theme = Theme::named(name);
+            assert_eq!(theme.get_name(), *name);
+            assert_eq!(theme.pieces.len(), PALETTE_SIZE);
+            assert_eq!(theme.highlights.len(), PALETTE_SIZE);
+            if *name != "gameboy" {
+                let mut kinds = theme.pieces[..7].to_vec();
+                kinds.sort();
+                kinds.dedup();
+                assert_eq!(kinds.len(), 7, "{} repeats a piece color", name);
+            }
+        }
+        assert_eq!(lighter(&["#000000".to_string()]), vec!["#666666".to_string()]);
+
+        // Every kind has its own cell value, so patterns survive locking.
+        let colors: Vec<u8> = (0..7).map(|kind| Tetris::create_piece(&default_shapes(), kind).color).collect();
+        assert_eq!(colors, vec![1, 2, 3, 4, 5, 6, 7]);
+        let mut game = Tetris::with_seed(0);
+        let mut settings = Settings::default();
+        settings.set_patterns(true);
+        settings.set_theme("deuteranopia");
+        game.set_settings(settings);
+        assert!(game.get_theme().patterns);
+        assert_eq!(game.get_theme().get_name(), "deuteranopia");
+    }
+}
@@ -0,0 +1,524 @@
+//! Touch controls for phones. `Gestures` is plain logic over touch point
+//! sequences: horizontal swipes move one column per cell dragged, slow swipes
+//! down soft drop, flicks down hard drop, taps rotate (left or right half of
+//! the board) and a two-finger tap holds. `TouchControls` feeds it from the
+//! canvas's touch events and can draw on-screen buttons.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement, TouchEvent};
+
+use crate::input::Input;
+use crate::render::canvas::BLOCK_SIZE;
+use crate::storage::log;
+use crate::{Action, Tetris};
+
+const BUTTONS: [(Action, &str); 7] = [
+    (Action::MoveLeft, "◀"),
+    (Action::MoveRight, "▶"),
+    (Action::SoftDrop, "▼"),
+    (Action::HardDrop, "⤓"),
+    (Action::RotateLeft, "⟲"),
+    (Action::RotateRight, "⟳"),
+    (Action::Hold, "H"),
+];
+const VELOCITY_WINDOW: f64 = 80.0; // Milliseconds of samples used for flick speed
+
+/// Distances are in board units, 30 to a cell whatever the zoom.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GestureConfig {
+    pub width: f64,         // Board width; taps rotate by half
+    pub height: f64,        // Board height; buttons sit along the bottom
+    pub cell: f64,          // Drag distance per move or soft drop row
+    pub tap_distance: f64,  // Furthest a tap may wander
+    pub tap_time: f64,      // Longest a tap may last, in milliseconds
+    pub flick_speed: f64,   // Downward speed for a hard drop, in units per millisecond
+    pub button_height: f64, // 0 hides the on-screen buttons
+}
+
+impl Default for GestureConfig {
+    fn default() -> GestureConfig {
+        GestureConfig {
+            width: 300.0,
+            height: 600.0,
+            cell: 30.0,
+            tap_distance: 10.0,
+            tap_time: 250.0,
+            flick_speed: 1.5,
+            button_height: 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Axis {
+    Horizontal,
+    Vertical,
+}
+
+#[derive(Clone, Debug)]
+struct Track {
+    id: i32,
+    start: (f64, f64, f64), // x, y, time
+    samples: VecDeque<(f64, f64, f64)>,
+    axis: Option<Axis>,
+    columns: i32, // Moves emitted so far, signed
+    rows: i32,    // Soft drop rows emitted so far
+    button: Option<Action>,
+}
+
+impl Track {
+    fn last(&self) -> (f64, f64, f64) {
+        *self.samples.back().unwrap_or(&self.start)
+    }
+
+    fn is_tap(&self, config: &GestureConfig) -> bool {
+        let (x, y, time) = self.last();
+        let distance = (x - self.start.0).hypot(y - self.start.1);
+        distance <= config.tap_distance && time - self.start.2 <= config.tap_time && self.axis.is_none()
+    }
+
+    // Downward speed over the last few samples.
+    fn fall_speed(&self) -> f64 {
+        let (_, last_y, last_time) = self.last();
+        let first = self
+            .samples
+            .iter()
+            .find(|&&(_, _, time)| last_time - time <= VELOCITY_WINDOW)
+            .copied()
+            .unwrap_or(self.start);
+        let elapsed = last_time - first.2;
+        if elapsed > 0.0 {
+            (last_y - first.1) / elapsed
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Recognizes gestures from raw touch points in canvas pixels.
+#[derive(Clone, Debug, Default)]
+pub struct Gestures {
+    config: GestureConfig,
+    tracks: Vec<Track>,
+    fingers: usize, // Most gesture fingers down at once since all were lifted
+    all_taps: bool, // Every finger lifted so far in this session was a tap
+}
+
+impl Gestures {
+    pub fn new(config: GestureConfig) -> Gestures {
+        Gestures {
+            config,
+            ..Gestures::default()
+        }
+    }
+
+    pub fn config(&self) -> &GestureConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: GestureConfig) {
+        self.config = config;
+    }
+
+    /// On-screen button under `(x, y)`, if buttons are shown.
+    pub fn button_at(&self, x: f64, y: f64) -> Option<Action> {
+        let height = self.config.button_height;
+        if height <= 0.0 || y < self.config.height - height || y > self.config.height {
+            return None;
+        }
+        let width = self.config.width / BUTTONS.len() as f64;
+        let index = (x / width).floor();
+        if index < 0.0 {
+            return None;
+        }
+        BUTTONS.get(index as usize).map(|&(action, _)| action)
+    }
+
+    pub fn start(&mut self, id: i32, x: f64, y: f64, time: f64) -> Vec<Input> {
+        let button = self.button_at(x, y);
+        if button.is_none() {
+            if self.gesture_fingers() == 0 {
+                self.fingers = 0;
+                self.all_taps = true;
+            }
+            self.fingers = self.fingers.max(self.gesture_fingers() + 1);
+        }
+        self.tracks.push(Track {
+            id,
+            start: (x, y, time),
+            samples: VecDeque::new(),
+            axis: None,
+            columns: 0,
+            rows: 0,
+            button,
+        });
+        button.map(Input::Press).into_iter().collect()
+    }
+
+    pub fn moved(&mut self, id: i32, x: f64, y: f64, time: f64) -> Vec<Input> {
+        let config = self.config;
+        let single = self.fingers == 1;
+        let track = match self.tracks.iter_mut().find(|track| track.id == id) {
+            Some(track) => track,
+            None => return Vec::new(),
+        };
+        track.samples.push_back((x, y, time));
+        while track.samples.len() > 2 && time - track.samples[0].2 > VELOCITY_WINDOW {
+            track.samples.pop_front();
+        }
+        if track.button.is_some() || !single {
+            return Vec::new();
+        }
+
+        let (dx, dy) = (x - track.start.0, y - track.start.1);
+        if track.axis.is_none() && dx.hypot(dy) > config.tap_distance {
+            track.axis = Some(if dx.abs() >= dy.abs() { Axis::Horizontal } else { Axis::Vertical });
+        }
+        let mut inputs = Vec::new();
+        match track.axis {
+            Some(Axis::Horizontal) => {
+                // One move per cell dragged, in either direction.
+                let columns = (dx / config.cell).trunc() as i32;
+                while track.columns != columns {
+                    let action = if columns > track.columns { Action::MoveRight } else { Action::MoveLeft };
+                    track.columns += if columns > track.columns { 1 } else { -1 };
+                    tap(&mut inputs, action);
+                }
+            }
+            Some(Axis::Vertical) => {
+                let rows = (dy / config.cell).trunc() as i32;
+                while track.rows < rows {
+                    track.rows += 1;
+                    tap(&mut inputs, Action::SoftDrop);
+                }
+            }
+            None => {}
+        }
+        inputs
+    }
+
+    pub fn end(&mut self, id: i32, x: f64, y: f64, time: f64) -> Vec<Input> {
+        let mut inputs = self.moved(id, x, y, time);
+        let index = match self.tracks.iter().position(|track| track.id == id) {
+            Some(index) => index,
+            None => return inputs,
+        };
+        let track = self.tracks.remove(index);
+        if let Some(action) = track.button {
+            inputs.push(Input::Release(action));
+            return inputs;
+        }
+
+        let is_tap = track.is_tap(&self.config);
+        self.all_taps &= is_tap;
+        if self.fingers == 1 {
+            if is_tap {
+                let action = if track.start.0 < self.config.width / 2.0 {
+                    Action::RotateLeft
+                } else {
+                    Action::RotateRight
+                };
+                tap(&mut inputs, action);
+            } else if track.axis == Some(Axis::Vertical) && track.fall_speed() >= self.config.flick_speed {
+                tap(&mut inputs, Action::HardDrop);
+            }
+        } else if self.fingers == 2 && self.all_taps && self.gesture_fingers() == 0 {
+            tap(&mut inputs, Action::Hold);
+        }
+        inputs
+    }
+
+    /// The touch was taken away (e.g. by a system gesture): let go without
+    /// acting on it.
+    pub fn cancel(&mut self, id: i32) -> Vec<Input> {
+        let index = match self.tracks.iter().position(|track| track.id == id) {
+            Some(index) => index,
+            None => return Vec::new(),
+        };
+        let track = self.tracks.remove(index);
+        self.all_taps = false;
+        track.button.map(Input::Release).into_iter().collect()
+    }
+
+    fn gesture_fingers(&self) -> usize {
+        self.tracks.iter().filter(|track| track.button.is_none()).count()
+    }
+}
+
+// A press immediately followed by its release: one step, no auto-repeat.
+fn tap(inputs: &mut Vec<Input>, action: Action) {
+    inputs.push(Input::Press(action));
+    inputs.push(Input::Release(action));
+}
+
+#[derive(Default)]
+struct TouchState {
+    gestures: Gestures,
+    inputs: VecDeque<Input>,
+    block: Option<f64>, // CSS pixels per cell, once known from the game
+}
+
+type Listener = Closure<dyn FnMut(web_sys::Event)>;
+
+/// Touch listeners on the game canvas.
+#[wasm_bindgen]
+pub struct TouchControls {
+    state: Rc<RefCell<TouchState>>,
+    canvas: Option<HtmlCanvasElement>,
+    listeners: Vec<(&'static str, Listener)>,
+}
+
+#[wasm_bindgen]
+impl TouchControls {
+    #[wasm_bindgen(constructor)]
+    pub fn new(canvas_id: &str) -> TouchControls {
+        let canvas = web_sys::window()
+            .and_then(|win| win.document())
+            .and_then(|doc| doc.get_element_by_id(canvas_id))
+            .and_then(|elem| elem.dyn_into::<HtmlCanvasElement>().ok());
+        let config = match canvas {
+            Some(ref canvas) => GestureConfig {
+                width: canvas.width() as f64,
+                height: canvas.height() as f64,
+                ..GestureConfig::default()
+            },
+            None => GestureConfig::default(),
+        };
+        let mut controls = TouchControls {
+            state: Rc::new(RefCell::new(TouchState {
+                gestures: Gestures::new(config),
+                inputs: VecDeque::new(),
+                block: None,
+            })),
+            canvas,
+            listeners: Vec::new(),
+        };
+        if controls.canvas.is_none() {
+            log(&format!("No canvas '{}' for touch controls", canvas_id));
+            return controls;
+        }
+        for event in ["touchstart", "touchmove", "touchend", "touchcancel"] {
+            let state = controls.state.clone();
+            let canvas = controls.canvas.clone().unwrap();
+            controls.listen(event, move |e| {
+                if let Some(e) = e.dyn_ref::<TouchEvent>() {
+                    e.prevent_default(); // No scrolling or zooming over the board
+                    handle_touches(&mut state.borrow_mut(), &canvas, e);
+                }
+            });
+        }
+        controls
+    }
+
+    /// Shows on-screen buttons in a strip `height` board units tall along
+    /// the bottom of the board. 0 hides them.
+    pub fn set_buttons(&self, height: f64) {
+        let mut state = self.state.borrow_mut();
+        let config = GestureConfig {
+            button_height: height.max(0.0),
+            ..*state.gestures.config()
+        };
+        state.gestures.set_config(config);
+    }
+
+    /// Drag distance in board units per move or soft drop row.
+    pub fn set_cell_size(&self, cell: f64) {
+        let mut state = self.state.borrow_mut();
+        if cell > 0.0 {
+            let config = GestureConfig {
+                cell,
+                ..*state.gestures.config()
+            };
+            state.gestures.set_config(config);
+        }
+    }
+
+    /// Feeds every gesture recognized since the last call to `game`.
+    pub fn apply(&self, game: &mut Tetris) {
+        let inputs: Vec<Input> = {
+            let mut state = self.state.borrow_mut();
+            // Follow the board as it is resized.
+            let config = GestureConfig {
+                width: BLOCK_SIZE * game.width as f64,
+                height: BLOCK_SIZE * game.height as f64,
+                ..*state.gestures.config()
+            };
+            state.gestures.set_config(config);
+            state.block = Some(game.get_block_size());
+            state.inputs.drain(..).collect()
+        };
+        for input in inputs {
+            match input {
+                Input::Press(action) => game.press(action),
+                Input::Release(action) => game.release(action),
+                Input::TogglePause => {}
+            }
+        }
+    }
+
+    /// Draws the on-screen buttons over the canvas. Call after `game.draw()`.
+    #[allow(deprecated)]
+    pub fn draw(&self, game: &Tetris) {
+        let config = *self.state.borrow().gestures.config();
+        if config.button_height <= 0.0 {
+            return;
+        }
+        let ctx = match self
+            .canvas
+            .as_ref()
+            .and_then(|canvas| canvas.get_context("2d").ok().flatten())
+            .and_then(|ctx| ctx.dyn_into::<CanvasRenderingContext2d>().ok())
+        {
+            Some(ctx) => ctx,
+            None => return,
+        };
+        let width = config.width / BUTTONS.len() as f64;
+        let top = config.height - config.button_height;
+        ctx.set_font(&format!("{}px Arial", (config.button_height * 0.5).round()));
+        ctx.set_text_align("center");
+        ctx.set_text_baseline("middle");
+        for (i, &(_, label)) in BUTTONS.iter().enumerate() {
+            let x = i as f64 * width;
+            ctx.set_fill_style(&JsValue::from_str("rgba(255, 255, 255, 0.15)"));
+            ctx.fill_rect(x + 2.0, top + 2.0, width - 4.0, config.button_height - 4.0);
+            ctx.set_fill_style(&JsValue::from_str("rgba(255, 255, 255, 0.8)"));
+            let _ = ctx.fill_text(label, x + width / 2.0, top + config.button_height / 2.0);
+        }
+        // The buttons are translucent, so the board under them is repainted.
+        game.invalidate_area(0.0, top, config.width, config.button_height);
+    }
+}
+
+impl TouchControls {
+    fn listen(&mut self, event: &'static str, handler: impl FnMut(web_sys::Event) + 'static) {
+        let listener = Closure::wrap(Box::new(handler) as Box<dyn FnMut(web_sys::Event)>);
+        if let Some(ref canvas) = self.canvas {
+            if canvas
+                .add_event_listener_with_callback(event, listener.as_ref().unchecked_ref())
+                .is_err()
+            {
+                log(&format!("Failed to listen for {}", event));
+            }
+        }
+        self.listeners.push((event, listener));
+    }
+}
+
+impl Drop for TouchControls {
+    fn drop(&mut self) {
+        if let Some(ref canvas) = self.canvas {
+            for (event, listener) in &self.listeners {
+                let _ = canvas.remove_event_listener_with_callback(event, listener.as_ref().unchecked_ref());
+            }
+        }
+    }
+}
+
+// Converts the changed touches to board units and runs them through the
+// recognizer.
+fn handle_touches(state: &mut TouchState, canvas: &HtmlCanvasElement, event: &TouchEvent) {
+    let rect = canvas.get_bounding_client_rect();
+    let (scale_x, scale_y) = match state.block {
+        Some(block) if block > 0.0 => (BLOCK_SIZE / block, BLOCK_SIZE / block),
+        _ => (
+            if rect.width() > 0.0 { canvas.width() as f64 / rect.width() } else { 1.0 },
+            if rect.height() > 0.0 { canvas.height() as f64 / rect.height() } else { 1.0 },
+        ),
+    };
+    let time = event.time_stamp();
+    let touches = event.changed_touches();
+    for i in 0..touches.length() {
+        let touch = match touches.get(i) {
+            Some(touch) => touch,
+            None => continue,
+        };
+        let id = touch.identifier();
+        let x = (touch.client_x() as f64 - rect.left()) * scale_x;
+        let y = (touch.client_y() as f64 - rect.top()) * scale_y;
+        let inputs = match event.type_().as_str() {
+            "touchstart" => state.gestures.start(id, x, y, time),
+            "touchmove" => state.gestures.moved(id, x, y, time),
+            "touchend" => state.gestures.end(id, x, y, time),
+            _ => state.gestures.cancel(id),
+        };
+        state.inputs.extend(inputs);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Actions pressed, ignoring the matching releases.
+    fn pressed(inputs: Vec<Input>) -> Vec<Action> {
+        inputs
+            .into_iter()
+            .filter_map(|input| match input {
+                Input::Press(action) => Some(action),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn swipe(gestures: &mut Gestures, from: (f64, f64), to: (f64, f64), duration: f64) -> Vec<Action> {
+        let mut actions = pressed(gestures.start(0, from.0, from.1, 0.0));
+        let steps = 10;
+        for step in 1..steps {
+            let t = step as f64 / steps as f64;
+            let (x, y) = (from.0 + (to.0 - from.0) * t, from.1 + (to.1 - from.1) * t);
+            actions.extend(pressed(gestures.moved(0, x, y, duration * t)));
+        }
+        actions.extend(pressed(gestures.end(0, to.0, to.1, duration)));
+        actions
+    }
+
+    #[test]
+    fn test_swipes() {
+        let mut gestures = Gestures::new(GestureConfig::default());
+        assert_eq!(swipe(&mut gestures, (100.0, 300.0), (195.0, 310.0), 400.0), vec![Action::MoveRight; 3]);
+        assert_eq!(swipe(&mut gestures, (200.0, 300.0), (135.0, 300.0), 400.0), vec![Action::MoveLeft; 2]);
+        // A slow drag down soft drops; a quick one ends in a hard drop.
+        assert_eq!(swipe(&mut gestures, (150.0, 100.0), (150.0, 170.0), 500.0), vec![Action::SoftDrop; 2]);
+        assert_eq!(
+            swipe(&mut gestures, (150.0, 100.0), (150.0, 200.0), 50.0),
+            vec![Action::SoftDrop, Action::SoftDrop, Action::SoftDrop, Action::HardDrop]
+        );
+    }
+
+    #[test]
+    fn test_taps() {
+        let mut gestures = Gestures::new(GestureConfig::default());
+        assert_eq!(swipe(&mut gestures, (50.0, 300.0), (52.0, 301.0), 100.0), vec![Action::RotateLeft]);
+        assert_eq!(swipe(&mut gestures, (250.0, 300.0), (250.0, 300.0), 100.0), vec![Action::RotateRight]);
+        // Held too long: not a tap.
+        assert_eq!(swipe(&mut gestures, (250.0, 300.0), (250.0, 300.0), 1000.0), vec![]);
+
+        // Two fingers together: hold, no rotation.
+        gestures.start(1, 50.0, 300.0, 0.0);
+        gestures.start(2, 250.0, 300.0, 20.0);
+        assert_eq!(pressed(gestures.end(1, 50.0, 300.0, 100.0)), vec![]);
+        assert_eq!(pressed(gestures.end(2, 250.0, 300.0, 120.0)), vec![Action::Hold]);
+    }
+
+    #[test]
+    fn test_buttons() {
+        let mut gestures = Gestures::new(GestureConfig {
+            button_height: 60.0,
+            ..GestureConfig::default()
+        });
+        assert_eq!(gestures.button_at(10.0, 590.0), Some(Action::MoveLeft));
+        assert_eq!(gestures.button_at(290.0, 590.0), Some(Action::Hold));
+        assert_eq!(gestures.button_at(10.0, 500.0), None);
+
+        // Buttons are held like keys, so DAS applies.
+        assert_eq!(gestures.start(5, 10.0, 590.0, 0.0), vec![Input::Press(Action::MoveLeft)]);
+        assert_eq!(gestures.moved(5, 100.0, 590.0, 300.0), vec![]);
+        assert_eq!(gestures.end(5, 100.0, 590.0, 400.0), vec![Input::Release(Action::MoveLeft)]);
+    }
+}